@@ -0,0 +1,168 @@
+use std::io::{self, Write};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::style::{Attribute, Color as CColor, Print, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::{execute, queue};
+
+use tui::backend::Backend;
+use tui::buffer::Cell;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier};
+
+/// Draws tui buffers with our own crossterm.
+/// tui's crossterm backend pins an older crossterm than the one we read focus and paste events with,
+/// and two copies of crossterm don't share terminal state, so tui is built without it.
+pub struct CrosstermBackend<W: Write> {
+    buffer: W,
+}
+
+impl<W: Write> CrosstermBackend<W> {
+    pub fn new(buffer: W) -> CrosstermBackend<W> {
+        CrosstermBackend { buffer }
+    }
+}
+
+impl<W: Write> Backend for CrosstermBackend<W> {
+    fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        let mut fg = Color::Reset;
+        let mut bg = Color::Reset;
+        let mut modifier = Modifier::empty();
+        let mut last_pos: Option<(u16, u16)> = None;
+        for (x, y, cell) in content {
+            //Only move if this cell doesn't follow the last one printed
+            if !matches!(last_pos, Some(p) if x == p.0 + 1 && y == p.1) {
+                queue!(self.buffer, MoveTo(x, y))?;
+            }
+            last_pos = Some((x, y));
+            if cell.modifier != modifier {
+                queue_modifier_change(&mut self.buffer, modifier, cell.modifier)?;
+                modifier = cell.modifier;
+            }
+            if cell.fg != fg {
+                queue!(self.buffer, SetForegroundColor(to_crossterm_color(cell.fg)))?;
+                fg = cell.fg;
+            }
+            if cell.bg != bg {
+                queue!(self.buffer, SetBackgroundColor(to_crossterm_color(cell.bg)))?;
+                bg = cell.bg;
+            }
+            queue!(self.buffer, Print(&cell.symbol))?;
+        }
+
+        queue!(self.buffer, SetForegroundColor(CColor::Reset), SetBackgroundColor(CColor::Reset), SetAttribute(Attribute::Reset))
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        execute!(self.buffer, Hide)
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        execute!(self.buffer, Show)
+    }
+
+    fn get_cursor(&mut self) -> io::Result<(u16, u16)> {
+        crossterm::cursor::position()
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        execute!(self.buffer, MoveTo(x, y))
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        execute!(self.buffer, Clear(ClearType::All))
+    }
+
+    fn size(&self) -> io::Result<Rect> {
+        let (width, height) = terminal::size()?;
+        Ok(Rect::new(0, 0, width, height))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.buffer.flush()
+    }
+}
+
+fn to_crossterm_color(color: Color) -> CColor {
+    match color {
+        Color::Reset => CColor::Reset,
+        Color::Black => CColor::Black,
+        Color::Red => CColor::DarkRed,
+        Color::Green => CColor::DarkGreen,
+        Color::Yellow => CColor::DarkYellow,
+        Color::Blue => CColor::DarkBlue,
+        Color::Magenta => CColor::DarkMagenta,
+        Color::Cyan => CColor::DarkCyan,
+        Color::Gray => CColor::Grey,
+        Color::DarkGray => CColor::DarkGrey,
+        Color::LightRed => CColor::Red,
+        Color::LightGreen => CColor::Green,
+        Color::LightBlue => CColor::Blue,
+        Color::LightYellow => CColor::Yellow,
+        Color::LightMagenta => CColor::Magenta,
+        Color::LightCyan => CColor::Cyan,
+        Color::White => CColor::White,
+        Color::Indexed(i) => CColor::AnsiValue(i),
+        Color::Rgb(r, g, b) => CColor::Rgb { r, g, b },
+    }
+}
+
+/// Queues the attribute changes to go from the `from` modifiers to the `to` ones
+fn queue_modifier_change<W: Write>(w: &mut W, from: Modifier, to: Modifier) -> io::Result<()> {
+    let removed = from - to;
+    if removed.contains(Modifier::REVERSED) {
+        queue!(w, SetAttribute(Attribute::NoReverse))?;
+    }
+    if removed.contains(Modifier::BOLD) {
+        queue!(w, SetAttribute(Attribute::NormalIntensity))?;
+        //Normal intensity also turns dim off
+        if to.contains(Modifier::DIM) {
+            queue!(w, SetAttribute(Attribute::Dim))?;
+        }
+    }
+    if removed.contains(Modifier::ITALIC) {
+        queue!(w, SetAttribute(Attribute::NoItalic))?;
+    }
+    if removed.contains(Modifier::UNDERLINED) {
+        queue!(w, SetAttribute(Attribute::NoUnderline))?;
+    }
+    if removed.contains(Modifier::DIM) {
+        queue!(w, SetAttribute(Attribute::NormalIntensity))?;
+    }
+    if removed.contains(Modifier::CROSSED_OUT) {
+        queue!(w, SetAttribute(Attribute::NotCrossedOut))?;
+    }
+    if removed.contains(Modifier::SLOW_BLINK) || removed.contains(Modifier::RAPID_BLINK) {
+        queue!(w, SetAttribute(Attribute::NoBlink))?;
+    }
+
+    let added = to - from;
+    if added.contains(Modifier::REVERSED) {
+        queue!(w, SetAttribute(Attribute::Reverse))?;
+    }
+    if added.contains(Modifier::BOLD) {
+        queue!(w, SetAttribute(Attribute::Bold))?;
+    }
+    if added.contains(Modifier::ITALIC) {
+        queue!(w, SetAttribute(Attribute::Italic))?;
+    }
+    if added.contains(Modifier::UNDERLINED) {
+        queue!(w, SetAttribute(Attribute::Underlined))?;
+    }
+    if added.contains(Modifier::DIM) {
+        queue!(w, SetAttribute(Attribute::Dim))?;
+    }
+    if added.contains(Modifier::CROSSED_OUT) {
+        queue!(w, SetAttribute(Attribute::CrossedOut))?;
+    }
+    if added.contains(Modifier::SLOW_BLINK) {
+        queue!(w, SetAttribute(Attribute::SlowBlink))?;
+    }
+    if added.contains(Modifier::RAPID_BLINK) {
+        queue!(w, SetAttribute(Attribute::RapidBlink))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::io;
+
+use crate::editor::Editor;
+use crate::session::{self, Session};
+
+/// Runs a command typed into the command runner (ctrl + r)
+pub fn run(input: &str, editor: &mut Editor) -> Result<(), io::Error> {
+    //Shell commands are taken as they are, whitespace and all
+    let trimmed = input.trim_start();
+    if let Some(command) = trimmed.strip_prefix("r !").or_else(|| trimmed.strip_prefix("r!")) {
+        return editor.insert_output(command);
+    }
+    if let Some(command) = trimmed.strip_prefix('!') {
+        return editor.filter_through(command);
+    }
+    let mut args = input.split_whitespace();
+    match (args.next(), args.next(), args.next()) {
+        (Some("session"), Some("save"), Some(name)) => {
+            Session::from_editor(editor).save(&session::named_session_path(name)?)
+        },
+        (Some("session"), Some("load"), Some(name)) => {
+            let loaded = Session::load(&session::named_session_path(name)?)?.open()?;
+            editor.replace_files(loaded)
+        },
+        (Some("sort"), ..) => {
+            let mut ignore_case = false;
+            let mut numeric = false;
+            for arg in input.split_whitespace().skip(1) {
+                match arg {
+                    "-i" => ignore_case = true,
+                    "-n" => numeric = true,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown sort option: {}", arg))),
+                }
+            }
+            editor.transform_lines(|lines| sort_lines(lines, ignore_case, numeric));
+            Ok(())
+        },
+        (Some("uniq"), None, _) => {
+            editor.transform_lines(|lines| {
+                let mut seen = std::collections::HashSet::new();
+                lines.into_iter().filter(|line| seen.insert(line.clone())).collect()
+            });
+            Ok(())
+        },
+        (Some("reverse"), None, _) => {
+            editor.transform_lines(|mut lines| {
+                lines.reverse();
+                lines
+            });
+            Ok(())
+        },
+        (Some("comment"), None, _) => editor.toggle_comment(),
+        (Some("hover"), None, _) => editor.hover(),
+        (Some("definition"), None, _) => editor.goto_definition(),
+        (Some("references"), None, _) => editor.find_references(),
+        (Some("rename"), Some(name), None) => editor.rename_symbol(name),
+        (Some("format"), None, _) => editor.format_buffer(),
+        (Some("blame"), None, _) => editor.blame_line(),
+        (Some("revert"), Some("hunk"), None) => editor.revert_hunk(),
+        (Some("diff"), against, None) => editor.show_diff(against),
+        (Some("changes"), None, _) => editor.show_unsaved_changes(None),
+        (Some("terminal"), None, _) => editor.toggle_terminal(),
+        (Some("goto"), Some(target), None) => editor.goto(target),
+        (Some("readonly"), None, _) => {
+            editor.toggle_read_only();
+            Ok(())
+        },
+        (Some("sudo"), Some("save"), None) => {
+            editor.request_sudo_save();
+            Ok(())
+        },
+        (None, ..) => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown command: {}", input.trim()))),
+    }
+}
+
+/// Sorts lines for the `sort` command. `numeric` compares the number at the start of each line,
+/// lines without one go first in their original order.
+fn sort_lines(mut lines: Vec<String>, ignore_case: bool, numeric: bool) -> Vec<String> {
+    if numeric {
+        let key = |line: &String| leading_number(line).unwrap_or(f64::NEG_INFINITY);
+        lines.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(std::cmp::Ordering::Equal));
+    } else if ignore_case {
+        lines.sort_by_cached_key(|line| line.to_lowercase());
+    } else {
+        lines.sort();
+    }
+    lines
+}
+
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let end = line.char_indices()
+        .take_while(|&(i, c)| c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+')))
+        .count();
+    line[..end].parse().ok()
+}
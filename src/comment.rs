@@ -0,0 +1,109 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::indent;
+
+/// Comment tokens as returned by `style::comment_tokens`
+type Tokens = (&'static str, Option<&'static str>);
+
+/// Whether a line, ignoring its indentation, is commented out with `tokens`
+fn is_commented(line: &str, (start, end): Tokens) -> bool {
+    let line = line.trim();
+    match end {
+        Some(end) => line.len() >= start.len() + end.len() && line.starts_with(start) && line.ends_with(end),
+        None => line.starts_with(start),
+    }
+}
+
+/// Longest start `a` and `b` have in common
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a.char_indices().zip(b.chars()).find(|((_, x), y)| x != y).map_or(a.len().min(b.len()), |((i, _), _)| i);
+    &a[..len]
+}
+
+/// Comments out `lines`, or uncomments them if every non-blank line is commented already. Blank lines are left alone.
+/// Comment tokens go after the indentation all lines share, so they line up even with tabs and spaces mixed.
+/// Every line comes back with the grapheme its text changed at and by how many graphemes, for moving the cursor along.
+pub fn toggle(lines: &[String], tokens: Tokens) -> Vec<(String, u16, i32)> {
+    let (start, end) = tokens;
+    let code = || lines.iter().filter(|line| !line.trim().is_empty());
+    let uncomment = code().all(|line| is_commented(line, tokens));
+    let column = code().map(|line| indent::leading_whitespace(line)).reduce(common_prefix).map_or(0, str::len);
+
+    lines.iter().map(|line| {
+        if line.trim().is_empty() {
+            return (line.clone(), 0, 0);
+        }
+        if uncomment {
+            let indent = indent::leading_whitespace(line);
+            let commented = &line[indent.len()..];
+            let mut text = commented.strip_prefix(start).unwrap_or(commented);
+            text = text.strip_prefix(' ').unwrap_or(text);
+            let removed = commented[..commented.len() - text.len()].graphemes(true).count();
+            if let Some(end) = end {
+                text = text.trim_end();
+                text = text.strip_suffix(end).unwrap_or(text);
+                text = text.strip_suffix(' ').unwrap_or(text);
+            }
+            (format!("{}{}", indent, text), indent.graphemes(true).count() as u16, -(removed as i32))
+        } else {
+            let (indent, text) = line.split_at(column);
+            let text = match end {
+                Some(end) => format!("{} {} {}", start, text, end),
+                None => format!("{} {}", start, text),
+            };
+            (format!("{}{}", indent, text), indent.graphemes(true).count() as u16, start.graphemes(true).count() as i32 + 1)
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: Tokens = ("//", None);
+    const BLOCK: Tokens = ("/*", Some("*/"));
+
+    fn toggled(lines: &[&str], tokens: Tokens) -> Vec<(String, u16, i32)> {
+        toggle(&lines.iter().map(|line| line.to_string()).collect::<Vec<_>>(), tokens)
+    }
+
+    #[test]
+    fn comments_at_shared_indentation() {
+        assert_eq!(toggled(&["    a", "      b", "", "    c"], LINE), vec![
+            ("    // a".to_string(), 4, 3),
+            ("    //   b".to_string(), 4, 3),
+            ("".to_string(), 0, 0),
+            ("    // c".to_string(), 4, 3),
+        ]);
+    }
+
+    #[test]
+    fn mixed_tabs_and_spaces() {
+        //Neither indentation is a prefix of the other, so the tokens go at the start of both
+        assert_eq!(toggled(&["\tfoo", "    bar"], LINE), vec![
+            ("// \tfoo".to_string(), 0, 3),
+            ("//     bar".to_string(), 0, 3),
+        ]);
+        assert_eq!(toggled(&["\t  foo", "\tbar"], LINE), vec![
+            ("\t//   foo".to_string(), 1, 3),
+            ("\t// bar".to_string(), 1, 3),
+        ]);
+    }
+
+    #[test]
+    fn uncomments_when_all_commented() {
+        assert_eq!(toggled(&["\t// foo", "  //bar"], LINE), vec![
+            ("\tfoo".to_string(), 1, -3),
+            ("  bar".to_string(), 2, -2),
+        ]);
+        //One line without a comment comments all of them
+        assert_eq!(toggled(&["// foo", "bar"], LINE)[0].0, "// // foo");
+    }
+
+    #[test]
+    fn block_comments() {
+        let commented = toggled(&["  é = 1;"], BLOCK);
+        assert_eq!(commented, vec![("  /* é = 1; */".to_string(), 2, 3)]);
+        assert_eq!(toggled(&[&commented[0].0], BLOCK), vec![("  é = 1;".to_string(), 2, -3)]);
+    }
+}
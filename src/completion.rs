@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::word::{self, Class};
+
+/// Words shorter than this aren't worth offering
+const MIN_WORD_LEN: usize = 3;
+/// How many different buffer words are collected at most, so huge files don't stall typing
+const MAX_WORDS: usize = 5000;
+/// Characters of a word to type before completions show up on their own
+pub const MIN_PREFIX: usize = 2;
+/// Rows of the dropdown
+pub const MAX_VISIBLE: usize = 8;
+
+/// What to insert for a completion
+#[derive(Clone)]
+pub enum Insert {
+    Text(String),
+    /// LSP snippet syntax, with `$1` and `${1:default}` placeholders
+    Snippet(String),
+}
+
+#[derive(Clone)]
+pub struct Item {
+    pub label: String,
+    /// One character hinting at what the item is, like `f` for functions
+    pub icon: char,
+    /// Type or signature, shown next to the label
+    pub detail: String,
+    pub insert: Insert,
+}
+
+impl Item {
+    pub fn word(word: &str) -> Self {
+        Self { label: word.to_string(), icon: 'w', detail: String::new(), insert: Insert::Text(word.to_string()) }
+    }
+}
+
+/// Icon for an LSP `CompletionItemKind`
+pub fn kind_icon(kind: u64) -> char {
+    match kind {
+        2..=4 => 'f',
+        5 | 10 => '.',
+        6 | 12 => 'v',
+        7 | 8 | 13 | 22 | 25 => 't',
+        9 => 'm',
+        14 => 'k',
+        15 => 's',
+        20 | 21 => 'c',
+        _ => '*',
+    }
+}
+
+/// An open completion dropdown. `start` is where the word being completed starts,
+/// everything from there to the cursor is the prefix the items are filtered by.
+pub struct Completion {
+    pub start: (u16, u16),
+    items: Vec<Item>,
+    /// Indices into `items` matching the prefix, best first
+    filtered: Vec<usize>,
+    pub selected: usize,
+}
+
+impl Completion {
+    pub fn new(start: (u16, u16), items: Vec<Item>, prefix: &str) -> Self {
+        let mut completion = Self { start, items: Vec::new(), filtered: Vec::new(), selected: 0 };
+        completion.add_items(items, prefix);
+        completion
+    }
+
+    /// Adds items that arrived later, like a language server's, in front of the ones already there
+    pub fn add_items(&mut self, mut items: Vec<Item>, prefix: &str) {
+        let labels: HashSet<String> = items.iter().map(|item| item.label.clone()).collect();
+        //A language server knows more about an item than the buffer word with the same name
+        items.extend(self.items.drain(..).filter(|item| !labels.contains(&item.label)));
+        self.items = items;
+        self.filter(prefix);
+    }
+
+    /// Keeps the items matching `prefix`: the ones starting with it first, then those containing its characters in order
+    pub fn filter(&mut self, prefix: &str) {
+        let prefix = prefix.to_lowercase();
+        let mut starts = Vec::new();
+        let mut fuzzy = Vec::new();
+        for (i, item) in self.items.iter().enumerate() {
+            let label = item.label.to_lowercase();
+            if label == prefix {
+                continue;
+            }
+            if label.starts_with(&prefix) {
+                starts.push(i);
+            } else if is_subsequence(&prefix, &label) {
+                fuzzy.push(i);
+            }
+        }
+        starts.extend(fuzzy);
+        self.filtered = starts;
+        self.selected = self.selected.min(self.filtered.len().saturating_sub(1));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filtered.is_empty()
+    }
+
+    pub fn select(&mut self, offset: isize) {
+        let len = self.filtered.len() as isize;
+        if len > 0 {
+            self.selected = (self.selected as isize + offset).rem_euclid(len) as usize;
+        }
+    }
+
+    pub fn selected_item(&self) -> Option<&Item> {
+        self.filtered.get(self.selected).map(|&i| &self.items[i])
+    }
+
+    /// The items to draw and the index of the selected one among them, scrolled to keep it visible
+    pub fn visible(&self) -> (Vec<&Item>, usize) {
+        let first = self.selected.saturating_sub(MAX_VISIBLE - 1);
+        let items = self.filtered.iter().skip(first).take(MAX_VISIBLE).map(|&i| &self.items[i]).collect();
+        (items, self.selected - first)
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+/// Distinct words of at least a few characters from `texts`, for completing without a language server
+pub fn buffer_words<'a>(texts: impl Iterator<Item = &'a Vec<String>>, skip: &str) -> Vec<Item> {
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    'files: for lines in texts {
+        for line in lines {
+            let graphemes: Vec<&str> = line.graphemes(true).collect();
+            for (start, end, class) in word::segments(line) {
+                if class != Class::Word || end - start < MIN_WORD_LEN {
+                    continue;
+                }
+                let word: String = graphemes[start..end].concat();
+                //Numbers aren't words anyone wants to complete
+                if word.starts_with(|c: char| c.is_ascii_digit()) || word == skip {
+                    continue;
+                }
+                if seen.insert(word.clone()) {
+                    items.push(Item::word(&word));
+                    if items.len() >= MAX_WORDS {
+                        break 'files;
+                    }
+                }
+            }
+        }
+    }
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+/// Expands an LSP snippet into the text to insert and its tab stops as grapheme ranges (start, end) into that text.
+/// Stops are ordered by number, with the final `$0` last. Choices like `${1|a,b|}` insert their first option.
+pub fn expand_snippet(snippet: &str) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::new();
+    //(number, start, end) in bytes of `text`
+    let mut stops: Vec<(u32, usize, usize)> = Vec::new();
+    //Placeholders we are inside of, as (number, start)
+    let mut open: Vec<(u32, usize)> = Vec::new();
+    let chars: Vec<char> = snippet.chars().collect();
+    let number_at = |i: usize| -> (Option<u32>, usize) {
+        let digits: String = chars[i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+        (digits.parse().ok(), i + digits.len())
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                text.push(chars[i + 1]);
+                i += 2;
+            },
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                let (number, after) = number_at(i + 2);
+                match (number, chars.get(after)) {
+                    (Some(number), Some(':')) => {
+                        open.push((number, text.len()));
+                        i = after + 1;
+                    },
+                    (Some(number), Some('|')) => {
+                        let options: String = chars[after + 1..].iter().take_while(|&&c| c != '|').collect();
+                        let start = text.len();
+                        text.push_str(options.split(',').next().unwrap_or(""));
+                        stops.push((number, start, text.len()));
+                        //Skip the options and the closing `|}`
+                        i = after + 1 + options.chars().count() + 2;
+                    },
+                    (Some(number), _) => {
+                        stops.push((number, text.len(), text.len()));
+                        i = after + 1;
+                    },
+                    (None, _) => {
+                        text.push('$');
+                        i += 1;
+                    },
+                }
+            },
+            '$' => match number_at(i + 1) {
+                (Some(number), after) => {
+                    stops.push((number, text.len(), text.len()));
+                    i = after;
+                },
+                (None, _) => {
+                    text.push('$');
+                    i += 1;
+                },
+            },
+            '}' if !open.is_empty() => {
+                if let Some((number, start)) = open.pop() {
+                    stops.push((number, start, text.len()));
+                }
+                i += 1;
+            },
+            c => {
+                text.push(c);
+                i += 1;
+            },
+        }
+    }
+
+    //The same number can show up more than once, the first one is where the cursor goes
+    stops.sort_by_key(|&(number, start, _)| (number == 0, number, start));
+    stops.dedup_by_key(|&mut (number, _, _)| number);
+    let graphemes = |byte: usize| text[..byte].graphemes(true).count();
+    let stops = stops.iter().map(|&(_, start, end)| (graphemes(start), graphemes(end))).collect();
+    (text, stops)
+}
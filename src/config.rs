@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::load();
+}
+
+/// User settings, read once at startup from `$XDG_CONFIG_HOME/rocket/config`
+/// (or `~/.config/rocket/config`). The file is a list of `key = value` lines,
+/// `#` starts a comment and unknown keys are ignored.
+pub struct Config {
+    /// Keep a `file~` copy of the previous version when saving
+    pub backup: bool,
+    /// Write swap files for unsaved buffers, so they can be recovered after a crash
+    pub swap: bool,
+    /// Seconds between swap file writes
+    pub swap_interval: u64,
+    /// Save files automatically, see the `autosave_*` settings for when
+    pub autosave: bool,
+    /// Seconds without input before saving, 0 disables saving on idle
+    pub autosave_idle: u64,
+    /// Save when the terminal loses focus
+    pub autosave_on_focus_lost: bool,
+    /// Save the current file when switching to another one
+    pub autosave_on_tab_switch: bool,
+    /// Vim style modal editing with normal, insert and visual mode
+    pub modal: bool,
+    /// Language server command per file extension, set with `lsp.<extension> = command args`.
+    /// An empty command disables the server for that extension.
+    pub lsp_servers: HashMap<String, String>,
+    /// Formatter command per file extension, set with `format.<extension> = command args`.
+    /// The buffer is piped through it, `{path}` is replaced with the file's path and `lsp` asks the language server instead.
+    pub formatters: HashMap<String, String>,
+    /// Format files with their formatter when saving them by hand
+    pub format_on_save: bool,
+    /// Runs a command as root, for saving files we aren't allowed to write, like `sudo` or `doas`
+    pub sudo_command: String,
+    /// Files bigger than this many megabytes are opened as large files: read-only, read from disk as needed
+    pub large_file_threshold: u64,
+    /// Key switching to the most recently used file, like ctrl+tab or alt+`.
+    /// Many terminals send a plain tab for ctrl + tab, those need another key here.
+    pub recent_file_key: (KeyModifiers, KeyCode),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backup: false,
+            swap: true,
+            swap_interval: 4,
+            autosave: false,
+            autosave_idle: 5,
+            autosave_on_focus_lost: true,
+            autosave_on_tab_switch: true,
+            modal: false,
+            lsp_servers: [("rs", "rust-analyzer"), ("c", "clangd"), ("h", "clangd"), ("cc", "clangd"), ("cpp", "clangd"), ("hpp", "clangd")]
+                .iter()
+                .map(|&(extension, command)| (extension.to_string(), command.to_string()))
+                .collect(),
+            formatters: [
+                ("rs", "rustfmt --emit stdout"),
+                ("c", "clang-format --assume-filename={path}"),
+                ("h", "clang-format --assume-filename={path}"),
+                ("cc", "clang-format --assume-filename={path}"),
+                ("cpp", "clang-format --assume-filename={path}"),
+                ("hpp", "clang-format --assume-filename={path}"),
+                ("js", "prettier --stdin-filepath {path}"),
+                ("ts", "prettier --stdin-filepath {path}"),
+                ("css", "prettier --stdin-filepath {path}"),
+                ("html", "prettier --stdin-filepath {path}"),
+                ("json", "prettier --stdin-filepath {path}"),
+                ("md", "prettier --stdin-filepath {path}"),
+            ]
+                .iter()
+                .map(|&(extension, command)| (extension.to_string(), command.to_string()))
+                .collect(),
+            format_on_save: false,
+            sudo_command: "sudo".to_string(),
+            large_file_threshold: 100,
+            recent_file_key: (KeyModifiers::CONTROL, KeyCode::Tab),
+        }
+    }
+}
+
+impl Config {
+    /// Command line of the language server for files with `extension`, if one is configured
+    pub fn lsp_server(&self, extension: &str) -> Option<&str> {
+        self.lsp_servers.get(extension).map(String::as_str).filter(|command| !command.trim().is_empty())
+    }
+
+    /// Formatter command for files with `extension`, if one is configured
+    pub fn formatter(&self, extension: &str) -> Option<&str> {
+        self.formatters.get(extension).map(String::as_str).filter(|command| !command.trim().is_empty())
+    }
+
+    /// Whether `key` is the one set to switch to the most recently used file
+    pub fn is_recent_file_key(&self, key: &KeyEvent) -> bool {
+        (key.modifiers, key.code) == self.recent_file_key
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+            return Some(PathBuf::from(dir).join("rocket").join("config"));
+        }
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("rocket").join("config"))
+    }
+
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        if let Some(content) = Self::path().and_then(|p| std::fs::read_to_string(p).ok()) {
+            config.parse(&content);
+        }
+        config
+    }
+
+    fn parse(&mut self, content: &str) {
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if let Some((key, value)) = line.split_once('=') {
+                self.set(key.trim(), value.trim());
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "backup" => self.backup = parse_bool(value, self.backup),
+            "swap" => self.swap = parse_bool(value, self.swap),
+            "swap_interval" => self.swap_interval = value.parse().unwrap_or(self.swap_interval),
+            "autosave" => self.autosave = parse_bool(value, self.autosave),
+            "autosave_idle" => self.autosave_idle = value.parse().unwrap_or(self.autosave_idle),
+            "autosave_on_focus_lost" => self.autosave_on_focus_lost = parse_bool(value, self.autosave_on_focus_lost),
+            "autosave_on_tab_switch" => self.autosave_on_tab_switch = parse_bool(value, self.autosave_on_tab_switch),
+            "modal" => self.modal = parse_bool(value, self.modal),
+            "format_on_save" => self.format_on_save = parse_bool(value, self.format_on_save),
+            "sudo_command" => self.sudo_command = value.to_string(),
+            "large_file_threshold" => self.large_file_threshold = value.parse().unwrap_or(self.large_file_threshold),
+            "recent_file_key" => self.recent_file_key = parse_key(value).unwrap_or(self.recent_file_key),
+            _ => {
+                if let Some(extension) = key.strip_prefix("lsp.") {
+                    self.lsp_servers.insert(extension.to_string(), value.to_string());
+                } else if let Some(extension) = key.strip_prefix("format.") {
+                    self.formatters.insert(extension.to_string(), value.to_string());
+                }
+            },
+        }
+    }
+}
+
+fn parse_bool(value: &str, default: bool) -> bool {
+    match value {
+        "true" | "yes" | "on" | "1" => true,
+        "false" | "no" | "off" | "0" => false,
+        _ => default,
+    }
+}
+
+/// Parses a key like ctrl+tab, alt+` or ctrl+shift+f5: modifiers and then a key, joined by `+`
+fn parse_key(value: &str) -> Option<(KeyModifiers, KeyCode)> {
+    //The key itself may be a `+`
+    let (modifiers, key) = match value.strip_suffix('+') {
+        Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest.trim_end_matches('+'), "+"),
+        _ => value.rsplit_once('+').unwrap_or(("", value)),
+    };
+    let mut parsed = KeyModifiers::NONE;
+    for modifier in modifiers.split('+').map(str::trim).filter(|modifier| !modifier.is_empty()) {
+        parsed |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+    let key = key.trim();
+    let code = match key.to_lowercase().as_str() {
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "space" => KeyCode::Char(' '),
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        lower => match (lower.strip_prefix('f').and_then(|n| n.parse().ok()), key.chars().count()) {
+            (Some(n), _) if (1..=12).contains(&n) => KeyCode::F(n),
+            (_, 1) => KeyCode::Char(key.chars().next()?),
+            _ => return None,
+        },
+    };
+    Some((parsed, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key("ctrl+tab"), Some((KeyModifiers::CONTROL, KeyCode::Tab)));
+        assert_eq!(parse_key("alt+`"), Some((KeyModifiers::ALT, KeyCode::Char('`'))));
+        assert_eq!(parse_key("Ctrl + Shift + F5"), Some((KeyModifiers::CONTROL | KeyModifiers::SHIFT, KeyCode::F(5))));
+        assert_eq!(parse_key("alt++"), Some((KeyModifiers::ALT, KeyCode::Char('+'))));
+        assert_eq!(parse_key("+"), Some((KeyModifiers::NONE, KeyCode::Char('+'))));
+        assert_eq!(parse_key("hyper+tab"), None);
+        assert_eq!(parse_key("ctrl+nope"), None);
+    }
+
+    #[test]
+    fn recent_file_key_setting() {
+        let mut config = Config::default();
+        assert_eq!(config.recent_file_key, (KeyModifiers::CONTROL, KeyCode::Tab));
+        config.parse("recent_file_key = alt+`  # ctrl+tab doesn't reach us");
+        assert!(config.is_recent_file_key(&KeyEvent::new(KeyCode::Char('`'), KeyModifiers::ALT)));
+        //Bad values keep the key there was
+        config.parse("recent_file_key = ctrl+");
+        assert_eq!(config.recent_file_key, (KeyModifiers::ALT, KeyCode::Char('`')));
+    }
+}
@@ -0,0 +1,289 @@
+/// A single step in turning the old sequence into the new one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edit {
+    /// `old[old] == new[new]`
+    Equal { old: usize, new: usize },
+    /// `old[old]` was removed
+    Delete { old: usize },
+    /// `new[new]` was added
+    Insert { new: usize },
+}
+
+/// A group of changes with some surrounding context, like a hunk in a unified diff
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub edits: Vec<Edit>,
+}
+
+/// Computes the shortest edit script between `a` and `b` using Myers' algorithm in linear space.
+/// The common prefix and suffix are stripped first, which keeps the usual case of a
+/// few changes in a big file cheap.
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    diff_range(a, b, 0, 0, &mut edits);
+    edits
+}
+
+/// Appends the edits turning `a` into `b`, which start at `old` and `new` in the full sequences
+fn diff_range<T: PartialEq>(a: &[T], b: &[T], old: usize, new: usize, edits: &mut Vec<Edit>) {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    edits.extend((0..prefix).map(|i| Edit::Equal { old: old + i, new: new + i }));
+
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (old_mid, new_mid) = (old + prefix, new + prefix);
+    if a_mid.is_empty() {
+        edits.extend((0..b_mid.len()).map(|i| Edit::Insert { new: new_mid + i }));
+    } else if b_mid.is_empty() {
+        edits.extend((0..a_mid.len()).map(|i| Edit::Delete { old: old_mid + i }));
+    } else {
+        //Split on the middle of the shortest path so only two rows of it are ever stored
+        let (x, y) = middle_snake(a_mid, b_mid);
+        diff_range(&a_mid[..x], &b_mid[..y], old_mid, new_mid, edits);
+        diff_range(&a_mid[x..], &b_mid[y..], old_mid + x, new_mid + y, edits);
+    }
+
+    edits.extend((0..suffix).map(|i| Edit::Equal { old: old + a.len() - suffix + i, new: new + b.len() - suffix + i }));
+}
+
+/// Searches the shortest path from both ends at once until the two meet and returns a point
+/// on it roughly halfway. `a` and `b` must be non-empty and differ in their first and last items.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> (usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    //Furthest x reached on each diagonal, from the start and from the end
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && forward[idx - 1] < forward[idx + 1]) { forward[idx + 1] } else { forward[idx - 1] + 1 };
+            let mut y = x - k;
+            let start = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[idx] = x;
+            //Diagonal k from the start is diagonal delta - k from the end
+            let reverse_k = delta - k;
+            if odd && reverse_k.abs() < d && x + backward[(reverse_k + offset) as usize] >= n {
+                return (start.0 as usize, start.1 as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && backward[idx - 1] < backward[idx + 1]) { backward[idx + 1] } else { backward[idx - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[idx] = x;
+            let forward_k = delta - k;
+            if !odd && forward_k.abs() <= d && x + forward[(forward_k + offset) as usize] >= n {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+    unreachable!("The searches from both ends always meet")
+}
+
+/// Where item `old` of the old sequence ends up in the new one.
+/// Changed items map to the items replacing them in order, removed ones to where they used to be.
+pub fn map_index(edits: &[Edit], old: usize) -> usize {
+    let mut shift = 0isize;
+    for hunk in hunks(edits, 0) {
+        if old < hunk.old_start {
+            break;
+        }
+        if old < hunk.old_start + hunk.old_len {
+            return hunk.new_start + (old - hunk.old_start).min(hunk.new_len.saturating_sub(1));
+        }
+        shift += hunk.new_len as isize - hunk.old_len as isize;
+    }
+    (old as isize + shift) as usize
+}
+
+/// Groups an edit script into hunks, keeping up to `context` unchanged lines around each change
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    //Position in the old and new sequence before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in edits {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Equal { .. } => { old_pos += 1; new_pos += 1; },
+            Edit::Delete { .. } => old_pos += 1,
+            Edit::Insert { .. } => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let changes: Vec<usize> = edits.iter().enumerate().filter(|(_, e)| !matches!(e, Edit::Equal { .. })).map(|(i, _)| i).collect();
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while i < changes.len() {
+        let start = changes[i].saturating_sub(context);
+        let mut end = changes[i];
+        //Merge changes whose context would overlap
+        while i + 1 < changes.len() && changes[i + 1] <= end + 2 * context + 1 {
+            i += 1;
+            end = changes[i];
+        }
+        let end = (end + context + 1).min(edits.len());
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        hunks.push(Hunk {
+            old_start,
+            old_len: old_end - old_start,
+            new_start,
+            new_len: new_end - new_start,
+            edits: edits[start..end].to_vec(),
+        });
+        i += 1;
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rebuilds `b` from `a` and the script, checking every index is in order and points at the right item
+    fn apply(a: &[char], b: &[char], edits: &[Edit]) -> Vec<char> {
+        let (mut old_pos, mut new_pos) = (0, 0);
+        let mut out = Vec::new();
+        for edit in edits {
+            match *edit {
+                Edit::Equal { old, new } => {
+                    assert_eq!((old, new), (old_pos, new_pos));
+                    assert_eq!(a[old], b[new]);
+                    out.push(a[old]);
+                    old_pos += 1;
+                    new_pos += 1;
+                },
+                Edit::Delete { old } => {
+                    assert_eq!(old, old_pos);
+                    old_pos += 1;
+                },
+                Edit::Insert { new } => {
+                    assert_eq!(new, new_pos);
+                    out.push(b[new]);
+                    new_pos += 1;
+                },
+            }
+        }
+        assert_eq!((old_pos, new_pos), (a.len(), b.len()));
+        out
+    }
+
+    fn changes(edits: &[Edit]) -> usize {
+        edits.iter().filter(|e| !matches!(e, Edit::Equal { .. })).count()
+    }
+
+    fn lcs(a: &[char], b: &[char]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diagonal = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y { diagonal + 1 } else { above.max(row[j]) };
+                diagonal = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn identical() {
+        let a = chars("abcdef");
+        let edits = diff(&a, &a);
+        assert_eq!(edits.len(), 6);
+        assert_eq!(changes(&edits), 0);
+        assert!(hunks(&edits, 3).is_empty());
+    }
+
+    #[test]
+    fn empty_sides() {
+        let a = chars("abc");
+        assert!(diff::<char>(&[], &[]).is_empty());
+        assert_eq!(diff(&[], &a), vec![Edit::Insert { new: 0 }, Edit::Insert { new: 1 }, Edit::Insert { new: 2 }]);
+        assert_eq!(diff(&a, &[]), vec![Edit::Delete { old: 0 }, Edit::Delete { old: 1 }, Edit::Delete { old: 2 }]);
+        let hunk = &hunks(&diff(&a, &[]), 3)[0];
+        assert_eq!((hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len), (0, 3, 0, 0));
+    }
+
+    #[test]
+    fn pure_insert() {
+        let (a, b) = (chars("abef"), chars("abcdef"));
+        let edits = diff(&a, &b);
+        assert_eq!(apply(&a, &b, &edits), b);
+        assert_eq!(changes(&edits), 2);
+        let hunks = hunks(&edits, 0);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_len, hunks[0].new_start, hunks[0].new_len), (2, 0, 2, 2));
+    }
+
+    #[test]
+    fn pure_delete() {
+        let (a, b) = (chars("abcdef"), chars("af"));
+        let edits = diff(&a, &b);
+        assert_eq!(apply(&a, &b, &edits), b);
+        assert_eq!(changes(&edits), 4);
+        let hunks = hunks(&edits, 1);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_len, hunks[0].new_start, hunks[0].new_len), (0, 6, 0, 2));
+    }
+
+    #[test]
+    fn context_merging() {
+        //Changes 3 items apart share a hunk with 1 line of context, but not with none
+        let (a, b) = (chars("aXbcdYe"), chars("aZbcdWe"));
+        let edits = diff(&a, &b);
+        assert_eq!(hunks(&edits, 0).len(), 2);
+        let merged = hunks(&edits, 2);
+        assert_eq!(merged.len(), 1);
+        assert_eq!((merged[0].old_start, merged[0].old_len), (0, 7));
+        let apart = hunks(&edits, 1);
+        assert_eq!(apart.len(), 2);
+        assert_eq!((apart[0].old_start, apart[0].old_len, apart[1].old_start, apart[1].old_len), (0, 3, 4, 3));
+    }
+
+    #[test]
+    fn shortest_script() {
+        //Small alphabets give plenty of matches for the searches to go wrong on
+        let mut seed = 0x2545_f491u64;
+        let mut next = |limit: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % limit
+        };
+        for _ in 0..2000 {
+            let a: Vec<char> = (0..next(12)).map(|_| (b'a' + next(3) as u8) as char).collect();
+            let b: Vec<char> = (0..next(12)).map(|_| (b'a' + next(3) as u8) as char).collect();
+            let edits = diff(&a, &b);
+            assert_eq!(apply(&a, &b, &edits), b);
+            assert_eq!(changes(&edits), a.len() + b.len() - 2 * lcs(&a, &b), "{:?} -> {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn map_index_follows_changes() {
+        let (a, b) = (chars("abcd"), chars("xabyd"));
+        let edits = diff(&a, &b);
+        assert_eq!(map_index(&edits, 0), 1);
+        assert_eq!(map_index(&edits, 2), 3);
+        assert_eq!(map_index(&edits, 3), 4);
+    }
+}
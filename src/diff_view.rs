@@ -0,0 +1,281 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use tui::text::{Span, Spans, Text};
+use tui::style::Style;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::diff::{self, Edit};
+use crate::popup::PopupKind;
+use crate::style;
+
+/// Unchanged lines shown around each change
+const CONTEXT: usize = 3;
+/// Lines longer than this aren't compared character by character, that gets slow
+const MAX_INTRA_LEN: usize = 1000;
+/// Width of a line number column
+const NUM_WIDTH: usize = 5;
+
+/// One side of a line: its number, its text and which of its graphemes differ from the other side
+struct Side {
+    num: usize,
+    text: Vec<String>,
+    changed: Vec<bool>,
+}
+
+impl Side {
+    fn new(num: usize, line: &str) -> Self {
+        let text: Vec<String> = line.replace('\t', "    ").graphemes(true).map(str::to_string).collect();
+        Self { num, changed: Vec::new(), text }
+    }
+}
+
+enum Row {
+    /// Name of the file the following hunks belong to
+    File(String),
+    Hunk(String),
+    /// A line on either side or both, `changed` unless it is the same on both
+    Line { old: Option<Side>, new: Option<Side>, changed: bool },
+}
+
+/// Which part of a row a screen line shows
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Both,
+    Old,
+    New,
+}
+
+/// Full screen view of the differences between versions of one or more files,
+/// side by side or unified, with the changed parts of changed lines highlighted
+pub struct DiffView {
+    title: String,
+    rows: Vec<Row>,
+    side_by_side: bool,
+    /// Row and part of it on each screen line, for the current layout
+    lines: Vec<(usize, Part)>,
+    scroll: usize,
+    height: usize,
+    /// Popup to show again when the view is closed, like the question it was opened from
+    pub return_to: Option<PopupKind>,
+}
+
+impl DiffView {
+    /// Compares the `(name, old lines, new lines)` of each section
+    pub fn new(title: &str, sections: Vec<(String, Vec<String>, Vec<String>)>) -> Self {
+        let mut rows = Vec::new();
+        for (name, old, new) in sections {
+            let hunks = diff::hunks(&diff::diff(&old, &new), CONTEXT);
+            if hunks.is_empty() {
+                continue;
+            }
+            rows.push(Row::File(name));
+            for hunk in hunks {
+                rows.push(Row::Hunk(format!("@@ -{},{} +{},{} @@", hunk.old_start + 1, hunk.old_len, hunk.new_start + 1, hunk.new_len)));
+                let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+                for edit in hunk.edits {
+                    match edit {
+                        Edit::Equal { old: o, new: n } => {
+                            push_changes(&mut rows, &old, &new, &mut deleted, &mut inserted);
+                            rows.push(Row::Line { old: Some(Side::new(o, &old[o])), new: Some(Side::new(n, &new[n])), changed: false });
+                        },
+                        Edit::Delete { old } => deleted.push(old),
+                        Edit::Insert { new } => inserted.push(new),
+                    }
+                }
+                push_changes(&mut rows, &old, &new, &mut deleted, &mut inserted);
+            }
+        }
+        let mut view = Self { title: title.to_string(), rows, side_by_side: true, lines: Vec::new(), scroll: 0, height: 1, return_to: None };
+        view.layout();
+        view
+    }
+
+    /// True if there was nothing different to show
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn layout(&mut self) {
+        self.lines.clear();
+        for (i, row) in self.rows.iter().enumerate() {
+            match row {
+                Row::Line { old, new, changed: true } if !self.side_by_side => {
+                    if old.is_some() { self.lines.push((i, Part::Old)); }
+                    if new.is_some() { self.lines.push((i, Part::New)); }
+                },
+                _ => self.lines.push((i, Part::Both)),
+            }
+        }
+    }
+
+    /// Switches between side by side and unified, keeping the same row at the top
+    pub fn toggle_layout(&mut self) {
+        let top = self.lines.get(self.scroll).map(|&(row, _)| row).unwrap_or(0);
+        self.side_by_side = !self.side_by_side;
+        self.layout();
+        self.scroll = self.lines.iter().position(|&(row, _)| row == top).unwrap_or(0);
+    }
+
+    pub fn set_height(&mut self, height: u16) {
+        self.height = (height as usize).max(1);
+    }
+
+    fn scroll_by(&mut self, dy: isize) {
+        let max = self.lines.len().saturating_sub(self.height);
+        self.scroll = (self.scroll as isize + dy).clamp(0, max as isize) as usize;
+    }
+
+    fn is_hunk(&self, line: usize) -> bool {
+        matches!(self.rows[self.lines[line].0], Row::Hunk(_))
+    }
+
+    /// Scrolls the next (or with `forward` unset the previous) hunk to the top
+    pub fn jump_to_hunk(&mut self, forward: bool) {
+        let target = if forward {
+            (self.scroll + 1..self.lines.len()).find(|&line| self.is_hunk(line))
+        } else {
+            (0..self.scroll).rev().find(|&line| self.is_hunk(line))
+        };
+        if let Some(line) = target {
+            //The file name goes along when it is right above the first hunk of a file
+            self.scroll = if line > 0 && matches!(self.rows[self.lines[line - 1].0], Row::File(_)) { line - 1 } else { line };
+        }
+    }
+
+    /// Handles a key, returning true when the view should be closed
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let page = self.height as isize;
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_by(1),
+            KeyCode::PageUp => self.scroll_by(-page),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.scroll_by(page),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll = 0,
+            KeyCode::End | KeyCode::Char('G') => self.scroll_by(self.lines.len() as isize),
+            KeyCode::Char('n') | KeyCode::Char(']') => self.jump_to_hunk(true),
+            KeyCode::Char('p') | KeyCode::Char('[') => self.jump_to_hunk(false),
+            KeyCode::Tab | KeyCode::Char('s') => self.toggle_layout(),
+            _ => {},
+        }
+        false
+    }
+
+    /// What is compared, which hunk is at the top and how to get around, for the footer
+    pub fn status(&self) -> String {
+        let total = self.rows.iter().filter(|row| matches!(row, Row::Hunk(_))).count();
+        let current = (0..=self.scroll.min(self.lines.len().saturating_sub(1))).filter(|&line| self.is_hunk(line)).count();
+        let layout = if self.side_by_side { "side by side" } else { "unified" };
+        format!("{} \\\\ hunk {}/{} \\\\ {} \\\\ n/p hunks, s layout, q close", self.title, current.max(1), total, layout)
+    }
+
+    /// The lines on screen, `width` columns wide
+    pub fn render(&self, width: u16) -> Text<'static> {
+        let width = width as usize;
+        let mut text = Vec::new();
+        for &(row, part) in self.lines.iter().skip(self.scroll).take(self.height) {
+            let spans = match &self.rows[row] {
+                Row::File(name) => vec![Span::styled(pad(name.clone(), width), style::header_style(true))],
+                Row::Hunk(header) => vec![Span::styled(pad(header.clone(), width), style::diff_hunk_style())],
+                Row::Line { old, new, changed } if self.side_by_side => {
+                    let half = width.saturating_sub(1) / 2;
+                    let mut spans = side_spans(old.as_ref(), *changed, style::diff_removed_style(), half);
+                    spans.push(Span::styled("│", style::diff_hunk_style()));
+                    spans.extend(side_spans(new.as_ref(), *changed, style::diff_added_style(), width.saturating_sub(half + 1)));
+                    spans
+                },
+                Row::Line { old, new, .. } => {
+                    //Unified: both line numbers, then the line itself
+                    let (old_num, new_num, side, marker, base) = match part {
+                        Part::Old => (old.as_ref().map(|side| side.num), None, old.as_ref(), "- ", style::diff_removed_style()),
+                        Part::New => (None, new.as_ref().map(|side| side.num), new.as_ref(), "+ ", style::diff_added_style()),
+                        Part::Both => (old.as_ref().map(|side| side.num), new.as_ref().map(|side| side.num), new.as_ref(), "  ", Style::default()),
+                    };
+                    let num = |num: Option<usize>| num.map(|num| format!("{:>w$} ", num + 1, w = NUM_WIDTH - 1)).unwrap_or_else(|| " ".repeat(NUM_WIDTH));
+                    let mut spans = vec![Span::styled(format!("{}{}", num(old_num), num(new_num)), style::diff_number_style()), Span::styled(marker, base)];
+                    if let Some(side) = side {
+                        spans.extend(text_spans(side, base, width.saturating_sub(2 * NUM_WIDTH + 2)));
+                    }
+                    spans
+                },
+            };
+            text.push(Spans::from(spans));
+        }
+        Text::from(text)
+    }
+}
+
+/// Adds the lines removed and added since the last unchanged line, pairing them up so their differences can be highlighted
+fn push_changes(rows: &mut Vec<Row>, old: &[String], new: &[String], deleted: &mut Vec<usize>, inserted: &mut Vec<usize>) {
+    for i in 0..deleted.len().max(inserted.len()) {
+        let mut old_side = deleted.get(i).map(|&o| Side::new(o, &old[o]));
+        let mut new_side = inserted.get(i).map(|&n| Side::new(n, &new[n]));
+        if let (Some(old_side), Some(new_side)) = (&mut old_side, &mut new_side) {
+            mark_changes(old_side, new_side);
+        }
+        rows.push(Row::Line { old: old_side, new: new_side, changed: true });
+    }
+    deleted.clear();
+    inserted.clear();
+}
+
+/// Marks the graphemes that differ between two versions of a line.
+/// Lines with nothing in common get no marks, the whole line being highlighted already says enough.
+fn mark_changes(old: &mut Side, new: &mut Side) {
+    if old.text.len() > MAX_INTRA_LEN || new.text.len() > MAX_INTRA_LEN {
+        return;
+    }
+    let edits = diff::diff(&old.text, &new.text);
+    if !edits.iter().any(|edit| matches!(edit, Edit::Equal { .. })) {
+        return;
+    }
+    old.changed = vec![false; old.text.len()];
+    new.changed = vec![false; new.text.len()];
+    for edit in edits {
+        match edit {
+            Edit::Delete { old: o } => old.changed[o] = true,
+            Edit::Insert { new: n } => new.changed[n] = true,
+            Edit::Equal { .. } => {},
+        }
+    }
+}
+
+fn pad(mut text: String, width: usize) -> String {
+    let len = text.graphemes(true).count();
+    if len < width {
+        text.push_str(&" ".repeat(width - len));
+    }
+    text
+}
+
+/// One half of a side by side line: the line number and as much of the text as fits in `width`
+fn side_spans(side: Option<&Side>, changed: bool, base: Style, width: usize) -> Vec<Span<'static>> {
+    let Some(side) = side else {
+        return vec![Span::raw(" ".repeat(width))];
+    };
+    let base = if changed { base } else { Style::default() };
+    let mut spans = vec![Span::styled(format!("{:>w$} ", side.num + 1, w = NUM_WIDTH - 1), style::diff_number_style())];
+    let room = width.saturating_sub(NUM_WIDTH);
+    spans.extend(text_spans(side, base, room));
+    let used = side.text.len().min(room);
+    spans.push(Span::styled(" ".repeat(room - used), base));
+    spans
+}
+
+/// Text of a line cut to `width`, changed parts emphasized
+fn text_spans(side: &Side, base: Style, width: usize) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut run = String::new();
+    let mut run_changed = false;
+    for (i, grapheme) in side.text.iter().take(width).enumerate() {
+        let changed = side.changed.get(i).copied().unwrap_or(false);
+        if changed != run_changed && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), if run_changed { style::diff_emphasis_style(base) } else { base }));
+        }
+        run_changed = changed;
+        run.push_str(grapheme);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, if run_changed { style::diff_emphasis_style(base) } else { base }));
+    }
+    spans
+}
@@ -1,21 +1,138 @@
+use std::collections::{HashMap, HashSet};
 use std::iter::Iterator;
-use std::time::{Instant, Duration};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use unicode_segmentation::UnicodeSegmentation;
 
-use tui::text::Text;
+use tui::text::{Text, Spans};
 
-use crossterm::event::{KeyEvent, KeyCode};
+use crossterm::event::{KeyEvent, KeyCode, KeyModifiers};
 
-use crate::popup::Popup;
+use crate::config::CONFIG;
+use crate::fileio::{self, DiskStamp};
+use crate::modal::{Modal, Mode};
+use crate::word;
+use crate::indent;
+use crate::pairs;
+use crate::comment;
+use crate::lsp;
+use crate::format;
+use crate::shell;
+use crate::git;
+use crate::completion::{self, Completion};
+use crate::diff_view::DiffView;
+use crate::terminal::{self, TerminalPane};
+use crate::hex::{self, HexView};
+use crate::large_file::LargeFile;
+use crate::popup::PopupKind;
+use crate::style::LexState;
+use crate::util;
 
 pub struct File {
     path: Option<String>,
     content: Vec<String>,
     cursor: (u16, u16),
     scroll: (u16, u16),
+    /// Other end of the selection, the cursor being one end
+    selection_anchor: Option<(u16, u16)>,
+    /// Cursors besides the main one, for editing in several places at once
+    extra_carets: Vec<Caret>,
+    /// Tab stops of the last inserted snippet that tab hasn't gone to yet, as (graphemes from the stop to the end of the file, length).
+    /// Counting from the end keeps them in place while the text before them is edited.
+    snippet_stops: Vec<(usize, usize)>,
 
     is_dirty: bool,
     saved_time: Option<Instant>,
+    /// State of the file on disk when we last loaded or saved it
+    disk_stamp: Option<DiskStamp>,
+    /// Hash of the content last written to the swap file, so unchanged buffers aren't rewritten
+    swap_hash: Option<u64>,
+    /// The file changed on disk while we had unsaved changes, and the user hasn't decided what to do yet
+    disk_conflict: bool,
+    /// Edits are blocked, because we can't write the file, it was opened with `--readonly` or the user said so
+    read_only: bool,
+    /// An edit was refused because the file is read-only, for the editor to tell the user
+    blocked_edit: bool,
+    /// Value of `Editor::focus_counter` when this file was last the current one
+    last_focus: u64,
+    /// States before each edit, the last one being the most recent
+    undo_stack: Vec<Snapshot>,
+    /// States that were undone, so they can be redone
+    redo_stack: Vec<Snapshot>,
+    /// Kind of the last edit, consecutive edits of the same kind are undone together
+    last_edit: Option<EditKind>,
+    /// Changes with every edit, so the language servers only hear about files that changed
+    version: u64,
+    /// Lines of the file in git's HEAD commit, `None` outside a repository or when the file isn't committed
+    git_head: Option<Vec<String>>,
+    /// Change markers against `git_head` and the version they were computed for, diffing again only after an edit
+    git_changes: Option<(u64, Vec<Option<git::LineChange>>)>,
+    /// Bytes of a binary file, shown in a hex view instead of the text. `content` stays empty for those.
+    hex: Option<HexView>,
+    /// A file too big to load, read from disk as it is shown. `content` stays empty for those too.
+    large: Option<LargeFile>,
+}
+
+/// Undo steps kept per file, older ones are dropped
+const MAX_UNDO: usize = 256;
+/// Searches remembered in the history, older ones are dropped
+const MAX_SEARCH_HISTORY: usize = 50;
+/// How often to look for a different branch or commit checked out
+const GIT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a message stays in the footer
+const STATUS_DURATION: Duration = Duration::from_secs(3);
+const READ_ONLY_MESSAGE: &str = "read-only, run `readonly` to allow edits";
+const HEX_MESSAGE: &str = "binary file, type hex digits to overwrite bytes";
+const LARGE_FILE_MESSAGE: &str = "large file, opened read-only";
+
+/// Versions are unique among all files, a file replaced by another one never looks unchanged
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Content and cursor of a file at some point, for undo and redo
+struct Snapshot {
+    content: Vec<String>,
+    cursor: (u16, u16),
+}
+
+/// Text to put in place of the text between two positions, as (start, end, text)
+pub type Edit = ((u16, u16), (u16, u16), String);
+
+/// A cursor besides the main one, with its own selection
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Caret {
+    pub cursor: (u16, u16),
+    pub anchor: Option<(u16, u16)>,
+}
+
+impl Caret {
+    /// Selected range as (start, end) in document order, like `File::selection`
+    pub fn selection(&self) -> Option<((u16, u16), (u16, u16))> {
+        let anchor = self.anchor.filter(|&anchor| anchor != self.cursor)?;
+        if (anchor.1, anchor.0) < (self.cursor.1, self.cursor.0) {
+            Some((anchor, self.cursor))
+        } else {
+            Some((self.cursor, anchor))
+        }
+    }
+
+    /// Where the cursor or its selection starts, for putting cursors in document order
+    fn start(&self) -> (u16, u16) {
+        self.selection().map(|(start, _)| start).unwrap_or(self.cursor)
+    }
+}
+
+/// What an edit does, so runs of typing can be undone as one step instead of per character
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Typing,
+    Deleting,
+    /// Everything until `end_edit_group` is one step, like a vim command and the insert mode it started
+    Group,
 }
 
 impl File {
@@ -23,366 +140,2513 @@ impl File {
     pub fn new() -> Self {
         Self {
             path: None,
-            content: Vec::new(),
+            content: vec![String::new()],
             cursor: (0, 0),
             scroll: (0, 0),
+            selection_anchor: None,
+            extra_carets: Vec::new(),
+            snippet_stops: Vec::new(),
 
             is_dirty: false,
             saved_time: None,
+            disk_stamp: None,
+            swap_hash: None,
+            disk_conflict: false,
+            read_only: false,
+            blocked_edit: false,
+            last_focus: 0,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_edit: None,
+            version: next_version(),
+            git_head: None,
+            git_changes: None,
+            hex: None,
+            large: None,
         }
     }
 
     /// Open a file from a path
     pub fn from_path(path: &str) -> Result<Self, std::io::Error> {
+        //Not read at all, so no stamp to notice changes with and no HEAD version to compare to
+        if std::fs::metadata(path)?.len() > CONFIG.large_file_threshold.saturating_mul(1024 * 1024) {
+            return Ok(Self { path: Some(path.to_string()), read_only: true, large: Some(LargeFile::open(path)?), ..Self::new() });
+        }
+        let (bytes, disk_stamp) = read_bytes(path)?;
+        let (content, hex) = if fileio::is_binary(&bytes) {
+            (vec![String::new()], Some(HexView::new(bytes)))
+        } else {
+            (text_lines(bytes)?, None)
+        };
+        let read_only = !fileio::is_writable(Path::new(path));
         Ok(Self {
             path: Some(path.to_string()),
-            content: std::fs::read_to_string(path)?.lines().map(|s| s.to_string()).collect::<Vec<String>>(),
+            content,
             cursor: (0, 0),
             scroll: (0, 0),
+            selection_anchor: None,
+            extra_carets: Vec::new(),
+            snippet_stops: Vec::new(),
 
             is_dirty: false,
             saved_time: None,
+            disk_stamp: Some(disk_stamp),
+            swap_hash: None,
+            disk_conflict: false,
+            read_only,
+            blocked_edit: false,
+            last_focus: 0,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_edit: None,
+            version: next_version(),
+            git_head: git::head_lines(path),
+            git_changes: None,
+            hex,
+            large: None,
         })
     }
 
+    /// Saves the file to its path, formatting it first if `format_on_save` is set.
+    /// A formatter that fails doesn't keep the file from being saved, its error is returned afterwards.
     pub fn save(&mut self) -> Result<(), std::io::Error> {
-        if let Some(path) = &self.path {
-            self.save_file_to_path(path.to_string())
-        } else {
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "No path!"))
+        let path = self.path.clone().ok_or_else(|| std::io::Error::other("No path!"))?;
+        self.check_writable()?;
+        //Language servers answer later, the editor saves once they did
+        let formatted = match CONFIG.formatter(self.extension()) {
+            Some(command) if CONFIG.format_on_save && command != format::LSP && self.hex.is_none() => self.format_with(command),
+            _ => Ok(()),
+        };
+        self.save_file_to_path(path)?;
+        formatted
+    }
+
+    /// Pipes the buffer through the formatter `command` and applies the changes it made
+    pub fn format_with(&mut self, command: &str) -> Result<(), std::io::Error> {
+        self.check_writable()?;
+        let path = self.path.clone().unwrap_or_else(|| format!("unsaved.{}", self.extension()));
+        let formatted = format::run(command, &path, &self.content_string())?;
+        self.apply_formatted(formatted.lines().map(str::to_string).collect());
+        Ok(())
+    }
+
+    /// Pipes the selection, or the whole file without one, through the shell command `command` and replaces it with the output
+    pub fn filter_through(&mut self, command: &str) -> Result<(), std::io::Error> {
+        self.check_writable()?;
+        let Some((start, end)) = self.selection() else {
+            let output = shell::run(command, Some(&self.content_string()))?;
+            self.apply_formatted(output.lines().map(str::to_string).collect());
+            return Ok(());
+        };
+        let text = self.text_range(start, end);
+        let output = shell::run(command, Some(&text))?.replace("\r\n", "\n");
+        //Most programs end their output with a line break, which only belongs there if the selection had one
+        let output = if text.ends_with('\n') { output.as_str() } else { output.strip_suffix('\n').unwrap_or(&output) };
+        if !self.begin_edit(None) {
+            return Ok(());
         }
+        self.insert_text(output);
+        self.set_selection(start, self.cursor);
+        Ok(())
     }
 
-    pub fn save_file_to_path(&mut self, path: String) -> Result<(), std::io::Error> {
-        let content: String = self.content.iter().map(|s| {
+    /// Runs the shell command `command` and types its output at the cursor, replacing the selection
+    pub fn insert_output(&mut self, command: &str) -> Result<(), std::io::Error> {
+        self.check_writable()?;
+        let output = shell::run(command, None)?.replace("\r\n", "\n");
+        if !self.begin_edit(None) {
+            return Ok(());
+        }
+        self.insert_text(output.strip_suffix('\n').unwrap_or(&output));
+        Ok(())
+    }
+
+    /// Replaces the content with `lines`, touching only the lines that differ as one undo step.
+    /// The cursor stays on its line, at the same place relative to the line's indentation.
+    pub fn apply_formatted(&mut self, mut lines: Vec<String>) {
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let edits = crate::diff::diff(&self.content, &lines);
+        let hunks = crate::diff::hunks(&edits, 0);
+        if hunks.is_empty() {
+            return;
+        }
+        if !self.begin_edit(None) {
+            return;
+        }
+        self.extra_carets.clear();
+        self.snippet_stops.clear();
+        self.selection_anchor = None;
+
+        let (x, y) = self.cursor;
+        let new_y = crate::diff::map_index(&edits, y as usize).min(lines.len() - 1);
+        let indent = |line: &str| line.graphemes(true).take_while(|g| g.trim().is_empty()).count() as u16;
+        let old_indent = indent(&self.content[y as usize]);
+        let new_x = if x >= old_indent { x - old_indent + indent(&lines[new_y]) } else { x };
+
+        //Back to front, so each hunk leaves the line numbers of the ones before it alone
+        for hunk in hunks.iter().rev() {
+            self.content.splice(hunk.old_start..hunk.old_start + hunk.old_len, lines[hunk.new_start..hunk.new_start + hunk.new_len].iter().cloned());
+        }
+        self.is_dirty = true;
+        self.set_cursor((new_x, new_y as u16));
+    }
+
+    fn content_string(&self) -> String {
+        self.content.iter().map(|s| {
             let mut s = String::from(s);
             s.push('\n');
             s
-        }).collect();
-        std::fs::write(path.clone(), content)?;
-        self.is_dirty = false;
-        self.saved_time = Some(Instant::now());
-        self.path = Some(path);
-        Ok(())
+        }).collect()
     }
 
-    pub fn path(&self) -> Option<&String> {
-        self.path.as_ref()
+    /// What saving writes: the bytes of a binary file or the lines of a text file
+    fn content_bytes(&self) -> Vec<u8> {
+        match &self.hex {
+            Some(hex) => hex.bytes().to_vec(),
+            None => self.content_string().into_bytes(),
+        }
     }
 
-    pub fn content(&self) -> &Vec<String> {
-        &self.content
+    /// Returns true if the content is what we last loaded or saved
+    fn matches_disk(&self) -> bool {
+        self.disk_stamp.as_ref().is_some_and(|stamp| stamp.hash == fileio::hash_bytes(&self.content_bytes()))
     }
 
-    pub fn cursor(&self) -> (u16, u16) {
-        self.cursor
+    /// Fails for large files, which aren't loaded so there is nothing to write or compare
+    fn check_loaded(&self) -> Result<(), std::io::Error> {
+        match self.large {
+            Some(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Large files are only viewed, not loaded!")),
+            None => Ok(()),
+        }
     }
 
-    pub fn cursor_unscrolled(&self) -> (u16, u16) {
-        (self.cursor.0 - self.scroll.1, self.cursor.1 - self.scroll.1)
+    /// Errors for read-only files, saving or formatting them would change them on disk
+    pub fn check_writable(&self) -> Result<(), std::io::Error> {
+        self.check_loaded()?;
+        match self.read_only {
+            true => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "The file is read-only, run `readonly` to allow edits!")),
+            false => Ok(()),
+        }
     }
 
-    pub fn is_dirty(&self) -> bool {
-        self.is_dirty
+    pub fn save_file_to_path(&mut self, path: String) -> Result<(), std::io::Error> {
+        self.check_loaded()?;
+        let content = self.content_bytes();
+        fileio::write_atomic(Path::new(&path), &content, CONFIG.backup)?;
+        self.mark_saved(path, &content);
+        Ok(())
     }
 
-    pub fn saved_time(&self) -> Option<Instant> {
-        self.saved_time
+    /// Saves the file through `sudo_command`, for files we aren't allowed to write ourselves
+    pub fn save_with_sudo(&mut self) -> Result<(), std::io::Error> {
+        let path = self.path.clone().ok_or_else(|| std::io::Error::other("No path!"))?;
+        self.check_writable()?;
+        let content = self.content_bytes();
+        fileio::write_privileged(Path::new(&path), &content, &CONFIG.sudo_command)?;
+        self.mark_saved(path, &content);
+        Ok(())
     }
 
-    fn line_length(&self) -> u16 {
-        self.content[self.cursor.1 as usize].len() as u16
+    /// Remembers that `content` is what is on disk at `path` now
+    fn mark_saved(&mut self, path: String, content: &[u8]) {
+        let modified = std::fs::metadata(&path).ok().and_then(|meta| meta.modified().ok());
+        self.disk_stamp = Some(DiskStamp::from_bytes(content, modified));
+        if let Some(hex) = &mut self.hex {
+            hex.mark_saved();
+        }
+        self.is_dirty = false;
+        self.saved_time = Some(Instant::now());
+        self.path = Some(path);
+        self.refresh_git_head();
     }
 
-    fn line_count(&self) -> u16 {
-        self.content.len() as u16
+    /// Reads the HEAD version of the file again, after it was saved somewhere else or a commit was made
+    pub fn refresh_git_head(&mut self) {
+        if self.large.is_some() {
+            return;
+        }
+        self.git_head = self.path.as_deref().and_then(git::head_lines);
+        self.git_changes = None;
     }
 
-    pub fn move_cursor(&mut self, dx: i16, dy: i16) {
-        if dx < 0  {
-            if self.cursor.0 > 0 {
-                self.cursor.0 -= dx.abs() as u16;
-            } else if self.cursor.1 > 0 {
-                self.cursor.1 -= 1;
-                self.cursor.0 = self.line_length();
-            }
-        } else if dx > 0 {
-            if self.cursor.0 < self.line_length() {
-                self.cursor.0 += dx as u16;
-            } else if self.cursor.1 < self.line_count() - 1 {
-                self.cursor.0 = 0;
-                self.cursor.1 += 1;
-            }
+    /// How each line differs from the HEAD version, all `None` if there is no HEAD version
+    pub fn git_changes(&mut self) -> Vec<Option<git::LineChange>> {
+        let head = match &self.git_head {
+            Some(head) => head,
+            None => return vec![None; self.content.len()],
+        };
+        match &self.git_changes {
+            Some((version, changes)) if *version == self.version => changes.clone(),
+            _ => {
+                let changes = git::line_changes(head, &self.content);
+                self.git_changes = Some((self.version, changes.clone()));
+                changes
+            },
         }
+    }
 
-        if dy < 0 {
-            if self.cursor.1 > 0 {
-                self.cursor.1 -= dy.abs() as u16;
-                self.cursor.0 = self.cursor.0.min(self.line_length());
-            }
-        } else if dy > 0 {
-            if self.cursor.1 < self.line_count() - 1 {
-                self.cursor.1 += dy as u16;
-                self.cursor.0 = self.cursor.0.min(self.line_length());
-            }
+    /// Puts the lines of the change at the cursor back the way they are in HEAD
+    pub fn revert_hunk(&mut self) -> Result<(), std::io::Error> {
+        let head = self.git_head.as_ref().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "The file isn't committed to a git repository!"))?;
+        let (range, lines) = git::hunk_at(head, &self.content, self.cursor.1 as usize)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No change at the cursor!"))?;
+        if !self.begin_edit(None) {
+            return Ok(());
         }
+        self.extra_carets.clear();
+        self.set_cursor((0, range.start as u16));
+        self.replace_lines(range, lines);
+        Ok(())
     }
 
-    pub fn add_line(&mut self) {
-        if self.cursor.0 >= self.line_length() {
-            if self.cursor.1 >= self.line_count()-1 {
-                self.content.push(String::new());
-            } else {
-                self.content.insert(self.cursor.1 as usize + 1, String::new());
-            }
-        } else {
-            //Not at the end of a line, so we have to split the current line and move it the right side down
-            let left: String = self.content[self.cursor.1 as usize][..].graphemes(true).take(self.cursor.0 as usize).collect();
-            let right: String = self.content[self.cursor.1 as usize][..].graphemes(true).skip(self.cursor.0 as usize).collect();
-            self.content[self.cursor.1 as usize] = left;
-            self.content.insert(self.cursor.1 as usize + 1, right);
+    /// Writes the buffer to `swap_path` if it changed since the last swap write
+    pub fn write_swap(&mut self, swap_path: &Path) -> Result<(), std::io::Error> {
+        let content = self.content_bytes();
+        let hash = fileio::hash_bytes(&content);
+        if self.swap_hash != Some(hash) || !swap_path.exists() {
+            fileio::write_swap(swap_path, &content)?;
+            self.swap_hash = Some(hash);
         }
-        self.move_cursor(0, 1);
-        self.cursor.0 = 0;
-        self.is_dirty = true;
+        Ok(())
     }
 
-    pub fn add_character(&mut self, c: char) {
-        if self.cursor.0 >= self.line_length() {
-            self.content[self.cursor.1 as usize].push(c);
-        } else {
-            //We are not at the end of a line, so we have to split, push and merge
-            let mut left: String = self.content[self.cursor.1 as usize][..].graphemes(true).take(self.cursor.0 as usize).collect();
-            let right: String = self.content[self.cursor.1 as usize][..].graphemes(true).skip(self.cursor.0 as usize).collect();
-            left.push(c);
-            left.push_str(&right);
-            self.content[self.cursor.1 as usize] = left;
+    /// Replaces the buffer with the content of a swap file, leaving it dirty so it still has to be saved
+    pub fn recover_from(&mut self, swap_path: &Path) -> Result<(), std::io::Error> {
+        let bytes = std::fs::read(swap_path)?;
+        let hash = fileio::hash_bytes(&bytes);
+        match &mut self.hex {
+            Some(hex) => hex.set_bytes(bytes),
+            None => self.content = text_lines(bytes)?,
         }
-        self.move_cursor(1, 0);
+        self.version = next_version();
+        self.cursor = (0, 0);
+        self.scroll = (0, 0);
+        self.selection_anchor = None;
+        self.extra_carets.clear();
         self.is_dirty = true;
+        self.swap_hash = Some(hash);
+        Ok(())
     }
 
-    #[inline(never)]
-    pub fn remove_token(&mut self) {
-        let mut c = self.content[self.cursor.1 as usize].chars().nth(self.cursor.0 as usize - 1);
+    /// Checks whether the file on disk changed since we last looked at it.
+    /// Touching a file without changing its content just refreshes the stamp.
+    pub fn poll_disk_change(&mut self) -> bool {
+        let (path, stamp) = match (&self.path, &self.disk_stamp) {
+            (Some(path), Some(stamp)) => (path, stamp),
+            _ => return false,
+        };
+        let modified = match std::fs::metadata(path).and_then(|meta| meta.modified()) {
+            Ok(modified) => Some(modified),
+            Err(_) => return false,
+        };
+        if modified == stamp.modified {
+            return false;
+        }
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        let new_stamp = DiskStamp::from_bytes(&bytes, modified);
+        if new_stamp.hash == stamp.hash {
+            self.disk_stamp = Some(new_stamp);
+            return false;
+        }
+        true
+    }
 
-        'remove: while c.is_some() && self.cursor.0 > 0 {
-            if c.unwrap().is_whitespace() { break 'remove; }
-            self.remove_character();
-            c = self.content[self.cursor.1 as usize].chars().nth(self.cursor.0 as usize - 1);
+    /// Replaces the buffer with the file on disk, keeping the cursor where it was as far as possible
+    pub fn reload(&mut self) -> Result<(), std::io::Error> {
+        let path = self.path.clone().ok_or_else(|| std::io::Error::other("No path!"))?;
+        if self.large.is_some() {
+            self.large = Some(LargeFile::open(&path)?);
+            return Ok(());
+        }
+        let (bytes, disk_stamp) = read_bytes(&path)?;
+        match &mut self.hex {
+            Some(hex) => hex.set_bytes(bytes),
+            None => self.content = text_lines(bytes)?,
         }
+        self.version = next_version();
+        self.disk_stamp = Some(disk_stamp);
+        self.is_dirty = false;
+        self.disk_conflict = false;
+        self.selection_anchor = None;
+        self.extra_carets.clear();
+        self.cursor.1 = self.cursor.1.min(self.line_count().saturating_sub(1));
+        self.cursor.0 = self.cursor.0.min(self.line_length());
+        //Whatever changed the file, like checking out another branch, may have changed HEAD too
+        self.refresh_git_head();
+        Ok(())
     }
 
-    pub fn remove_character(&mut self) {
-        if self.cursor.0 == 0 {
-            if self.cursor.1 > 0 {
-                let cur = self.content[self.cursor.1 as usize].clone();
-                let next_x = self.content[self.cursor.1 as usize - 1].len() as u16;
-                self.content[self.cursor.1 as usize - 1].push_str(&cur);
-                self.content.remove(self.cursor.1 as usize);
-                // self.move_cursor(0,-1);
-                self.cursor.1 -= 1;
-                self.cursor.0 = next_x;
-                self.is_dirty = true;
+    /// Accepts the current version on disk as the one we're editing on top of, so saving overwrites it without asking
+    pub fn acknowledge_disk_change(&mut self) {
+        if let Some(path) = &self.path {
+            let modified = std::fs::metadata(path).ok().and_then(|meta| meta.modified().ok());
+            if let Ok(bytes) = std::fs::read(path) {
+                self.disk_stamp = Some(DiskStamp::from_bytes(&bytes, modified));
             }
-        } else if self.cursor.0 > 0 {
-            //Not at the start of a line, so we have to remove characters
-            let mut left: String = self.content[self.cursor.1 as usize][..].graphemes(true).take(self.cursor.0 as usize - 1).collect();
-            let right: String = self.content[self.cursor.1 as usize][..].graphemes(true).skip(self.cursor.0 as usize).collect();
-            left.push_str(&right);
-            self.content[self.cursor.1 as usize] = left;
-            self.move_cursor(-1, 0);
-            self.is_dirty = true;
         }
+        self.disk_conflict = false;
     }
 
-    pub fn delete_character(&mut self) {
-        if self.cursor.0 < self.line_length() {
-            self.move_cursor(1, 0);
-            self.remove_character();
-            // self.move_cursor(-1, 0);
+    /// Lines of the version on disk, none for a file that was never saved
+    pub fn disk_lines(&self) -> Result<Vec<String>, std::io::Error> {
+        self.check_loaded()?;
+        match &self.path {
+            Some(path) => Ok(read_lines(path)?.0),
+            None => Ok(Vec::new()),
         }
     }
-}
-
-pub struct Editor {
-    pub open_files: Vec<File>,
-    pub cur_file_idx: usize,
-
-    pub styled_text: Text<'static>,
-}
 
-impl Editor {
-    pub fn from_paths(paths: Vec<&str>) -> Result<Self, std::io::Error> {
-        let files = if paths.is_empty() {
-            vec![File::new()]
-        } else {
-            let mut tmp = Vec::new();
-            for path in paths {
-                tmp.push(File::from_path(path)?);
-            }
-            tmp
-        };
-        let mut obj = Self {
-            open_files: files,
-            cur_file_idx: 0,
+    /// Lines in git's HEAD commit, if the file is committed
+    pub fn git_head(&self) -> Option<&Vec<String>> {
+        self.git_head.as_ref()
+    }
 
-            styled_text: Text::default(),
-        };
-        obj.update_styled_text();
-        Ok(obj)
+    /// Returns true if the file was modified on disk since we loaded or last saved it
+    pub fn changed_on_disk(&self) -> bool {
+        match (&self.path, &self.disk_stamp) {
+            (Some(path), Some(stamp)) => stamp.is_outdated(Path::new(path)),
+            _ => false,
+        }
     }
 
-    pub fn save_file(&mut self) -> Result<(), std::io::Error> {
-        self.open_files[self.cur_file_idx].save()
+    pub fn path(&self) -> Option<&String> {
+        self.path.as_ref()
     }
 
-    pub fn save_file_to_path(&mut self, path: String) -> Result<(), std::io::Error> {
-        self.open_files[self.cur_file_idx].save_file_to_path(path)
+    pub fn content(&self) -> &Vec<String> {
+        &self.content
     }
 
-    pub fn load_file_from_path(&mut self, path: String) -> Result<(), std::io::Error> {
-        let mut is_open = false;
-        let mut open_idx = 0;
-        'search: for (i, file) in self.open_files.iter().enumerate() {
-            if file.path == Some(path.clone()) {
-                open_idx = i;
-                is_open = true;
-                break 'search;
-            }
-        }
-        if !is_open {
-            self.open_files.push(File::from_path(&path)?);
-        } else {
-            self.cur_file_idx = open_idx;
+    pub fn extension(&self) -> &str {
+        match &self.path {
+            Some(path) => Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or(""),
+            None => "",
         }
+    }
 
-        self.update_styled_text();
+    pub fn cursor(&self) -> (u16, u16) {
+        self.cursor
+    }
 
-        Ok(())
+    /// Moves the cursor to `cursor`, clamped to the content
+    pub fn set_cursor(&mut self, cursor: (u16, u16)) {
+        self.cursor.1 = cursor.1.min(self.line_count().saturating_sub(1));
+        self.cursor.0 = cursor.0.min(self.line_length());
     }
 
-    fn update_styled_text(&mut self) {
-        let mut content_spans = Vec::new();
-        let lines = self.content();
-        let max_nums = (lines.len().max(1)-1).to_string().chars().count();
-        for (i, line) in lines.into_iter().enumerate() {
-            let line_num = format!("{:width$}~ ", i, width = max_nums);
-            let line = format!("{}", line.replace('\t', "    "));
-            let styled_line_num = crate::style::editor_style(line_num);
-            let mut styled_line = crate::style::style_line(line, self.extension());
-            styled_line.0.insert(0, styled_line_num);
-            content_spans.push(styled_line);
-        }
-        self.styled_text = Text::from(content_spans);
+    pub fn scroll(&self) -> (u16, u16) {
+        self.scroll
     }
 
-    pub fn all_filenames(&self) -> impl Iterator<Item = &str> {
-        use std::path::Path;
-        use std::ffi::OsStr;
-        self.all_paths().map(Path::new).map(|p| p.file_name().unwrap_or(OsStr::new("unsaved"))).map(|s| s.to_str().expect("Filenames with non-unicode characters are not supported!"))
+    pub fn set_scroll(&mut self, scroll: (u16, u16)) {
+        self.scroll = (scroll.0, scroll.1.min(self.line_count().saturating_sub(1)));
     }
 
-    pub fn all_filenames_modified(&self) -> impl Iterator<Item = String> + '_ {
-        self.all_filenames().enumerate().map(move |(i, s)| {
-            if self.open_files[i].is_dirty() {
-                format!("*{}", s)
-            } else {
-                s.to_string()
-            }
-        })
+    /// Screen column of the cursor before scrolling, which is further right than `cursor.0` after a tab
+    fn cursor_column(&self) -> u16 {
+        self.content.get(self.cursor.1 as usize)
+            .map_or(self.cursor.0, |line| util::display_column(line, self.cursor.0 as usize) as u16)
     }
 
-    pub fn all_paths(&self) -> impl Iterator<Item = &String> {
-        self.open_files.iter().filter_map(File::path)
+    /// Cursor position relative to the top left of the view, `None` if it is scrolled out of view
+    pub fn cursor_unscrolled(&self) -> Option<(u16, u16)> {
+        let x = self.cursor_column();
+        if self.cursor.1 < self.scroll.1 || x < self.scroll.0 {
+            return None;
+        }
+        Some((x - self.scroll.0, self.cursor.1 - self.scroll.1))
     }
 
-    pub fn path(&self) -> Option<&String> {
-        self.open_files[self.cur_file_idx].path()
+    /// Scrolls by `dy` lines without moving the cursor
+    pub fn scroll_by(&mut self, dy: i16) {
+        let top = (self.scroll.1 as i32 + dy as i32).clamp(0, self.line_count().saturating_sub(1) as i32);
+        self.scroll.1 = top as u16;
     }
 
-    pub fn extension(&self) -> &str {
-        if let Some(filename) = self.path() {
-            std::path::Path::new(filename).extension().unwrap_or(std::ffi::OsStr::new("")).to_str().expect("Extension contains non-Unicode characters!")
+    /// Scrolls just far enough to have the cursor inside a view of `width` columns and `height` lines
+    pub fn scroll_to_cursor(&mut self, width: u16, height: u16) {
+        if self.cursor.1 < self.scroll.1 {
+            self.scroll.1 = self.cursor.1;
+        } else if height > 0 && self.cursor.1 >= self.scroll.1 + height {
+            self.scroll.1 = self.cursor.1 + 1 - height;
+        }
+        //The view scrolls by screen columns
+        let x = self.cursor_column();
+        if x < self.scroll.0 {
+            self.scroll.0 = x;
+        } else if width > 0 && x >= self.scroll.0 + width {
+            self.scroll.0 = x + 1 - width;
+        }
+    }
+
+    /// Selected range as (start, end) in document order, `None` if nothing is selected
+    pub fn selection(&self) -> Option<((u16, u16), (u16, u16))> {
+        let anchor = self.selection_anchor?;
+        if anchor == self.cursor {
+            return None;
+        }
+        if (anchor.1, anchor.0) < (self.cursor.1, self.cursor.0) {
+            Some((anchor, self.cursor))
         } else {
-            ""
+            Some((self.cursor, anchor))
         }
     }
 
-    pub fn content(&self) -> &Vec<String> {
-        self.open_files[self.cur_file_idx].content()
+    /// Selects from `anchor` to `cursor`, leaving the cursor at `cursor`
+    pub fn set_selection(&mut self, anchor: (u16, u16), cursor: (u16, u16)) {
+        self.set_cursor(anchor);
+        self.selection_anchor = Some(self.cursor);
+        self.set_cursor(cursor);
     }
 
-    pub fn is_dirty(&self) -> bool {
-        self.open_files[self.cur_file_idx].is_dirty()
+    pub fn clear_selection(&mut self) {
+        self.selection_anchor = None;
     }
 
-    pub fn seconds_since_save(&self) -> Option<u64> {
-        Some(self.open_files[self.cur_file_idx].saved_time()?.elapsed().as_secs())
+    /// Other end of the selection, which may be the cursor itself
+    pub fn selection_anchor(&self) -> Option<(u16, u16)> {
+        self.selection_anchor
     }
 
-    pub fn cursor(&self) -> (u16, u16) {
-        self.open_files[self.cur_file_idx].cursor_unscrolled()
+    /// Cursors besides the main one
+    pub fn extra_carets(&self) -> &[Caret] {
+        &self.extra_carets
     }
 
-    pub fn increment_file_idx(&mut self) {
-        self.cur_file_idx += 1;
-        if self.cur_file_idx >= self.open_files.len() {
-            self.cur_file_idx = 0;
-        }
-        self.update_styled_text();
+    /// Drops all cursors but the main one, returning false if there were none
+    pub fn clear_extra_carets(&mut self) -> bool {
+        let had_carets = !self.extra_carets.is_empty();
+        self.extra_carets.clear();
+        had_carets
     }
 
-    pub fn decrement_file_idx(&mut self) {
-        if self.cur_file_idx > 0 {
-            self.cur_file_idx -= 1;
-        } else {
-            self.cur_file_idx = self.open_files.len() - 1;
-        }
-        self.update_styled_text();
+    /// Number of cursors, including the main one
+    pub fn cursor_count(&self) -> usize {
+        self.extra_carets.len() + 1
     }
 
-    fn move_cursor(&mut self, dx: i16, dy: i16) {
-        self.open_files[self.cur_file_idx].move_cursor(dx,dy);
-        // self.update_styled_text();
+    fn main_caret(&self) -> Caret {
+        Caret { cursor: self.cursor, anchor: self.selection_anchor }
     }
 
-    pub fn handle_key(&mut self, key: KeyEvent) {
-        match key.code {
-            KeyCode::Left => self.move_cursor(-1, 0),
-            KeyCode::Right => self.move_cursor(1, 0),
-            KeyCode::Up => self.move_cursor(0,-1),
-            KeyCode::Down => self.move_cursor(0, 1),
+    /// Makes `caret` the main cursor, keeping the current one as an extra cursor
+    fn push_caret(&mut self, caret: Caret) {
+        self.extra_carets.push(self.main_caret());
+        self.cursor = caret.cursor;
+        self.selection_anchor = caret.anchor;
+        self.last_edit = None;
+    }
 
-            KeyCode::Enter => {
-                self.open_files[self.cur_file_idx].add_line();
-                // self.move_cursor(0, 1);
-                self.update_styled_text();
+    /// Clamps the extra cursors to the content and drops the ones that ended up on top of another
+    fn merge_carets(&mut self) {
+        let mut seen = vec![self.cursor];
+        let mut carets = std::mem::take(&mut self.extra_carets);
+        for caret in &mut carets {
+            caret.cursor = self.clamp(caret.cursor);
+            caret.anchor = caret.anchor.map(|anchor| self.clamp(anchor));
+        }
+        carets.retain(|caret| {
+            if seen.contains(&caret.cursor) {
+                return false;
+            }
+            seen.push(caret.cursor);
+            true
+        });
+        self.extra_carets = carets;
+    }
+
+    fn clamp(&self, pos: (u16, u16)) -> (u16, u16) {
+        let y = pos.1.min(self.line_count().saturating_sub(1));
+        (pos.0.min(self.content[y as usize].graphemes(true).count() as u16), y)
+    }
+
+    /// Position as a grapheme offset from the start of the file, line breaks counting as one
+    fn offset_of(&self, pos: (u16, u16)) -> usize {
+        let lines: usize = self.content[..pos.1 as usize].iter().map(|line| line.graphemes(true).count() + 1).sum();
+        lines + pos.0 as usize
+    }
+
+    /// Inverse of `offset_of`, offsets past the end end up at the end of the file
+    fn pos_at(&self, mut offset: usize) -> (u16, u16) {
+        for (y, line) in self.content.iter().enumerate() {
+            let len = line.graphemes(true).count();
+            if offset <= len {
+                return (offset as u16, y as u16);
+            }
+            offset -= len + 1;
+        }
+        let last = self.line_count() - 1;
+        (self.content[last as usize].graphemes(true).count() as u16, last)
+    }
+
+    fn text_len(&self) -> usize {
+        self.offset_of((0, self.line_count() - 1)) + self.content[self.content.len() - 1].graphemes(true).count()
+    }
+
+    /// Runs `edit` at every cursor, as if it was the only one. Cursors are visited in document order,
+    /// each one moved along with the text that edits at the cursors before it added or removed.
+    /// `edit` also gets the index of the cursor in that order.
+    pub fn for_each_cursor(&mut self, mut edit: impl FnMut(&mut File, usize)) {
+        if self.extra_carets.is_empty() {
+            edit(self, 0);
+            return;
+        }
+        let main = self.main_caret();
+        let mut carets = std::mem::take(&mut self.extra_carets);
+        carets.push(main);
+        carets.sort_by_key(|caret| (caret.start().1, caret.start().0));
+        let main_idx = carets.iter().position(|&caret| caret == main).unwrap_or(0);
+
+        let offsets: Vec<(usize, Option<usize>)> = carets.iter()
+            .map(|caret| (self.offset_of(caret.cursor), caret.anchor.map(|anchor| self.offset_of(anchor))))
+            .collect();
+        let mut shift = 0isize;
+        let mut edited = Vec::with_capacity(offsets.len());
+        for (i, (cursor, anchor)) in offsets.into_iter().enumerate() {
+            let shifted = |offset: usize| (offset as isize + shift).max(0) as usize;
+            self.cursor = self.pos_at(shifted(cursor));
+            self.selection_anchor = anchor.map(|anchor| self.pos_at(shifted(anchor)));
+            let len = self.text_len();
+            edit(self, i);
+            shift += self.text_len() as isize - len as isize;
+            edited.push(self.main_caret());
+        }
+
+        let main = edited.remove(main_idx);
+        self.cursor = main.cursor;
+        self.selection_anchor = main.anchor;
+        self.extra_carets = edited;
+        self.merge_carets();
+    }
+
+    /// Adds a cursor on the line above (`dy` < 0) or below the topmost or bottommost cursor, in the same column
+    pub fn add_cursor_vertically(&mut self, dy: i16) {
+        let carets = self.extra_carets.iter().map(|caret| caret.cursor).chain(std::iter::once(self.cursor));
+        let edge = if dy < 0 { carets.min_by_key(|&(x, y)| (y, x)) } else { carets.max_by_key(|&(x, y)| (y, x)) };
+        let Some((x, y)) = edge else { return };
+        let y = y as i32 + dy.signum() as i32;
+        if y < 0 || y >= self.line_count() as i32 {
+            return;
+        }
+        let cursor = self.clamp((x, y as u16));
+        if cursor != self.cursor && !self.extra_carets.iter().any(|caret| caret.cursor == cursor) {
+            self.push_caret(Caret { cursor, anchor: None });
+        }
+    }
+
+    /// Every occurrence of `needle` as (start, end), in document order. Needles spanning lines never match.
+    pub fn find_all(&self, needle: &str) -> Vec<((u16, u16), (u16, u16))> {
+        if needle.is_empty() || needle.contains('\n') {
+            return Vec::new();
+        }
+        let len = needle.graphemes(true).count() as u16;
+        let mut matches = Vec::new();
+        for (y, line) in self.content.iter().enumerate() {
+            for (byte, _) in line.match_indices(needle) {
+                let x = line[..byte].graphemes(true).count() as u16;
+                matches.push(((x, y as u16), (x + len, y as u16)));
+            }
+        }
+        matches
+    }
+
+    /// Adds a cursor selecting the next occurrence of the selected text (ctrl + d), wrapping around at the end.
+    /// Without a selection the word under the cursor is selected first.
+    pub fn add_next_occurrence(&mut self) {
+        let (start, end) = match self.selection() {
+            Some((start, end)) if start.1 == end.1 => (start, end),
+            Some(_) => return,
+            None => {
+                self.select_word_at(self.cursor);
+                return;
+            },
+        };
+        let needle = self.text_range(start, end);
+        let taken: Vec<(u16, u16)> = self.extra_carets.iter().map(Caret::start).chain(std::iter::once(start)).collect();
+        let matches = self.find_all(&needle);
+        let free = |&&(s, _): &&((u16, u16), (u16, u16))| !taken.contains(&s);
+        let next = matches.iter().filter(free).find(|(s, _)| (s.1, s.0) > (end.1, end.0))
+            .or_else(|| matches.iter().find(free));
+        if let Some(&(s, e)) = next {
+            self.push_caret(Caret { cursor: e, anchor: Some(s) });
+        }
+    }
+
+    /// Puts a cursor on every occurrence of `needle`, each one selecting it, and returns how many there are.
+    /// The main cursor goes to the first occurrence after the current one, so the view doesn't jump far.
+    pub fn select_all_matches(&mut self, needle: &str) -> usize {
+        let matches = self.find_all(needle);
+        if matches.is_empty() {
+            return 0;
+        }
+        let cursor = (self.cursor.1, self.cursor.0);
+        let main = matches.iter().position(|(s, _)| (s.1, s.0) >= cursor).unwrap_or(0);
+        self.extra_carets = matches.iter().enumerate()
+            .filter(|&(i, _)| i != main)
+            .map(|(_, &(s, e))| Caret { cursor: e, anchor: Some(s) })
+            .collect();
+        self.selection_anchor = Some(matches[main].0);
+        self.cursor = matches[main].1;
+        self.last_edit = None;
+        matches.len()
+    }
+
+    /// Text from `start` up to but not including `end`, lines joined with `\n`
+    pub fn text_range(&self, start: (u16, u16), end: (u16, u16)) -> String {
+        let mut lines = Vec::new();
+        for y in start.1..=end.1 {
+            let line = self.content[y as usize].graphemes(true);
+            let from = if y == start.1 { start.0 as usize } else { 0 };
+            if y == end.1 {
+                lines.push(line.take(end.0 as usize).skip(from).collect::<String>());
+            } else {
+                lines.push(line.skip(from).collect::<String>());
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Types `text` at the cursor, replacing the selection
+    pub fn insert_text(&mut self, text: &str) {
+        self.delete_selection();
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.add_line();
+            }
+            for c in line.chars() {
+                self.add_character(c);
+            }
+        }
+    }
+
+    /// Types the expanded snippet `text` at the cursor and selects its first tab stop.
+    /// `stops` are grapheme ranges into `text`, in the order tab visits them.
+    pub fn insert_snippet(&mut self, text: &str, stops: Vec<(usize, usize)>) {
+        self.delete_selection();
+        let start = self.offset_of(self.cursor);
+        self.insert_text(text);
+        let len = self.text_len();
+        self.snippet_stops = stops.into_iter().map(|(stop_start, stop_end)| (len - (start + stop_start), stop_end - stop_start)).collect();
+        self.next_snippet_stop();
+    }
+
+    /// Selects the next tab stop of the last inserted snippet, returning false if there is none left
+    pub fn next_snippet_stop(&mut self) -> bool {
+        if self.snippet_stops.is_empty() {
+            return false;
+        }
+        let (from_end, len) = self.snippet_stops.remove(0);
+        //Deleting text the stop was in may have moved it past the start of the file
+        let Some(start) = self.text_len().checked_sub(from_end) else {
+            self.snippet_stops.clear();
+            return false;
+        };
+        let start_pos = self.pos_at(start);
+        if len == 0 {
+            self.clear_selection();
+            self.set_cursor(start_pos);
+        } else {
+            self.set_selection(start_pos, self.pos_at(start + len));
+        }
+        true
+    }
+
+    /// Replaces the lines in `range` with `lines`, keeping the cursor inside the content
+    pub fn replace_lines(&mut self, range: std::ops::Range<usize>, lines: Vec<String>) {
+        self.content.splice(range, lines);
+        if self.content.is_empty() {
+            self.content.push(String::new());
+        }
+        self.version = next_version();
+        self.selection_anchor = None;
+        self.extra_carets.clear();
+        self.is_dirty = true;
+        self.set_cursor(self.cursor);
+    }
+
+    /// Selects the word (or run of whitespace or punctuation) under `pos`
+    pub fn select_word_at(&mut self, pos: (u16, u16)) {
+        self.set_cursor(pos);
+        match word::segment_at(&self.content[self.cursor.1 as usize], self.cursor.0 as usize) {
+            Some((start, end)) => self.set_selection((start as u16, self.cursor.1), (end as u16, self.cursor.1)),
+            None => self.clear_selection(),
+        }
+    }
+
+    /// Selects the whole line `y`, including its line break when there is a next line
+    pub fn select_line(&mut self, y: u16) {
+        let y = y.min(self.line_count().saturating_sub(1));
+        if y + 1 < self.line_count() {
+            self.set_selection((0, y), (0, y + 1));
+        } else {
+            let len = self.content[y as usize].graphemes(true).count() as u16;
+            self.set_selection((0, y), (len, y));
+        }
+    }
+
+    /// Removes the selected text, returning false if there was no selection
+    pub fn delete_selection(&mut self) -> bool {
+        let (start, end) = match self.selection() {
+            Some(selection) => selection,
+            None => {
+                self.selection_anchor = None;
+                return false;
+            },
+        };
+        let left: String = self.content[start.1 as usize].graphemes(true).take(start.0 as usize).collect();
+        let right: String = self.content[end.1 as usize].graphemes(true).skip(end.0 as usize).collect();
+        self.content.drain(start.1 as usize + 1..=end.1 as usize);
+        self.content[start.1 as usize] = left + &right;
+        self.cursor = start;
+        self.selection_anchor = None;
+        self.is_dirty = true;
+        true
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty
+    }
+
+    /// Records the current state as an undo step, call it before changing the content.
+    /// Edits of the same `kind` in a row share a step, `None` starts a new one unless a group is open.
+    /// Returns false if the file can't be edited, the caller has to leave the content alone then.
+    pub fn begin_edit(&mut self, kind: Option<EditKind>) -> bool {
+        //Binary files only change through the hex view, which doesn't come through here
+        if self.read_only || self.hex.is_some() {
+            self.blocked_edit = true;
+            return false;
+        }
+        self.version = next_version();
+        if self.last_edit == Some(EditKind::Group) || (kind.is_some() && self.last_edit == kind) {
+            return true;
+        }
+        self.undo_stack.push(self.snapshot());
+        if self.undo_stack.len() > MAX_UNDO {
+            self.undo_stack.remove(0);
+        }
+        self.redo_stack.clear();
+        self.last_edit = kind;
+        true
+    }
+
+    /// Returns whether an edit was refused since the last call
+    pub fn take_blocked_edit(&mut self) -> bool {
+        std::mem::take(&mut self.blocked_edit)
+    }
+
+    /// Changes whenever the content does
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Hex view of a binary file, `None` for text files
+    pub fn hex(&self) -> Option<&HexView> {
+        self.hex.as_ref()
+    }
+
+    pub fn hex_mut(&mut self) -> Option<&mut HexView> {
+        self.hex.as_mut()
+    }
+
+    /// The file when it is too big to load, `None` for files that are loaded
+    pub fn large(&self) -> Option<&LargeFile> {
+        self.large.as_ref()
+    }
+
+    pub fn large_mut(&mut self) -> Option<&mut LargeFile> {
+        self.large.as_mut()
+    }
+
+    /// Handles a key in the hex view, `height` being the rows on screen
+    pub fn hex_key(&mut self, key: KeyEvent, height: usize) {
+        if let Some(hex) = &mut self.hex {
+            if hex.handle_key(key, height) {
+                self.is_dirty = true;
+            }
+        }
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only || self.large.is_some();
+    }
+
+    /// Makes the next edit start a new undo step
+    pub fn end_edit_group(&mut self) {
+        self.last_edit = None;
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot { content: self.content.clone(), cursor: self.cursor }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.content = snapshot.content;
+        self.version = next_version();
+        self.set_cursor(snapshot.cursor);
+        self.selection_anchor = None;
+        self.extra_carets.clear();
+        self.snippet_stops.clear();
+        self.last_edit = None;
+        //Undoing back to what is on disk makes the file clean again
+        self.is_dirty = !self.matches_disk();
+    }
+
+    /// Reverts the last edit, returning false if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if let Some(hex) = &mut self.hex {
+            if !hex.undo() {
+                return false;
+            }
+            self.is_dirty = !self.matches_disk();
+            return true;
+        }
+        //Steps that didn't change anything, like entering and leaving insert mode, are skipped
+        while let Some(snapshot) = self.undo_stack.pop() {
+            if snapshot.content != self.content {
+                self.redo_stack.push(self.snapshot());
+                self.restore(snapshot);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Redoes the last undone edit, returning false if there is nothing to redo
+    pub fn redo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if let Some(hex) = &mut self.hex {
+            if !hex.redo() {
+                return false;
+            }
+            self.is_dirty = !self.matches_disk();
+            return true;
+        }
+        match self.redo_stack.pop() {
+            Some(snapshot) => {
+                self.undo_stack.push(self.snapshot());
+                self.restore(snapshot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// First and last line the line operations work on: the selected lines or the cursor line.
+    /// A selection ending at the start of a line doesn't include that line.
+    pub fn selected_lines(&self) -> (u16, u16) {
+        match self.selection() {
+            Some((start, end)) if end.0 == 0 && end.1 > start.1 => (start.1, end.1 - 1),
+            Some((start, end)) => (start.1, end.1),
+            None => (self.cursor.1, self.cursor.1),
+        }
+    }
+
+    /// Moves the cursor and selection anchor by `dy` lines, used when lines move around
+    fn shift_lines(&mut self, dy: i32) {
+        let shift = |pos: (u16, u16)| (pos.0, (pos.1 as i32 + dy) as u16);
+        self.selection_anchor = self.selection_anchor.map(shift);
+        self.cursor = shift(self.cursor);
+        self.cursor.0 = self.cursor.0.min(self.line_length());
+    }
+
+    /// Moves the selected lines up (`dy` < 0) or down by one line
+    pub fn move_lines(&mut self, dy: i16) {
+        let (first, last) = self.selected_lines();
+        let (first, last) = (first as usize, last as usize);
+        if (dy < 0 && first == 0) || (dy > 0 && last + 1 >= self.content.len()) {
+            return;
+        }
+        if !self.begin_edit(None) {
+            return;
+        }
+        if dy < 0 {
+            self.content[first - 1..=last].rotate_left(1);
+            self.shift_lines(-1);
+        } else {
+            self.content[first..=last + 1].rotate_right(1);
+            self.shift_lines(1);
+        }
+        self.is_dirty = true;
+    }
+
+    /// Inserts a copy of the selected lines below them and moves the cursor and selection onto the copy
+    pub fn duplicate_lines(&mut self) {
+        let (first, last) = self.selected_lines();
+        if !self.begin_edit(None) {
+            return;
+        }
+        let copy = self.content[first as usize..=last as usize].to_vec();
+        let at = last as usize + 1;
+        self.content.splice(at..at, copy);
+        self.shift_lines((last - first) as i32 + 1);
+        self.is_dirty = true;
+    }
+
+    /// Removes the selected lines
+    pub fn delete_lines(&mut self) {
+        let (first, last) = self.selected_lines();
+        if !self.begin_edit(None) {
+            return;
+        }
+        self.replace_lines(first as usize..last as usize + 1, Vec::new());
+        self.set_cursor((self.cursor.0, first));
+    }
+
+    /// Joins the selected lines into one, or the cursor line with the next one.
+    /// Indentation of the joined lines is dropped and they are separated by a single space.
+    pub fn join_lines(&mut self) {
+        let (first, last) = self.selected_lines();
+        let last = if first == last { last + 1 } else { last };
+        if last as usize >= self.content.len() {
+            return;
+        }
+        if !self.begin_edit(None) {
+            return;
+        }
+        let mut joined = self.content[first as usize].trim_end().to_string();
+        let mut cursor = joined.graphemes(true).count() as u16;
+        for line in &self.content[first as usize + 1..=last as usize] {
+            let line = line.trim();
+            cursor = joined.graphemes(true).count() as u16;
+            if !joined.is_empty() && !line.is_empty() {
+                joined.push(' ');
+            }
+            joined.push_str(line);
+        }
+        self.replace_lines(first as usize..last as usize + 1, vec![joined]);
+        self.set_cursor((cursor, first));
+    }
+
+    /// Replaces the selected lines (or all lines without a multi-line selection) with what `f` makes of them,
+    /// like sorting them. The result stays selected.
+    pub fn transform_lines(&mut self, f: impl FnOnce(Vec<String>) -> Vec<String>) {
+        let (first, last) = match self.selected_lines() {
+            (first, last) if first != last => (first as usize, last as usize),
+            _ => (0, self.content.len() - 1),
+        };
+        let lines = f(self.content[first..=last].to_vec());
+        if lines[..] == self.content[first..=last] {
+            return;
+        }
+        if !self.begin_edit(None) {
+            return;
+        }
+        let count = lines.len();
+        self.replace_lines(first..last + 1, lines);
+        if count > 0 {
+            let end = (first + count - 1) as u16;
+            let len = self.content[end as usize].graphemes(true).count() as u16;
+            self.set_selection((0, first as u16), (len, end));
+        }
+    }
+
+    /// Comments out the selected lines or the cursor line with `tokens`, or uncomments them if they all are
+    pub fn toggle_comment(&mut self, tokens: (&'static str, Option<&'static str>)) {
+        let (first, last) = self.selected_lines();
+        let edits = comment::toggle(&self.content[first as usize..=last as usize], tokens);
+        if !self.begin_edit(None) {
+            return;
+        }
+        for (y, (line, column, dx)) in (first..=last).zip(edits) {
+            self.content[y as usize] = line;
+            let shift = |pos: (u16, u16)| if pos.1 == y && pos.0 >= column { ((pos.0 as i32 + dx).max(column as i32) as u16, y) } else { pos };
+            self.cursor = shift(self.cursor);
+            if let Some(anchor) = self.selection_anchor {
+                self.selection_anchor = Some(shift(anchor));
+            }
+        }
+        //Removing the end of a block comment can leave the cursor past the end of the line
+        self.cursor = self.clamp(self.cursor);
+        self.selection_anchor = self.selection_anchor.map(|anchor| self.clamp(anchor));
+        self.is_dirty = true;
+    }
+
+    /// Replaces ranges of text as one undo step, for edits worked out elsewhere like a language server's rename.
+    /// The ranges can't overlap. The cursor stays where it was, as far as the content allows.
+    pub fn apply_edits(&mut self, mut edits: Vec<Edit>) {
+        if edits.is_empty() {
+            return;
+        }
+        if !self.begin_edit(None) {
+            return;
+        }
+        self.extra_carets.clear();
+        let cursor = self.cursor;
+        //Back to front, so each edit leaves the positions of the ones before it alone
+        edits.sort_by_key(|&(start, _, _)| std::cmp::Reverse((start.1, start.0)));
+        for (start, end, text) in edits {
+            self.set_selection(start, end);
+            self.delete_selection();
+            self.insert_text(&text);
+        }
+        self.selection_anchor = None;
+        self.cursor = self.clamp(cursor);
+        self.is_dirty = true;
+    }
+
+    /// Saves the file if it has unsaved changes and autosaving it is safe:
+    /// it needs a path, must be writable and can't have been changed by another program.
+    /// Returns whether the file was saved.
+    pub fn autosave(&mut self) -> Result<bool, std::io::Error> {
+        let Some(path) = self.path.clone() else { return Ok(false) };
+        if !self.is_dirty || self.read_only || self.disk_conflict || self.changed_on_disk() {
+            return Ok(false);
+        }
+        //Not `save`, formatting would rearrange the text while the user is still typing it
+        self.save_file_to_path(path)?;
+        Ok(true)
+    }
+
+    pub fn saved_time(&self) -> Option<Instant> {
+        self.saved_time
+    }
+
+    fn line_length(&self) -> u16 {
+        self.content[self.cursor.1 as usize].graphemes(true).count() as u16
+    }
+
+    fn line_count(&self) -> u16 {
+        self.content.len() as u16
+    }
+
+    /// Moves the cursor, extending the selection if `select` is set and dropping it otherwise
+    pub fn move_cursor_selecting(&mut self, dx: i16, dy: i16, select: bool) {
+        self.last_edit = None;
+        if select {
+            if self.selection_anchor.is_none() {
+                self.selection_anchor = Some(self.cursor);
+            }
+        } else {
+            self.selection_anchor = None;
+        }
+        self.move_cursor(dx, dy);
+    }
+
+    pub fn move_cursor(&mut self, dx: i16, dy: i16) {
+        if dx < 0  {
+            if self.cursor.0 > 0 {
+                self.cursor.0 -= dx.unsigned_abs();
+            } else if self.cursor.1 > 0 {
+                self.cursor.1 -= 1;
+                self.cursor.0 = self.line_length();
+            }
+        } else if dx > 0 {
+            if self.cursor.0 < self.line_length() {
+                self.cursor.0 += dx as u16;
+            } else if self.cursor.1 < self.line_count() - 1 {
+                self.cursor.0 = 0;
+                self.cursor.1 += 1;
+            }
+        }
+
+        if dy < 0 && self.cursor.1 > 0 {
+            self.cursor.1 -= dy.unsigned_abs();
+            self.cursor.0 = self.cursor.0.min(self.line_length());
+        } else if dy > 0 && self.cursor.1 < self.line_count() - 1 {
+            self.cursor.1 += dy as u16;
+            self.cursor.0 = self.cursor.0.min(self.line_length());
+        }
+    }
+
+    pub fn add_line(&mut self) {
+        self.delete_selection();
+        if self.cursor.0 >= self.line_length() {
+            if self.cursor.1 >= self.line_count()-1 {
+                self.content.push(String::new());
+            } else {
+                self.content.insert(self.cursor.1 as usize + 1, String::new());
+            }
+        } else {
+            //Not at the end of a line, so we have to split the current line and move it the right side down
+            let left: String = self.content[self.cursor.1 as usize][..].graphemes(true).take(self.cursor.0 as usize).collect();
+            let right: String = self.content[self.cursor.1 as usize][..].graphemes(true).skip(self.cursor.0 as usize).collect();
+            self.content[self.cursor.1 as usize] = left;
+            self.content.insert(self.cursor.1 as usize + 1, right);
+        }
+        self.move_cursor(0, 1);
+        self.cursor.0 = 0;
+        self.is_dirty = true;
+    }
+
+    /// Enter with auto-indent: the new line gets the indentation of the current one, one level more after
+    /// a block opener like `{`, and `{|}` is split into three lines with the cursor on the indented middle one
+    pub fn smart_newline(&mut self, extension: &str, line_state: LexState) {
+        self.delete_selection();
+        let (x, y) = self.cursor;
+        let line = &self.content[y as usize];
+        let mut left: String = line.graphemes(true).take(x as usize).collect();
+        let right: String = line.graphemes(true).skip(x as usize).collect::<String>().trim_start().to_string();
+        let indent = indent::leading_whitespace(&left).to_string();
+        let opener = indent::opens_block(&left, extension, line_state);
+        let mut inner = indent.clone();
+        if opener.is_some() {
+            inner.push_str(indent::unit_for(&indent));
+        }
+        //Don't leave lines with nothing but indentation behind
+        if left.trim().is_empty() {
+            left.clear();
+        }
+
+        let closes = opener.and_then(indent::closing_bracket).is_some_and(|close| right.starts_with(close));
+        let lines = if closes {
+            vec![left, inner.clone(), indent + &right]
+        } else {
+            vec![left, inner.clone() + &right]
+        };
+        self.replace_lines(y as usize..y as usize + 1, lines);
+        self.set_cursor((inner.graphemes(true).count() as u16, y + 1));
+    }
+
+    pub fn char_before_cursor(&self) -> Option<char> {
+        let x = self.cursor.0.checked_sub(1)?;
+        self.content[self.cursor.1 as usize].graphemes(true).nth(x as usize)?.chars().next()
+    }
+
+    pub fn char_at_cursor(&self) -> Option<char> {
+        self.content[self.cursor.1 as usize].graphemes(true).nth(self.cursor.0 as usize)?.chars().next()
+    }
+
+    /// Lexer state at the start of line `y`, lexing from the top of the file
+    pub fn line_start_state(&self, y: u16) -> LexState {
+        let y = y as usize;
+        pairs::line_states(&self.content[..=y], self.extension())[y]
+    }
+
+    /// Lexer state right at the cursor, `line_state` being the state at the start of its line
+    pub fn lex_state_at_cursor(&self, line_state: LexState) -> LexState {
+        let (x, y) = self.cursor;
+        crate::style::lex_state_at(&self.content[y as usize], self.extension(), line_state, x as usize)
+    }
+
+    /// Types `c` with the auto-pairing rules, `state` being the lexer state at the cursor:
+    /// typing a closer in front of the same closer steps over it, an opener gets its closer
+    /// and a selection is wrapped in the pair. A closing bracket on an otherwise empty line
+    /// takes away one level of indentation first.
+    pub fn type_character(&mut self, c: char, state: LexState) {
+        let after = self.char_at_cursor();
+        if self.selection().is_none() && after == Some(c) {
+            let step_over = if pairs::is_quote(c) { state == LexState::String(c) } else { indent::is_closing_bracket(c) && state == LexState::Code };
+            if step_over {
+                self.cursor.0 += 1;
+                return;
+            }
+        }
+        if let Some(close) = pairs::closer(c) {
+            if let Some((start, end)) = self.selection() {
+                self.selection_anchor = None;
+                self.cursor = end;
+                self.add_character(close);
+                self.cursor = start;
+                self.add_character(c);
+                let end = if end.1 == start.1 { (end.0 + 1, end.1) } else { end };
+                self.set_selection((start.0 + 1, start.1), end);
+                return;
+            }
+            if pairs::should_pair(c, self.char_before_cursor(), after, state, self.extension()) {
+                self.add_character(c);
+                self.add_character(close);
+                self.cursor.0 -= 1;
+                return;
+            }
+        }
+
+        self.delete_selection();
+        let (x, y) = self.cursor;
+        let left: String = self.content[y as usize].graphemes(true).take(x as usize).collect();
+        if indent::is_closing_bracket(c) && !left.is_empty() && left.trim().is_empty() {
+            let dedented = indent::dedent(&left).to_string();
+            let right: String = self.content[y as usize].graphemes(true).skip(x as usize).collect();
+            self.cursor.0 = dedented.graphemes(true).count() as u16;
+            self.content[y as usize] = dedented + &right;
+        }
+        self.add_character(c);
+    }
+
+    /// Indents the selected lines by one level, keeping the selection on the same text
+    pub fn indent_lines(&mut self) {
+        let (first, last) = self.selected_lines();
+        if !self.begin_edit(None) {
+            return;
+        }
+        for y in first..=last {
+            let line = &mut self.content[y as usize];
+            if line.trim().is_empty() {
+                continue;
+            }
+            let unit = indent::unit_for(indent::leading_whitespace(line));
+            line.insert_str(0, unit);
+            self.shift_columns(y, unit.graphemes(true).count() as i32);
+        }
+        self.is_dirty = true;
+    }
+
+    /// Removes one level of indentation from the selected lines
+    pub fn dedent_lines(&mut self) {
+        let (first, last) = self.selected_lines();
+        if !self.begin_edit(None) {
+            return;
+        }
+        for y in first..=last {
+            let line = &self.content[y as usize];
+            let indent = indent::leading_whitespace(line);
+            let removed = indent.len() - indent::dedent(indent).len();
+            if removed == 0 {
+                continue;
+            }
+            let range = indent.len() - removed..indent.len();
+            self.content[y as usize].replace_range(range, "");
+            self.shift_columns(y, -(removed as i32));
+        }
+        self.is_dirty = true;
+    }
+
+    /// Moves the cursor and selection anchor on line `y` by `dx` columns, after text was added or removed at its start
+    fn shift_columns(&mut self, y: u16, dx: i32) {
+        let shift = |pos: (u16, u16)| if pos.1 == y && pos.0 > 0 { ((pos.0 as i32 + dx).max(0) as u16, y) } else { pos };
+        self.selection_anchor = self.selection_anchor.map(shift);
+        self.cursor = shift(self.cursor);
+    }
+
+    pub fn add_character(&mut self, c: char) {
+        self.delete_selection();
+        if self.cursor.0 >= self.line_length() {
+            self.content[self.cursor.1 as usize].push(c);
+        } else {
+            //We are not at the end of a line, so we have to split, push and merge
+            let mut left: String = self.content[self.cursor.1 as usize][..].graphemes(true).take(self.cursor.0 as usize).collect();
+            let right: String = self.content[self.cursor.1 as usize][..].graphemes(true).skip(self.cursor.0 as usize).collect();
+            left.push(c);
+            left.push_str(&right);
+            self.content[self.cursor.1 as usize] = left;
+        }
+        self.move_cursor(1, 0);
+        self.is_dirty = true;
+    }
+
+    /// Where moving one word from the cursor ends up, line breaks count as a word of their own
+    fn word_target(&self, forward: bool) -> (u16, u16) {
+        let (x, y) = self.cursor;
+        let line = &self.content[y as usize];
+        if forward {
+            if x >= self.line_length() && y + 1 < self.line_count() {
+                (0, y + 1)
+            } else {
+                (word::next_boundary(line, x as usize) as u16, y)
+            }
+        } else if x == 0 && y > 0 {
+            (self.content[y as usize - 1].graphemes(true).count() as u16, y - 1)
+        } else {
+            (word::prev_boundary(line, x as usize) as u16, y)
+        }
+    }
+
+    /// Moves the cursor by a word (ctrl + left/right), extending the selection if `select` is set
+    pub fn move_word_selecting(&mut self, forward: bool, select: bool) {
+        self.last_edit = None;
+        if select {
+            if self.selection_anchor.is_none() {
+                self.selection_anchor = Some(self.cursor);
+            }
+        } else {
+            self.selection_anchor = None;
+        }
+        self.cursor = self.word_target(forward);
+    }
+
+    /// Deletes the word in front of the cursor (ctrl + backspace), or the selection if there is one
+    pub fn remove_token(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        let target = self.word_target(false);
+        let cursor = self.cursor;
+        self.set_selection(target, cursor);
+        self.delete_selection();
+    }
+
+    /// Deletes the word behind the cursor (ctrl + delete), or the selection if there is one
+    pub fn delete_token(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        let target = self.word_target(true);
+        let cursor = self.cursor;
+        self.set_selection(cursor, target);
+        self.delete_selection();
+    }
+
+    /// Backspace between an empty pair like `(|)` removes both halves, returns false if there was no such pair
+    pub fn remove_pair(&mut self) -> bool {
+        if self.selection().is_some() {
+            return false;
+        }
+        match (self.char_before_cursor(), self.char_at_cursor()) {
+            (Some(open), Some(close)) if pairs::closer(open) == Some(close) => {
+                self.delete_character();
+                self.remove_character();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn remove_character(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        if self.cursor.0 == 0 {
+            if self.cursor.1 > 0 {
+                let cur = self.content[self.cursor.1 as usize].clone();
+                let next_x = self.content[self.cursor.1 as usize - 1].graphemes(true).count() as u16;
+                self.content[self.cursor.1 as usize - 1].push_str(&cur);
+                self.content.remove(self.cursor.1 as usize);
+                // self.move_cursor(0,-1);
+                self.cursor.1 -= 1;
+                self.cursor.0 = next_x;
+                self.is_dirty = true;
+            }
+        } else if self.cursor.0 > 0 {
+            //Not at the start of a line, so we have to remove characters
+            let mut left: String = self.content[self.cursor.1 as usize][..].graphemes(true).take(self.cursor.0 as usize - 1).collect();
+            let right: String = self.content[self.cursor.1 as usize][..].graphemes(true).skip(self.cursor.0 as usize).collect();
+            left.push_str(&right);
+            self.content[self.cursor.1 as usize] = left;
+            self.move_cursor(-1, 0);
+            self.is_dirty = true;
+        }
+    }
+
+    pub fn delete_character(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        if self.cursor.0 < self.line_length() {
+            self.move_cursor(1, 0);
+            self.remove_character();
+            // self.move_cursor(-1, 0);
+        }
+    }
+}
+
+/// Reads a file, along with a stamp of what we read
+fn read_bytes(path: &str) -> Result<(Vec<u8>, DiskStamp), std::io::Error> {
+    let modified = std::fs::metadata(path)?.modified().ok();
+    let bytes = std::fs::read(path)?;
+    let disk_stamp = DiskStamp::from_bytes(&bytes, modified);
+    Ok((bytes, disk_stamp))
+}
+
+/// Splits the content of a text file into lines
+fn text_lines(bytes: Vec<u8>) -> Result<Vec<String>, std::io::Error> {
+    let text = String::from_utf8(bytes).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    let mut content: Vec<String> = text.lines().map(|s| s.to_string()).collect();
+    if content.is_empty() {
+        content.push(String::new());
+    }
+    Ok(content)
+}
+
+/// Reads a text file as lines, along with a stamp of what we read
+fn read_lines(path: &str) -> Result<(Vec<String>, DiskStamp), std::io::Error> {
+    let (bytes, disk_stamp) = read_bytes(path)?;
+    Ok((text_lines(bytes)?, disk_stamp))
+}
+
+fn remove_swap(swap: Option<PathBuf>) {
+    if let Some(swap) = swap {
+        let _ = std::fs::remove_file(swap);
+    }
+}
+
+pub struct Editor {
+    pub open_files: Vec<File>,
+    pub cur_file_idx: usize,
+
+    pub styled_text: Text<'static>,
+    /// Line numbers, drawn separately so they stay put when scrolling sideways
+    pub styled_gutter: Text<'static>,
+
+    last_swap: Instant,
+    last_disk_check: Instant,
+    /// Increases every time a different file becomes the current one
+    focus_counter: u64,
+    /// Files gone back through with repeated alt + `, the order of recent files stays put until another key is pressed
+    recent_walk: Option<usize>,
+    /// Number of columns and lines of text that fit on screen
+    view_width: u16,
+    view_height: u16,
+    /// Time of the last key press, for autosaving on idle
+    last_input: Instant,
+    /// Set on input and cleared once we tried to autosave, so a failing save isn't retried in a loop
+    autosave_pending: bool,
+    /// Vim style editing state, `None` unless `modal` is set in the config
+    modal: Option<Modal>,
+    /// Lexer state at the start of each line of the current file, as of the last restyle
+    line_states: Vec<LexState>,
+    /// Bracket at the cursor and the one matching it, highlighted in the text
+    bracket_match: Option<((u16, u16), (u16, u16))>,
+    /// Searches that selected matches, the most recent last
+    pub search_history: Vec<String>,
+    /// Running language servers by their command, shared by all files they handle
+    lsp_clients: HashMap<String, lsp::Client>,
+    /// Language server commands that failed to start or exited, so they aren't restarted on every edit
+    lsp_failed: HashSet<String>,
+    /// URIs of the paths of open files, made once instead of on every sync
+    lsp_uris: HashMap<String, String>,
+    /// Completion dropdown at the cursor, with the index of the file it was opened in
+    completion: Option<(usize, Completion)>,
+    /// Branch and commit checked out in the working directory, if it is in a git repository
+    git_head: Option<(String, String)>,
+    last_git_check: Option<Instant>,
+    /// Blame shown after a line, as (file index, line, text), until the cursor leaves the line
+    inline_blame: Option<(usize, u16, String)>,
+    /// Diff shown instead of the text while it is open
+    pub diff_view: Option<DiffView>,
+    /// Set once the user chose to quit, for the main loop to pick up
+    quit_requested: bool,
+    /// Shell running in a pane below the text
+    pub terminal: Option<TerminalPane>,
+    /// Keys go to the terminal pane instead of the text while set
+    pub terminal_focused: bool,
+    /// Message for the footer and when it was set
+    status: Option<(String, Instant)>,
+    /// Set once the user chose to save the current file with `sudo_command`, for the main loop to pick up,
+    /// as the terminal has to be handed over in case it asks for a password
+    sudo_save_requested: bool,
+}
+
+impl Editor {
+    pub fn from_paths(paths: Vec<&str>) -> Result<Self, std::io::Error> {
+        let mut files = Vec::new();
+        for path in paths {
+            files.push(File::from_path(path)?);
+        }
+        Ok(Self::from_files(files))
+    }
+
+    /// Editor with the given files open, or a single new file if there are none
+    pub fn from_files(mut files: Vec<File>) -> Self {
+        if files.is_empty() {
+            files.push(File::new());
+        }
+        let mut obj = Self {
+            open_files: files,
+            cur_file_idx: 0,
+
+            styled_text: Text::default(),
+            styled_gutter: Text::default(),
+
+            last_swap: Instant::now(),
+            last_disk_check: Instant::now(),
+            focus_counter: 0,
+            recent_walk: None,
+            view_width: 0,
+            view_height: 0,
+            last_input: Instant::now(),
+            autosave_pending: false,
+            modal: if CONFIG.modal { Some(Modal::new()) } else { None },
+            line_states: Vec::new(),
+            bracket_match: None,
+            search_history: Vec::new(),
+            lsp_clients: HashMap::new(),
+            lsp_failed: HashSet::new(),
+            lsp_uris: HashMap::new(),
+            completion: None,
+            git_head: None,
+            last_git_check: None,
+            inline_blame: None,
+            diff_view: None,
+            quit_requested: false,
+            terminal: None,
+            terminal_focused: false,
+            status: None,
+            sudo_save_requested: false,
+        };
+        obj.update_styled_text();
+        obj
+    }
+
+    /// Switches to a different set of open files, refusing to throw away unsaved changes
+    pub fn replace_files(&mut self, other: Editor) -> Result<(), std::io::Error> {
+        if self.open_files.iter().any(File::is_dirty) {
+            return Err(std::io::Error::other("Save or close files with unsaved changes first!"));
+        }
+        self.remove_swap_files();
+        self.open_files = other.open_files;
+        self.cur_file_idx = other.cur_file_idx;
+        self.search_history = other.search_history;
+        self.update_styled_text();
+        Ok(())
+    }
+
+    pub fn save_file(&mut self) -> Result<(), std::io::Error> {
+        self.open_files[self.cur_file_idx].check_writable()?;
+        //The language server's edits come in later, the file is saved once they are applied
+        if CONFIG.format_on_save && CONFIG.formatter(self.extension()) == Some(format::LSP) && self.lsp_format(true).is_ok() {
+            return Ok(());
+        }
+        let swap = self.swap_path(self.cur_file_idx);
+        let result = self.open_files[self.cur_file_idx].save();
+        //Saving went through even if formatting failed
+        if !self.open_files[self.cur_file_idx].is_dirty() {
+            remove_swap(swap);
+        }
+        self.update_styled_text();
+        result
+    }
+
+    /// Formats the current file with the formatter configured for its extension
+    pub fn format_buffer(&mut self) -> Result<(), std::io::Error> {
+        let command = CONFIG.formatter(self.extension())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "No formatter is configured for this kind of file!"))?;
+        self.open_files[self.cur_file_idx].check_writable()?;
+        if command == format::LSP {
+            return self.lsp_format(false);
+        }
+        self.open_files[self.cur_file_idx].format_with(command)?;
+        self.update_styled_text();
+        Ok(())
+    }
+
+    pub fn changed_on_disk(&self) -> bool {
+        self.open_files[self.cur_file_idx].changed_on_disk()
+    }
+
+    pub fn save_file_to_path(&mut self, path: String) -> Result<(), std::io::Error> {
+        let swap = self.swap_path(self.cur_file_idx);
+        self.open_files[self.cur_file_idx].save_file_to_path(path)?;
+        remove_swap(swap);
+        Ok(())
+    }
+
+    fn swap_path(&self, idx: usize) -> Option<PathBuf> {
+        match self.open_files[idx].path() {
+            Some(path) => fileio::swap_path(Path::new(path)).ok(),
+            None => fileio::unnamed_swap_path(idx),
+        }
+    }
+
+    /// Periodically writes swap files for all dirty buffers
+    pub fn update_swap_files(&mut self) {
+        if !CONFIG.swap || self.last_swap.elapsed() < Duration::from_secs(CONFIG.swap_interval) {
+            return;
+        }
+        self.last_swap = Instant::now();
+        //A failed swap write shouldn't interrupt editing, the next attempt might work
+        let _ = self.write_swap_files();
+    }
+
+    /// Writes a swap file for every dirty buffer right away, returning where they were written
+    pub fn write_swap_files(&mut self) -> Vec<PathBuf> {
+        let mut written = Vec::new();
+        for i in 0..self.open_files.len() {
+            if !self.open_files[i].is_dirty() { continue; }
+            if let Some(swap) = self.swap_path(i) {
+                if self.open_files[i].write_swap(&swap).is_ok() {
+                    written.push(swap);
+                }
+            }
+        }
+        written
+    }
+
+    /// Removes the swap files of all buffers, used when quitting normally
+    pub fn remove_swap_files(&mut self) {
+        for i in 0..self.open_files.len() {
+            if self.open_files[i].swap_hash.is_some() {
+                remove_swap(self.swap_path(i));
+            }
+        }
+    }
+
+    /// Paths of open files that have a swap file newer than the file itself, left behind by a crash
+    pub fn files_with_newer_swap(&self) -> Vec<String> {
+        self.open_files.iter().filter_map(File::path).filter(|path| {
+            match fileio::swap_path(Path::new(path)) {
+                Ok(swap) => fileio::swap_is_newer(&swap, Path::new(path)),
+                Err(_) => false,
+            }
+        }).cloned().collect()
+    }
+
+    pub fn recover_swap(&mut self, path: &str) -> Result<(), std::io::Error> {
+        let swap = fileio::swap_path(Path::new(path))?;
+        self.file_by_path(path)?.recover_from(&swap)?;
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Looks for open files that were changed by another program.
+    /// Clean buffers are reloaded right away, the paths of dirty ones are returned so the user can decide.
+    pub fn check_disk_changes(&mut self) -> Vec<String> {
+        if self.last_disk_check.elapsed() < Duration::from_millis(500) {
+            return Vec::new();
+        }
+        self.last_disk_check = Instant::now();
+
+        let mut conflicts = Vec::new();
+        let mut reloaded = false;
+        for file in self.open_files.iter_mut() {
+            if file.disk_conflict || !file.poll_disk_change() { continue; }
+            if file.is_dirty() {
+                file.disk_conflict = true;
+                conflicts.extend(file.path().cloned());
+            } else if file.reload().is_ok() {
+                reloaded = true;
+            }
+        }
+        if reloaded {
+            self.update_styled_text();
+        }
+        conflicts
+    }
+
+    /// Looks up the checked out branch every few seconds. When HEAD moved, like after a commit,
+    /// the HEAD versions of the open files are read again so their change markers are right.
+    pub fn check_git(&mut self) {
+        if self.last_git_check.is_some_and(|time| time.elapsed() < GIT_CHECK_INTERVAL) {
+            return;
+        }
+        self.last_git_check = Some(Instant::now());
+        let head = git::head(Path::new("."));
+        let moved = head.as_ref().map(|(_, commit)| commit) != self.git_head.as_ref().map(|(_, commit)| commit);
+        if moved && self.git_head.is_some() {
+            self.open_files.iter_mut().for_each(File::refresh_git_head);
+            self.update_styled_text();
+        }
+        self.git_head = head;
+    }
+
+    /// Branch checked out in the working directory, for the footer
+    pub fn git_branch(&self) -> Option<&str> {
+        self.git_head.as_ref().map(|(branch, _)| branch.as_str())
+    }
+
+    /// Shows who last changed the cursor line at its end
+    pub fn blame_line(&mut self) -> Result<(), std::io::Error> {
+        let file = &self.open_files[self.cur_file_idx];
+        let path = file.path().ok_or_else(|| std::io::Error::other("Save the file first, only files can be blamed!"))?;
+        let y = file.cursor().1;
+        self.inline_blame = Some((self.cur_file_idx, y, git::blame(path, file.content(), y as usize)?));
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Undoes the change at the cursor compared to git's HEAD
+    pub fn revert_hunk(&mut self) -> Result<(), std::io::Error> {
+        self.open_files[self.cur_file_idx].revert_hunk()?;
+        self.update_styled_text();
+        Ok(())
+    }
+
+    fn file_by_path(&mut self, path: &str) -> Result<&mut File, std::io::Error> {
+        self.open_files.iter_mut().find(|file| file.path().map(String::as_str) == Some(path))
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not open!", path)))
+    }
+
+    /// Throws away our changes to `path` in favour of the version on disk
+    pub fn reload_file(&mut self, path: &str) -> Result<(), std::io::Error> {
+        self.file_by_path(path)?.reload()?;
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Keeps our changes to `path`, the next save overwrites the version on disk
+    pub fn keep_buffer(&mut self, path: &str) -> Result<(), std::io::Error> {
+        self.file_by_path(path)?.acknowledge_disk_change();
+        Ok(())
+    }
+
+    /// Opens `view` unless there is nothing in it. `return_to` is shown again when it is closed.
+    fn open_diff_view(&mut self, mut view: DiffView, return_to: Option<PopupKind>) -> Result<(), std::io::Error> {
+        if view.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No differences!"));
+        }
+        view.return_to = return_to;
+        view.set_height(self.view_height);
+        self.diff_view = Some(view);
+        Ok(())
+    }
+
+    /// Closes the diff view, returning the popup it was opened from
+    pub fn close_diff_view(&mut self) -> Option<PopupKind> {
+        self.diff_view.take().and_then(|view| view.return_to)
+    }
+
+    /// Shows how the file at `path` differs from its version on disk
+    pub fn show_disk_diff(&mut self, path: &str, return_to: Option<PopupKind>) -> Result<(), std::io::Error> {
+        let file = self.file_by_path(path)?;
+        let section = (path.to_string(), file.disk_lines()?, file.content().clone());
+        self.open_diff_view(DiffView::new("disk -> buffer", vec![section]), return_to)
+    }
+
+    /// Compares the current file with `against`: `disk` (the default), `head` for git's HEAD commit
+    /// or the tab name of another open file
+    pub fn show_diff(&mut self, against: Option<&str>) -> Result<(), std::io::Error> {
+        let file = &self.open_files[self.cur_file_idx];
+        let name = self.all_filenames()[self.cur_file_idx].clone();
+        let (title, old) = match against {
+            None | Some("disk") => ("disk -> buffer".to_string(), file.disk_lines()?),
+            Some("head") => {
+                let head = file.git_head().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "The file isn't committed to a git repository!"))?;
+                ("HEAD -> buffer".to_string(), head.clone())
+            },
+            Some(other) => {
+                let idx = self.all_filenames().iter().position(|tab| tab == other)
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not open!", other)))?;
+                (format!("{} -> {}", other, name), self.open_files[idx].content().clone())
+            },
+        };
+        let section = (name, old, file.content().clone());
+        self.open_diff_view(DiffView::new(&title, vec![section]), None)
+    }
+
+    /// Number of open files with unsaved changes
+    pub fn dirty_count(&self) -> usize {
+        self.open_files.iter().filter(|file| file.is_dirty()).count()
+    }
+
+    /// Shows the unsaved changes of every open file
+    pub fn show_unsaved_changes(&mut self, return_to: Option<PopupKind>) -> Result<(), std::io::Error> {
+        let names = self.all_filenames();
+        let mut sections = Vec::new();
+        for (file, name) in self.open_files.iter().zip(names) {
+            if file.is_dirty() {
+                sections.push((name, file.disk_lines()?, file.content().clone()));
+            }
+        }
+        self.open_diff_view(DiffView::new("unsaved changes", sections), return_to)
+    }
+
+    /// Opens the terminal pane and focuses it, or moves the focus between it and the text if it is open already
+    pub fn toggle_terminal(&mut self) -> Result<(), std::io::Error> {
+        if self.terminal.is_some() {
+            self.terminal_focused = !self.terminal_focused;
+            return Ok(());
+        }
+        //Sized properly by the next `set_view_size`
+        self.terminal = Some(TerminalPane::new(1, self.view_width.max(1))?);
+        self.terminal_focused = true;
+        Ok(())
+    }
+
+    /// Closes the terminal pane once its shell has exited
+    pub fn check_terminal(&mut self) {
+        if let Some(pane) = &mut self.terminal {
+            if !pane.is_alive() {
+                self.terminal = None;
+                self.terminal_focused = false;
+            }
+        }
+    }
+
+    /// Shows `message` in the footer for a few seconds
+    pub fn set_status(&mut self, message: &str) {
+        self.status = Some((message.to_string(), Instant::now()));
+    }
+
+    pub fn status_message(&self) -> Option<&str> {
+        self.status.as_ref().filter(|(_, time)| time.elapsed() < STATUS_DURATION).map(|(message, _)| message.as_str())
+    }
+
+    /// Allows or blocks edits of the current file
+    pub fn toggle_read_only(&mut self) {
+        let file = &mut self.open_files[self.cur_file_idx];
+        file.set_read_only(!file.is_read_only());
+        let message = if file.is_read_only() { "read-only" } else { "edits allowed" };
+        self.set_status(message);
+    }
+
+    /// Blocks edits of every open file, for `--readonly`
+    pub fn set_all_read_only(&mut self) {
+        for file in &mut self.open_files {
+            file.set_read_only(true);
+        }
+    }
+
+    /// Which open files are read-only, for marking their tabs
+    pub fn read_only_files(&self) -> Vec<bool> {
+        self.open_files.iter().map(File::is_read_only).collect()
+    }
+
+    /// Asks the main loop to save the current file with `sudo_command`
+    pub fn request_sudo_save(&mut self) {
+        self.sudo_save_requested = true;
+    }
+
+    /// Returns whether a sudo save was requested, clearing the request
+    pub fn take_sudo_save_request(&mut self) -> bool {
+        std::mem::take(&mut self.sudo_save_requested)
+    }
+
+    /// Saves the current file with `sudo_command`, which may ask for a password on the terminal
+    pub fn save_with_sudo(&mut self) -> Result<(), std::io::Error> {
+        let swap = self.swap_path(self.cur_file_idx);
+        self.open_files[self.cur_file_idx].save_with_sudo()?;
+        remove_swap(swap);
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Makes the main loop exit, even with unsaved changes
+    pub fn request_quit(&mut self) {
+        self.quit_requested = true;
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    pub fn discard_swap(&mut self, path: &str) -> Result<(), std::io::Error> {
+        std::fs::remove_file(fileio::swap_path(Path::new(path))?)
+    }
+
+    pub fn load_file_from_path(&mut self, path: String) -> Result<(), std::io::Error> {
+        let mut is_open = false;
+        let mut open_idx = 0;
+        'search: for (i, file) in self.open_files.iter().enumerate() {
+            if file.path == Some(path.clone()) {
+                open_idx = i;
+                is_open = true;
+                break 'search;
+            }
+        }
+        if !is_open {
+            self.open_files.push(File::from_path(&path)?);
+        } else {
+            self.cur_file_idx = open_idx;
+        }
+
+        self.update_styled_text();
+
+        Ok(())
+    }
+
+    pub fn update_styled_text(&mut self) {
+        if self.open_files[self.cur_file_idx].take_blocked_edit() {
+            self.set_status(self.read_only_message());
+        }
+        let (extension, height) = (self.extension().to_string(), self.view_height as usize);
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            //Read again on every restyle, which also picks up lines added to the end
+            large.load_view(height);
+            let (gutter, text) = large.render(&extension);
+            self.styled_gutter = gutter;
+            self.styled_text = text;
+            self.line_states.clear();
+            self.bracket_match = None;
+            return;
+        }
+        if let Some(hex) = self.open_files[self.cur_file_idx].hex() {
+            //Only the rows on screen, binary files can be big
+            let (gutter, text) = hex.render(self.view_height as usize);
+            self.styled_gutter = gutter;
+            self.styled_text = text;
+            self.line_states.clear();
+            self.bracket_match = None;
+            return;
+        }
+        let git_changes = self.open_files[self.cur_file_idx].git_changes();
+        let mut content_spans = Vec::new();
+        let mut gutter_spans = Vec::new();
+        let lines = self.content();
+        let max_nums = (lines.len().max(1)-1).to_string().chars().count();
+        let file = &self.open_files[self.cur_file_idx];
+        let selections: Vec<_> = self.selection().into_iter().chain(file.extra_carets().iter().filter_map(Caret::selection)).collect();
+        let diagnostics = self.diagnostics();
+        let mut line_severity: HashMap<u32, lsp::Severity> = HashMap::new();
+        for diagnostic in diagnostics {
+            let severity = line_severity.entry(diagnostic.start.0).or_insert(diagnostic.severity);
+            *severity = (*severity).min(diagnostic.severity);
+        }
+        let mut line_states = Vec::with_capacity(lines.len());
+        let mut state = LexState::Code;
+        for (i, line) in lines.iter().enumerate() {
+            let line_num = format!("{:width$}", i, width = max_nums);
+            let line = line.replace('\t', &" ".repeat(util::TAB_WIDTH));
+            let styled_line_num = match line_severity.get(&(i as u32)) {
+                Some(&severity) => tui::text::Span::styled(line_num, crate::style::diagnostic_gutter_style(severity)),
+                None => crate::style::editor_style(line_num),
+            };
+            //Lines that differ from git's HEAD get a marker in place of the `~` after the number
+            let marker = match git_changes.get(i).copied().flatten() {
+                Some(change) => tui::text::Span::styled(format!("{} ", crate::style::git_marker(change)), crate::style::git_marker_style(change)),
+                None => crate::style::editor_style("~ ".to_string()),
+            };
+            line_states.push(state);
+            let (mut styled_line, next_state) = crate::style::style_line(line, self.extension(), state);
+            state = next_state;
+            for &(start, end) in &selections {
+                let i = i as u16;
+                if i >= start.1 && i <= end.1 {
+                    let line = &lines[i as usize];
+                    let from = if i == start.1 { util::display_column(line, start.0 as usize) } else { 0 };
+                    //Lines that continue the selection get one extra cell, to show the line break is selected
+                    let to = if i == end.1 { util::display_column(line, end.0 as usize) } else { util::display_column(line, usize::MAX) + 1 };
+                    crate::style::patch_style(&mut styled_line, from..to, crate::style::selection_style());
+                }
+            }
+            gutter_spans.push(Spans::from(vec![styled_line_num, marker]));
+            content_spans.push(styled_line);
+        }
+        for diagnostic in diagnostics {
+            let (start, end) = (lsp::from_lsp_pos(lines, diagnostic.start), lsp::from_lsp_pos(lines, diagnostic.end));
+            for y in start.1..=end.1 {
+                let line = &lines[y as usize];
+                let from = if y == start.1 { util::display_column(line, start.0 as usize) } else { 0 };
+                let to = if y == end.1 { util::display_column(line, end.0 as usize) } else { util::display_column(line, usize::MAX) };
+                //Empty ranges still get a cell, so the spot is visible
+                crate::style::patch_style(&mut content_spans[y as usize], from..to.max(from + 1), crate::style::diagnostic_style(diagnostic.severity));
+            }
+        }
+        self.line_states = line_states;
+        match &self.inline_blame {
+            Some((idx, y, text)) if *idx == self.cur_file_idx && *y == self.file_cursor().1 && (*y as usize) < content_spans.len() => {
+                content_spans[*y as usize].0.push(tui::text::Span::styled(format!("    {}", text), crate::style::blame_style()));
+            },
+            _ => self.inline_blame = None,
+        }
+        //The terminal only shows the main cursor, the others are drawn as part of the text
+        for caret in self.open_files[self.cur_file_idx].extra_carets() {
+            let (x, y) = caret.cursor;
+            if y as usize >= content_spans.len() {
+                continue;
+            }
+            let x = util::display_column(&self.open_files[self.cur_file_idx].content()[y as usize], x as usize);
+            crate::style::patch_style(&mut content_spans[y as usize], x..x + 1, crate::style::extra_cursor_style());
+        }
+        self.bracket_match = self.find_bracket_match();
+        if let Some((a, b)) = self.bracket_match {
+            for (x, y) in [a, b] {
+                let x = util::display_column(&self.content()[y as usize], x as usize);
+                crate::style::patch_style(&mut content_spans[y as usize], x..x + 1, crate::style::bracket_match_style());
+            }
+        }
+        self.styled_text = Text::from(content_spans);
+        self.styled_gutter = Text::from(gutter_spans);
+    }
+
+    fn find_bracket_match(&self) -> Option<((u16, u16), (u16, u16))> {
+        let file = &self.open_files[self.cur_file_idx];
+        pairs::matching_bracket(file.content(), file.extension(), &self.line_states, file.cursor())
+    }
+
+    /// Moves the cursor to the bracket matching the one at the cursor
+    pub fn jump_to_matching_bracket(&mut self) {
+        if let Some((_, target)) = self.find_bracket_match() {
+            self.clear_extra_cursors();
+            let file = &mut self.open_files[self.cur_file_idx];
+            file.clear_selection();
+            file.set_cursor(target);
+            self.update_styled_text();
+        }
+    }
+
+    /// Runs `edit` at every cursor of the current file and restyles, passing the lexer state at the start of the cursor line.
+    /// With several cursors, edits at the earlier ones move lines around, so the states are worked out again instead of cached.
+    fn edit_at_cursors(&mut self, mut edit: impl FnMut(&mut File, LexState)) {
+        let file = &mut self.open_files[self.cur_file_idx];
+        if file.cursor_count() > 1 {
+            file.for_each_cursor(|file, _| {
+                let state = file.line_start_state(file.cursor().1);
+                edit(file, state);
+            });
+        } else {
+            let state = self.line_states.get(file.cursor().1 as usize).copied().unwrap_or(LexState::Code);
+            edit(file, state);
+        }
+        self.update_styled_text();
+    }
+
+    /// Restyles after the cursor moved, if that changed the selection or the highlighted brackets
+    fn restyle_after_move(&mut self, had_selection: bool) {
+        if had_selection || self.inline_blame.is_some() || self.open_files[self.cur_file_idx].selection().is_some() || self.find_bracket_match() != self.bracket_match {
+            self.update_styled_text();
+        }
+    }
+
+    /// Selected range to highlight, in visual mode that includes the character under the cursor
+    fn selection(&self) -> Option<((u16, u16), (u16, u16))> {
+        let file = &self.open_files[self.cur_file_idx];
+        match &self.modal {
+            Some(modal) if modal.mode() != Mode::Normal && modal.mode() != Mode::Insert => modal.visual_range(file),
+            _ => file.selection(),
+        }
+    }
+
+    /// Mode and pending keys of modal editing for the footer, `None` when it is disabled
+    pub fn mode_status(&self) -> Option<String> {
+        if self.open_files[self.cur_file_idx].hex().is_some() {
+            return Some("hex".to_string());
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large() {
+            return Some(large.status());
+        }
+        self.modal.as_ref().map(Modal::status)
+    }
+
+    /// Width of the line number gutter in front of the text
+    pub fn gutter_width(&self) -> u16 {
+        if self.open_files[self.cur_file_idx].hex().is_some() {
+            return hex::GUTTER_WIDTH;
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large() {
+            return large.gutter_width();
+        }
+        (self.content().len().max(1)-1).to_string().chars().count() as u16 + 2
+    }
+
+    /// Names to show in the tabs, one per open file.
+    /// Files with the same name get as many parent directories as needed to tell them apart,
+    /// so `src/mod.rs` and `ui/mod.rs` don't both show up as `mod.rs`.
+    pub fn all_filenames(&self) -> Vec<String> {
+        let components: Vec<Vec<String>> = self.open_files.iter().map(|f| match f.path() {
+            Some(path) => Path::new(path).components().rev().map(|c| c.as_os_str().to_string_lossy().to_string()).collect(),
+            None => vec!["unsaved".to_string()],
+        }).collect();
+        let name = |i: usize, depth: usize| components[i][..depth.min(components[i].len())].iter().rev().cloned().collect::<Vec<String>>().join("/");
+
+        let mut depths = vec![1; components.len()];
+        loop {
+            let names: Vec<String> = (0..components.len()).map(|i| name(i, depths[i])).collect();
+            let mut changed = false;
+            for i in 0..components.len() {
+                let clashes = names.iter().enumerate().any(|(j, other)| j != i && *other == names[i]);
+                if clashes && depths[i] < components[i].len() {
+                    depths[i] += 1;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return names;
+            }
+        }
+    }
+
+    pub fn all_filenames_modified(&self) -> impl Iterator<Item = String> + '_ {
+        self.all_filenames().into_iter().enumerate().map(move |(i, s)| {
+            if self.open_files[i].is_dirty() {
+                format!("*{}", s)
+            } else {
+                s
+            }
+        })
+    }
+
+    pub fn path(&self) -> Option<&String> {
+        self.open_files[self.cur_file_idx].path()
+    }
+
+    pub fn extension(&self) -> &str {
+        self.open_files[self.cur_file_idx].extension()
+    }
+
+    pub fn content(&self) -> &Vec<String> {
+        self.open_files[self.cur_file_idx].content()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.open_files[self.cur_file_idx].is_dirty()
+    }
+
+    pub fn seconds_since_save(&self) -> Option<u64> {
+        Some(self.open_files[self.cur_file_idx].saved_time()?.elapsed().as_secs())
+    }
+
+    /// Cursor position inside the text view, `None` if it is scrolled out of view
+    pub fn cursor(&self) -> Option<(u16, u16)> {
+        if let Some(hex) = self.open_files[self.cur_file_idx].hex() {
+            return hex.screen_cursor(self.view_height as usize);
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large() {
+            return large.screen_cursor(self.view_width as usize, self.view_height as usize);
+        }
+        let (x, y) = self.open_files[self.cur_file_idx].cursor_unscrolled()?;
+        if x >= self.view_width || y >= self.view_height {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    /// Cursor position for the footer, the byte offset in a binary file
+    pub fn position_text(&self) -> String {
+        let file = &self.open_files[self.cur_file_idx];
+        if let Some(hex) = file.hex() {
+            return hex.position();
+        }
+        if let Some(large) = file.large() {
+            return large.position();
+        }
+        format!("{}:{}", file.cursor().0, file.cursor().1)
+    }
+
+    /// Cursor position in the file
+    pub fn file_cursor(&self) -> (u16, u16) {
+        self.open_files[self.cur_file_idx].cursor()
+    }
+
+    pub fn scroll(&self) -> (u16, u16) {
+        //Binary and large files only render the rows on screen, they are never scrolled down when drawn
+        if self.open_files[self.cur_file_idx].hex().is_some() {
+            return (0, 0);
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large() {
+            return (large.scroll_x(), 0);
+        }
+        self.open_files[self.cur_file_idx].scroll()
+    }
+
+    /// Tells the editor how much room the text view has, including the gutter
+    pub fn set_view_size(&mut self, width: u16, height: u16) {
+        //The terminal pane takes the bottom of the text area, one row of it for its header
+        let height = match &mut self.terminal {
+            Some(pane) => {
+                let pane_height = terminal::pane_height(height);
+                if pane_height >= 2 {
+                    pane.resize(pane_height - 1, width);
+                }
+                height.saturating_sub(pane_height)
+            },
+            None => height,
+        };
+        self.view_width = width.saturating_sub(self.gutter_width());
+        let resized = self.view_height != height;
+        self.view_height = height;
+        if let Some(view) = &mut self.diff_view {
+            view.set_height(height);
+        }
+        if resized && (self.open_files[self.cur_file_idx].hex().is_some() || self.open_files[self.cur_file_idx].large().is_some()) {
+            self.update_styled_text();
+        }
+    }
+
+    pub fn scroll_to_cursor(&mut self) {
+        let (width, height) = (self.view_width, self.view_height);
+        if let Some(hex) = self.open_files[self.cur_file_idx].hex_mut() {
+            hex.scroll_to_cursor(height as usize);
+            self.update_styled_text();
+            return;
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            large.scroll_to_cursor(width as usize, height as usize);
+            self.update_styled_text();
+            return;
+        }
+        self.open_files[self.cur_file_idx].scroll_to_cursor(width, height);
+    }
+
+    pub fn scroll_by(&mut self, dy: i16) {
+        if let Some(hex) = self.open_files[self.cur_file_idx].hex_mut() {
+            hex.scroll_by(dy as isize);
+            self.update_styled_text();
+            return;
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            large.scroll_by(dy as isize);
+            self.update_styled_text();
+            return;
+        }
+        self.open_files[self.cur_file_idx].scroll_by(dy);
+    }
+
+    /// Converts a position inside the text view (including the gutter) to a position in the file
+    pub fn view_to_file(&self, col: u16, row: u16) -> (u16, u16) {
+        let scroll = self.scroll();
+        let (column, y) = ((col.saturating_sub(self.gutter_width()) + scroll.0) as usize, row + scroll.1);
+        let x = self.content().get(y as usize).map_or(column, |line| util::grapheme_at_column(line, column));
+        (x as u16, y)
+    }
+
+    /// Handles a click in the text view, `clicks` being 1 for a single click, 2 for a double click and so on
+    pub fn click(&mut self, col: u16, row: u16, clicks: u8) {
+        let gutter_width = self.gutter_width();
+        if let Some(hex) = self.open_files[self.cur_file_idx].hex_mut() {
+            hex.click(col.saturating_sub(gutter_width), row);
+            self.update_styled_text();
+            return;
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            large.click(col.saturating_sub(gutter_width), row);
+            self.update_styled_text();
+            return;
+        }
+        let pos = self.view_to_file(col, row);
+        let file = &mut self.open_files[self.cur_file_idx];
+        match clicks {
+            1 => {
+                file.clear_extra_carets();
+                file.clear_selection();
+                file.end_edit_group();
+                file.set_cursor(pos);
+            },
+            2 => file.select_word_at(pos),
+            _ => file.select_line(pos.1),
+        }
+        self.update_styled_text();
+    }
+
+    /// Extends the selection to a position in the text view while dragging the mouse
+    pub fn drag(&mut self, col: u16, row: u16) {
+        if self.open_files[self.cur_file_idx].hex().is_some() || self.open_files[self.cur_file_idx].large().is_some() {
+            return;
+        }
+        let pos = self.view_to_file(col, row);
+        let file = &mut self.open_files[self.cur_file_idx];
+        let anchor = file.selection_anchor.unwrap_or(file.cursor);
+        file.set_selection(anchor, pos);
+        self.update_styled_text();
+    }
+
+    fn autosave_idx(&mut self, idx: usize) {
+        let swap = self.swap_path(idx);
+        //Autosave is best effort, a failure just leaves the file dirty
+        if let Ok(true) = self.open_files[idx].autosave() {
+            remove_swap(swap);
+        }
+    }
+
+    /// Autosaves the current file, used on tab switches
+    pub fn autosave_file(&mut self) {
+        if CONFIG.autosave {
+            self.autosave_idx(self.cur_file_idx);
+        }
+    }
+
+    /// Autosaves every open file, used on idle and when the terminal loses focus
+    pub fn autosave_all(&mut self) {
+        if CONFIG.autosave {
+            for i in 0..self.open_files.len() {
+                self.autosave_idx(i);
+            }
+        }
+    }
+
+    /// Autosaves every open file once there was no input for `autosave_idle` seconds
+    pub fn autosave_if_idle(&mut self) {
+        if !self.autosave_pending || CONFIG.autosave_idle == 0 || self.last_input.elapsed() < Duration::from_secs(CONFIG.autosave_idle) {
+            return;
+        }
+        self.autosave_pending = false;
+        self.autosave_all();
+    }
+
+    /// Remembers when the current file was last looked at, for switching to the most recently used file
+    pub fn track_focus(&mut self) {
+        if self.recent_walk.is_some() {
+            return;
+        }
+        let last_focus = self.open_files[self.cur_file_idx].last_focus;
+        //Zero means the file was never focused, focused files get numbers from one up
+        if last_focus == 0 || last_focus != self.focus_counter {
+            self.focus_counter += 1;
+            self.open_files[self.cur_file_idx].last_focus = self.focus_counter;
+        }
+    }
+
+    /// Switches to the most recently used file other than the current one.
+    /// Pressed again right away it goes on to the file used before that, like alt + tab does with windows.
+    pub fn switch_to_recent_file(&mut self) {
+        let mut recent: Vec<usize> = (0..self.open_files.len()).collect();
+        recent.sort_by_key(|&i| std::cmp::Reverse(self.open_files[i].last_focus));
+        let step = self.recent_walk.map_or(1, |step| step + 1) % recent.len();
+        self.switch_to_file(recent[step]);
+        self.recent_walk = Some(step);
+    }
+
+    /// Ends going back through the recent files, the file we ended up in becomes the most recent one
+    pub fn end_recent_walk(&mut self) {
+        self.recent_walk = None;
+    }
+
+    /// Moves the current tab `offset` places to the left or right
+    pub fn move_tab(&mut self, offset: isize) {
+        let target = self.cur_file_idx as isize + offset;
+        if target < 0 || target >= self.open_files.len() as isize { return; }
+        let file = self.open_files.remove(self.cur_file_idx);
+        self.cur_file_idx = target as usize;
+        self.open_files.insert(self.cur_file_idx, file);
+    }
+
+    /// Switches to the file at `idx`, as if switching tabs
+    pub fn switch_to_file(&mut self, idx: usize) {
+        self.recent_walk = None;
+        if idx >= self.open_files.len() || idx == self.cur_file_idx { return; }
+        if CONFIG.autosave_on_tab_switch { self.autosave_file(); }
+        self.cur_file_idx = idx;
+        self.update_styled_text();
+    }
+
+    pub fn increment_file_idx(&mut self) {
+        if CONFIG.autosave_on_tab_switch { self.autosave_file(); }
+        self.cur_file_idx += 1;
+        if self.cur_file_idx >= self.open_files.len() {
+            self.cur_file_idx = 0;
+        }
+        self.update_styled_text();
+    }
+
+    pub fn decrement_file_idx(&mut self) {
+        if CONFIG.autosave_on_tab_switch { self.autosave_file(); }
+        if self.cur_file_idx > 0 {
+            self.cur_file_idx -= 1;
+        } else {
+            self.cur_file_idx = self.open_files.len() - 1;
+        }
+        self.update_styled_text();
+    }
+
+    fn move_cursor(&mut self, dx: i16, dy: i16, select: bool) {
+        let had_selection = self.open_files[self.cur_file_idx].selection().is_some();
+        self.open_files[self.cur_file_idx].for_each_cursor(|file, _| file.move_cursor_selecting(dx, dy, select));
+        self.restyle_after_move(had_selection);
+    }
+
+    /// Called for every key press, before it is handled
+    pub fn register_input(&mut self) {
+        self.last_input = Instant::now();
+        self.autosave_pending = true;
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if self.open_files[self.cur_file_idx].hex().is_some() {
+            return self.hex_key(key);
+        }
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            if !large.handle_key(key, self.view_height as usize) && matches!(key.code, KeyCode::Char(_) | KeyCode::Enter | KeyCode::Tab | KeyCode::Backspace | KeyCode::Delete) {
+                self.set_status(LARGE_FILE_MESSAGE);
+            }
+            return self.update_styled_text();
+        }
+        if self.completion_key(key) {
+            return;
+        }
+        match self.modal.take() {
+            Some(mut modal) => {
+                modal.handle_key(key, self);
+                self.modal = Some(modal);
+            },
+            None => self.edit_key(key),
+        }
+        self.complete_after_key(key);
+    }
+
+    /// Handles a key in the hex view of a binary file, hex digits overwrite the byte at the cursor
+    fn hex_key(&mut self, key: KeyEvent) {
+        let file = &mut self.open_files[self.cur_file_idx];
+        if file.is_read_only() && matches!(key.code, KeyCode::Char(c) if c.is_ascii_hexdigit()) {
+            self.set_status(self.read_only_message());
+            return;
+        }
+        file.hex_key(key, self.view_height as usize);
+        self.update_styled_text();
+    }
+
+    /// Moves the cursor to a line, or in a binary file to a byte offset, which can be hex with `0x` in front
+    pub fn goto(&mut self, target: &str) -> Result<(), std::io::Error> {
+        let file = &mut self.open_files[self.cur_file_idx];
+        if let Some(hex) = file.hex_mut() {
+            hex.goto(target)?;
+        } else {
+            let line: u64 = target.parse().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Not a line number: {}", target)))?;
+            match file.large_mut() {
+                Some(large) => large.goto_line(line)?,
+                None => {
+                    file.clear_extra_carets();
+                    file.clear_selection();
+                    file.set_cursor((0, line.min(u16::MAX as u64) as u16));
+                },
+            }
+        }
+        self.scroll_to_cursor();
+        Ok(())
+    }
+
+    /// Why the current file can't be edited, for the footer
+    fn read_only_message(&self) -> &'static str {
+        let file = &self.open_files[self.cur_file_idx];
+        if file.large().is_some() {
+            LARGE_FILE_MESSAGE
+        } else if file.hex().is_some() && !file.is_read_only() {
+            HEX_MESSAGE
+        } else {
+            READ_ONLY_MESSAGE
+        }
+    }
+
+    /// Handles a key the modeless way, which is also what insert mode does
+    pub fn edit_key(&mut self, key: KeyEvent) {
+        let select = key.modifiers.contains(KeyModifiers::SHIFT);
+        match key.code {
+            KeyCode::Left => self.move_cursor(-1, 0, select),
+            KeyCode::Right => self.move_cursor(1, 0, select),
+            KeyCode::Up => self.move_cursor(0,-1, select),
+            KeyCode::Down => self.move_cursor(0, 1, select),
+
+            KeyCode::Esc if self.clear_extra_cursors() => self.update_styled_text(),
+
+            KeyCode::Enter => {
+                let extension = self.extension().to_string();
+                if self.begin_edit(Some(EditKind::Typing)) {
+                    self.edit_at_cursors(|file, line_state| file.smart_newline(&extension, line_state));
+                }
+            },
+
+            KeyCode::Backspace if self.begin_edit(Some(EditKind::Deleting)) => {
+                self.edit_at_cursors(|file, _| {
+                    if !file.remove_pair() {
+                        file.remove_character();
+                    }
+                });
+            },
+
+            KeyCode::Delete if self.begin_edit(Some(EditKind::Deleting)) => {
+                self.edit_at_cursors(|file, _| file.delete_character());
             },
 
-            KeyCode::Backspace => {
-                self.open_files[self.cur_file_idx].remove_character();
+            KeyCode::Tab if self.open_files[self.cur_file_idx].next_snippet_stop() => self.update_styled_text(),
+
+            //Tab on a selection over several lines indents them
+            KeyCode::Tab if self.open_files[self.cur_file_idx].selection().is_some_and(|(start, end)| start.1 != end.1) => {
+                self.clear_extra_cursors();
+                self.open_files[self.cur_file_idx].indent_lines();
                 self.update_styled_text();
             },
 
-            KeyCode::Delete => {
-                self.open_files[self.cur_file_idx].delete_character();
+            KeyCode::BackTab => {
+                self.clear_extra_cursors();
+                self.open_files[self.cur_file_idx].dedent_lines();
                 self.update_styled_text();
             },
 
-            KeyCode::Tab => {
-                for _ in 0..4 {
-                    self.open_files[self.cur_file_idx].add_character(' ');
-                }
+            KeyCode::Tab if self.begin_edit(Some(EditKind::Typing)) => {
+                self.edit_at_cursors(|file, _| {
+                    for _ in 0..4 {
+                        file.add_character(' ');
+                    }
+                });
                 // self.open_files[self.cur_file_idx].add_character('\t');
-                self.update_styled_text();
             },
 
-            KeyCode::Char(c) => {
-                //TODO: Check modifiers
-                self.open_files[self.cur_file_idx].add_character(c);
-                // self.move_cursor(1, 0);
-                self.update_styled_text();
+            //TODO: Check modifiers
+            KeyCode::Char(c) if self.begin_edit(Some(EditKind::Typing)) => {
+                self.edit_at_cursors(|file, line_state| {
+                    let state = file.lex_state_at_cursor(line_state);
+                    file.type_character(c, state);
+                });
             },
 
             _ => {},
@@ -390,7 +2654,525 @@ impl Editor {
     }
 
     pub fn ctrl_backspace(&mut self) {
-        self.open_files[self.cur_file_idx].remove_token();
+        if self.begin_edit(None) {
+            self.edit_at_cursors(|file, _| file.remove_token());
+        }
+    }
+
+    pub fn ctrl_delete(&mut self) {
+        if self.begin_edit(None) {
+            self.edit_at_cursors(|file, _| file.delete_token());
+        }
+    }
+
+    /// Starts an edit of the current file, or says why it can't be edited and returns false
+    fn begin_edit(&mut self, kind: Option<EditKind>) -> bool {
+        if self.open_files[self.cur_file_idx].begin_edit(kind) {
+            return true;
+        }
+        self.open_files[self.cur_file_idx].take_blocked_edit();
+        self.set_status(self.read_only_message());
+        false
+    }
+
+    /// Inserts pasted text at every cursor. When there are as many pasted lines as cursors, each cursor gets one of them.
+    pub fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let lines: Vec<&str> = text.strip_suffix('\n').unwrap_or(&text).split('\n').collect();
+        if !self.begin_edit(None) {
+            return;
+        }
+        let file = &mut self.open_files[self.cur_file_idx];
+        let per_cursor = file.cursor_count() > 1 && lines.len() == file.cursor_count();
+        file.for_each_cursor(|file, i| file.insert_text(if per_cursor { lines[i] } else { &text }));
+        self.update_styled_text();
+    }
+
+    /// Drops all cursors but the main one, returning false if there were none
+    pub fn clear_extra_cursors(&mut self) -> bool {
+        self.open_files[self.cur_file_idx].clear_extra_carets()
+    }
+
+    /// Number of cursors in the current file, including the main one
+    pub fn cursor_count(&self) -> usize {
+        self.open_files[self.cur_file_idx].cursor_count()
+    }
+
+    /// Adds a cursor above (`dy` < 0) or below the others
+    pub fn add_cursor_vertically(&mut self, dy: i16) {
+        self.open_files[self.cur_file_idx].add_cursor_vertically(dy);
+        self.update_styled_text();
+    }
+
+    /// Selects the next occurrence of the selection with another cursor (ctrl + d)
+    /// In a large file it goes to the next match of the last search instead
+    pub fn add_next_occurrence(&mut self) {
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            let found = match self.search_history.last() {
+                Some(needle) => large.find_next(needle),
+                None => Ok(false),
+            };
+            if !found.unwrap_or(false) {
+                self.set_status("no matches");
+            }
+            self.scroll_to_cursor();
+            return;
+        }
+        self.open_files[self.cur_file_idx].add_next_occurrence();
+        self.update_styled_text();
+    }
+
+    /// Puts a cursor on every occurrence of `needle` in the current file and remembers the search.
+    /// Large files can't have that many cursors, the cursor goes to the next match instead.
+    pub fn select_all_matches(&mut self, needle: &str) -> Result<(), std::io::Error> {
+        self.remember_search(needle);
+        if let Some(large) = self.open_files[self.cur_file_idx].large_mut() {
+            if !large.find_next(needle)? {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No matches for `{}`!", needle)));
+            }
+            self.scroll_to_cursor();
+            return Ok(());
+        }
+        if self.open_files[self.cur_file_idx].select_all_matches(needle) == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No matches for `{}`!", needle)));
+        }
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Keeps the language servers up to date with the open files, starting servers as files that need them show up.
+    /// Servers without open files left are shut down.
+    pub fn sync_lsp(&mut self) {
+        let mut uris: HashMap<String, Vec<String>> = HashMap::new();
+        for file in &self.open_files {
+            let (Some(path), Some(command)) = (file.path(), CONFIG.lsp_server(file.extension())) else { continue };
+            if self.lsp_failed.contains(command) {
+                continue;
+            }
+            if !self.lsp_clients.contains_key(command) {
+                match lsp::Client::spawn(command) {
+                    Ok(client) => { self.lsp_clients.insert(command.to_string(), client); },
+                    //Most likely the server isn't installed, which is no reason to bother the user
+                    Err(_) => {
+                        self.lsp_failed.insert(command.to_string());
+                        continue;
+                    },
+                }
+            }
+            if let Some(client) = self.lsp_clients.get_mut(command) {
+                let uri = match self.lsp_uris.get(path) {
+                    Some(uri) => uri.clone(),
+                    None => {
+                        let uri = lsp::path_to_uri(path);
+                        //Files that don't exist yet get a different URI once they are saved
+                        if Path::new(path).exists() {
+                            self.lsp_uris.insert(path.clone(), uri.clone());
+                        }
+                        uri
+                    },
+                };
+                //A server that can't be written to has exited, which `poll_lsp` reports
+                let _ = client.sync(&uri, file.extension(), file.version(), file.content());
+                uris.entry(command.to_string()).or_default().push(uri);
+            }
+        }
+        for (command, client) in &mut self.lsp_clients {
+            client.retain_documents(uris.get(command).map(Vec::as_slice).unwrap_or(&[]));
+        }
+        self.lsp_clients.retain(|_, client| client.has_documents());
+    }
+
+    /// Handles what the language servers sent since the last call, returning popups to show for it
+    pub fn poll_lsp(&mut self) -> Vec<PopupKind> {
+        let events: Vec<lsp::Event> = self.lsp_clients.values_mut().flat_map(lsp::Client::poll).collect();
+        let dead: Vec<String> = self.lsp_clients.iter().filter(|(_, client)| client.is_dead()).map(|(command, _)| command.clone()).collect();
+        for command in dead {
+            self.lsp_clients.remove(&command);
+            self.lsp_failed.insert(command);
+        }
+
+        let mut popups = Vec::new();
+        let mut restyle = false;
+        for event in events {
+            let result = match event {
+                lsp::Event::Diagnostics => {
+                    restyle = true;
+                    Ok(())
+                },
+                lsp::Event::Hover(text) => {
+                    popups.push(PopupKind::Info("hover".to_string(), text));
+                    Ok(())
+                },
+                lsp::Event::Definition(locations) => match locations.first() {
+                    Some(location) => self.open_location(location),
+                    None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No definition found!")),
+                },
+                lsp::Event::References(locations) if locations.is_empty() => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No references found!")),
+                lsp::Event::References(locations) => {
+                    let list: Vec<String> = locations.iter().map(|location| format!("{}:{}", crate::util::relative_to_cwd(&location.path), location.pos.0)).collect();
+                    popups.push(PopupKind::Info(format!("{} references", locations.len()), list.join("\n")));
+                    Ok(())
+                },
+                lsp::Event::Rename(files) => self.apply_workspace_edit(files),
+                lsp::Event::Completion(items) => {
+                    if let Some(prefix) = self.completion_prefix().map(|(_, prefix)| prefix) {
+                        if let Some((_, completion)) = &mut self.completion {
+                            completion.add_items(items, &prefix);
+                        }
+                    }
+                    Ok(())
+                },
+                lsp::Event::Formatting { path, edits, save } => self.apply_lsp_formatting(&path, edits, save),
+                lsp::Event::Error(message) => Err(std::io::Error::other(message)),
+            };
+            if let Err(err) = result {
+                popups.push(PopupKind::IOError(err.to_string()));
+            }
+        }
+        if restyle {
+            self.update_styled_text();
+        }
+        popups
+    }
+
+    /// Index of the open file at `path`, comparing absolute paths
+    fn file_idx_by_abs_path(&self, path: &str) -> Option<usize> {
+        let path = std::fs::canonicalize(path).ok()?;
+        self.open_files.iter().position(|file| file.path().and_then(|p| std::fs::canonicalize(p).ok()).as_ref() == Some(&path))
+    }
+
+    /// Index of the file at `path`, opening it if needed
+    fn open_abs_path(&mut self, path: &str) -> Result<usize, std::io::Error> {
+        if let Some(idx) = self.file_idx_by_abs_path(path) {
+            return Ok(idx);
+        }
+        self.open_files.push(File::from_path(&crate::util::relative_to_cwd(path))?);
+        Ok(self.open_files.len() - 1)
+    }
+
+    /// Switches to the file of `location` and puts the cursor there
+    fn open_location(&mut self, location: &lsp::Location) -> Result<(), std::io::Error> {
+        let idx = self.open_abs_path(&location.path)?;
+        self.switch_to_file(idx);
+        let file = &mut self.open_files[idx];
+        let pos = lsp::from_lsp_pos(file.content(), location.pos);
+        file.clear_extra_carets();
+        file.clear_selection();
+        file.end_edit_group();
+        file.set_cursor(pos);
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Applies the edits of a rename, opening the files that aren't open yet
+    fn apply_workspace_edit(&mut self, files: Vec<(String, Vec<lsp::TextEdit>)>) -> Result<(), std::io::Error> {
+        let files = files.into_iter()
+            .map(|(path, edits)| Ok((self.open_abs_path(&path)?, path, edits)))
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        //Renaming in only some of the files would leave the code broken
+        if let Some((_, path, _)) = files.iter().find(|(idx, _, _)| self.open_files[*idx].is_read_only()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("{} is read-only, nothing was renamed!", path)));
+        }
+        for (idx, _, edits) in files {
+            let file = &mut self.open_files[idx];
+            let edits = edits.into_iter()
+                .map(|edit| (lsp::from_lsp_pos(file.content(), edit.start), lsp::from_lsp_pos(file.content(), edit.end), edit.text))
+                .collect();
+            file.apply_edits(edits);
+        }
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Applies formatting edits from a language server to the open file at `path`, saving it afterwards if `save` is set
+    fn apply_lsp_formatting(&mut self, path: &str, edits: Vec<lsp::TextEdit>, save: bool) -> Result<(), std::io::Error> {
+        //The file may have been closed while the server was working on it
+        let Some(idx) = self.file_idx_by_abs_path(path) else { return Ok(()) };
+        let file = &mut self.open_files[idx];
+        let mut lines = lsp::apply_text_edits(file.content(), edits);
+        //Servers see the text without its final line break and like to add one, which saving adds anyway
+        if lines.len() > 1 && lines.last().is_some_and(String::is_empty) && !file.content().last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        file.apply_formatted(lines);
+        if save {
+            let swap = self.swap_path(idx);
+            if let Some(path) = self.open_files[idx].path().cloned() {
+                self.open_files[idx].check_writable()?;
+                self.open_files[idx].save_file_to_path(path)?;
+                remove_swap(swap);
+            }
+        }
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Asks the language server of the current file to format it, saving it afterwards if `save` is set
+    fn lsp_format(&mut self, save: bool) -> Result<(), std::io::Error> {
+        self.lsp_request(|client, uri, _| client.formatting(uri, save))
+    }
+
+    /// Sends a request about the cursor position in the current file to its language server.
+    /// The answer comes in later through `poll_lsp`.
+    fn lsp_request(&mut self, request: impl FnOnce(&mut lsp::Client, &str, lsp::LspPos) -> Result<(), std::io::Error>) -> Result<(), std::io::Error> {
+        self.sync_lsp();
+        let file = &self.open_files[self.cur_file_idx];
+        let path = file.path().ok_or_else(|| std::io::Error::other("Save the file first, language servers only work on files!"))?;
+        let command = CONFIG.lsp_server(file.extension())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "No language server is configured for this kind of file!"))?;
+        let client = self.lsp_clients.get_mut(command)
+            .ok_or_else(|| std::io::Error::other(format!("The language server `{}` isn't running!", command)))?;
+        request(client, &lsp::path_to_uri(path), lsp::to_lsp_pos(file.content(), file.cursor()))
+    }
+
+    /// Shows the documentation of the symbol at the cursor
+    pub fn hover(&mut self) -> Result<(), std::io::Error> {
+        self.lsp_request(|client, uri, pos| client.hover(uri, pos))
+    }
+
+    pub fn goto_definition(&mut self) -> Result<(), std::io::Error> {
+        self.lsp_request(|client, uri, pos| client.definition(uri, pos))
+    }
+
+    pub fn find_references(&mut self) -> Result<(), std::io::Error> {
+        self.lsp_request(|client, uri, pos| client.references(uri, pos))
+    }
+
+    /// Renames the symbol at the cursor everywhere the language server knows it is used
+    pub fn rename_symbol(&mut self, new_name: &str) -> Result<(), std::io::Error> {
+        self.lsp_request(|client, uri, pos| client.rename(uri, pos, new_name))
+    }
+
+    /// Opens the completion dropdown for the word before the cursor (ctrl + space)
+    pub fn trigger_completion(&mut self) {
+        self.open_completion(true);
+    }
+
+    /// Start of the word before the cursor and the part of it up to the cursor
+    fn word_before_cursor(&self) -> ((u16, u16), String) {
+        let file = &self.open_files[self.cur_file_idx];
+        let (x, y) = file.cursor();
+        let graphemes: Vec<&str> = file.content()[y as usize].graphemes(true).take(x as usize).collect();
+        let start = graphemes.iter().rposition(|g| !g.chars().all(|c| word::char_class(c) == word::Class::Word)).map(|i| i + 1).unwrap_or(0);
+        ((start as u16, y), graphemes[start..].concat())
+    }
+
+    /// Opens the completion dropdown with the words of all open files and asks the language server for more.
+    /// Unless `explicit`, it only opens once a few characters of a word were typed, or right after a `.` or `::`.
+    fn open_completion(&mut self, explicit: bool) {
+        if self.cursor_count() > 1 {
+            return;
+        }
+        let (start, prefix) = self.word_before_cursor();
+        let line = &self.content()[start.1 as usize];
+        let after_member = prefix.is_empty() && [".", "::", "->"].iter().any(|op| line.graphemes(true).take(start.0 as usize).collect::<String>().ends_with(op));
+        if !explicit && !after_member && prefix.graphemes(true).count() < completion::MIN_PREFIX {
+            return;
+        }
+        //Members are something only a language server knows about
+        let words = if after_member { Vec::new() } else { completion::buffer_words(self.open_files.iter().map(File::content), &prefix) };
+        let requested = self.lsp_request(|client, uri, pos| client.completion(uri, pos)).is_ok();
+        let completion = Completion::new(start, words, &prefix);
+        if requested || !completion.is_empty() {
+            self.completion = Some((self.cur_file_idx, completion));
+        }
+    }
+
+    /// The open completion and the part of the word typed so far, closing it if the cursor left the word
+    fn completion_prefix(&self) -> Option<(&Completion, String)> {
+        let (file_idx, completion) = self.completion.as_ref()?;
+        let (start, prefix) = self.word_before_cursor();
+        (*file_idx == self.cur_file_idx && start == completion.start && self.cursor_count() == 1).then_some((completion, prefix))
+    }
+
+    /// Opens, refilters or closes the completion dropdown after a key was handled
+    fn complete_after_key(&mut self, key: KeyEvent) {
+        let inserting = self.modal.as_ref().is_none_or(|modal| modal.mode() == Mode::Insert);
+        match key.code {
+            KeyCode::Char(_) | KeyCode::Backspace if inserting && self.completion.is_some() => {
+                match self.completion_prefix().map(|(_, prefix)| prefix) {
+                    Some(prefix) => if let Some((_, completion)) = &mut self.completion {
+                        completion.filter(&prefix);
+                    },
+                    //Typing something that isn't part of a word, like `.`, may start a new completion
+                    None => {
+                        self.completion = None;
+                        self.complete_after_key(key);
+                    },
+                }
+            },
+            KeyCode::Char(_) if inserting => self.open_completion(false),
+            _ => self.completion = None,
+        }
+    }
+
+    /// Handles keys meant for the completion dropdown while it shows items, returning false for all others
+    fn completion_key(&mut self, key: KeyEvent) -> bool {
+        if self.completion_prefix().is_none_or(|(completion, _)| completion.is_empty()) {
+            self.completion = None;
+            return false;
+        }
+        if !key.modifiers.is_empty() && key.modifiers != KeyModifiers::SHIFT {
+            return false;
+        }
+        match key.code {
+            KeyCode::Up => self.select_completion(-1),
+            KeyCode::Down => self.select_completion(1),
+            KeyCode::Tab | KeyCode::Enter => self.accept_completion(),
+            KeyCode::Esc => self.completion = None,
+            _ => return false,
+        }
+        true
+    }
+
+    fn select_completion(&mut self, offset: isize) {
+        if let Some((_, completion)) = &mut self.completion {
+            completion.select(offset);
+        }
+    }
+
+    /// Replaces the word before the cursor with the selected completion.
+    /// Text spanning several lines gets the indentation of the cursor line on every line but the first.
+    fn accept_completion(&mut self) {
+        let Some((_, completion)) = self.completion.take() else { return };
+        let Some(item) = completion.selected_item() else { return };
+        if !self.begin_edit(Some(EditKind::Typing)) {
+            return;
+        }
+        let file = &mut self.open_files[self.cur_file_idx];
+        let line = &file.content()[file.cursor().1 as usize];
+        let line_break = format!("\n{}", &line[..line.len() - line.trim_start().len()]);
+        file.set_selection(completion.start, file.cursor());
+        match &item.insert {
+            completion::Insert::Text(text) => file.insert_text(&text.replace('\n', &line_break)),
+            completion::Insert::Snippet(snippet) => {
+                let (text, stops) = completion::expand_snippet(&snippet.replace('\n', &line_break));
+                file.insert_snippet(&text, stops);
+            },
+        }
+        self.update_styled_text();
+    }
+
+    /// The completion dropdown to draw and where the word it completes starts in the text view
+    pub fn completion_menu(&self) -> Option<(&Completion, (u16, u16))> {
+        let (completion, prefix) = self.completion_prefix()?;
+        let (x, y) = self.cursor()?;
+        if completion.is_empty() {
+            return None;
+        }
+        Some((completion, (x.saturating_sub(util::display_column(&prefix, usize::MAX) as u16), y)))
+    }
+
+    /// Diagnostics of the current file, as of the last time its language server sent them
+    fn diagnostics(&self) -> &[lsp::Diagnostic] {
+        let file = &self.open_files[self.cur_file_idx];
+        let client = CONFIG.lsp_server(file.extension()).and_then(|command| self.lsp_clients.get(command));
+        match (client, file.path()) {
+            (Some(client), Some(path)) => client.diagnostics(&lsp::path_to_uri(path)),
+            _ => &[],
+        }
+    }
+
+    /// Message of the most severe diagnostic on the cursor line, for the footer
+    pub fn diagnostic_at_cursor(&self) -> Option<String> {
+        let y = self.file_cursor().1 as u32;
+        self.diagnostics().iter()
+            .filter(|diagnostic| diagnostic.start.0 <= y && diagnostic.end.0 >= y)
+            .min_by_key(|diagnostic| diagnostic.severity)
+            .map(|diagnostic| diagnostic.message.lines().next().unwrap_or("").to_string())
+    }
+
+    /// Adds a search to the history, moving it to the end if it was searched for before
+    pub fn remember_search(&mut self, needle: &str) {
+        if needle.is_empty() {
+            return;
+        }
+        self.search_history.retain(|search| search != needle);
+        self.search_history.push(needle.to_string());
+        if self.search_history.len() > MAX_SEARCH_HISTORY {
+            self.search_history.remove(0);
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if self.open_files[self.cur_file_idx].is_read_only() {
+            self.set_status(self.read_only_message());
+        } else if self.open_files[self.cur_file_idx].undo() {
+            self.update_styled_text();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if self.open_files[self.cur_file_idx].is_read_only() {
+            self.set_status(self.read_only_message());
+        } else if self.open_files[self.cur_file_idx].redo() {
+            self.update_styled_text();
+        }
+    }
+
+    /// Moves the selected lines (or the cursor line) up or down, with alt + up/down
+    pub fn move_lines(&mut self, dy: i16) {
+        self.clear_extra_cursors();
+        self.open_files[self.cur_file_idx].move_lines(dy);
+        self.update_styled_text();
+    }
+
+    pub fn duplicate_lines(&mut self) {
+        self.clear_extra_cursors();
+        self.open_files[self.cur_file_idx].duplicate_lines();
+        self.update_styled_text();
+    }
+
+    pub fn delete_lines(&mut self) {
+        self.clear_extra_cursors();
+        self.open_files[self.cur_file_idx].delete_lines();
+        self.update_styled_text();
+    }
+
+    pub fn join_lines(&mut self) {
+        self.clear_extra_cursors();
+        self.open_files[self.cur_file_idx].join_lines();
+        self.update_styled_text();
+    }
+
+    /// Toggles comments on the selected lines or the cursor line, with the comment syntax of the file's language
+    pub fn toggle_comment(&mut self) -> Result<(), std::io::Error> {
+        let tokens = crate::style::comment_tokens(self.extension())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "Don't know how to comment out lines in this kind of file!"))?;
+        self.clear_extra_cursors();
+        self.open_files[self.cur_file_idx].toggle_comment(tokens);
+        self.update_styled_text();
+        Ok(())
+    }
+
+    /// Rewrites the selected lines, or the whole file without a multi-line selection
+    pub fn transform_lines(&mut self, f: impl FnOnce(Vec<String>) -> Vec<String>) {
+        self.clear_extra_cursors();
+        self.open_files[self.cur_file_idx].transform_lines(f);
         self.update_styled_text();
     }
+
+    /// Filters the selection or the whole file through a shell command, like vim's `!`
+    pub fn filter_through(&mut self, command: &str) -> Result<(), std::io::Error> {
+        self.clear_extra_cursors();
+        let result = self.open_files[self.cur_file_idx].filter_through(command);
+        self.update_styled_text();
+        result
+    }
+
+    /// Types the output of a shell command at the cursor
+    pub fn insert_output(&mut self, command: &str) -> Result<(), std::io::Error> {
+        self.clear_extra_cursors();
+        let result = self.open_files[self.cur_file_idx].insert_output(command);
+        self.update_styled_text();
+        result
+    }
+
+    /// Moves the cursor by a word, `select` extends the selection like shift + arrows do
+    pub fn move_word(&mut self, forward: bool, select: bool) {
+        let file = &mut self.open_files[self.cur_file_idx];
+        let had_selection = file.selection().is_some();
+        file.for_each_cursor(|file, _| file.move_word_selecting(forward, select));
+        self.restyle_after_move(had_selection);
+    }
 }
@@ -0,0 +1,238 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+/// What a file looked like on disk the last time we read or wrote it.
+/// Used to notice when something else modified the file behind our back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DiskStamp {
+    pub modified: Option<SystemTime>,
+    pub hash: u64,
+}
+
+impl DiskStamp {
+    pub fn from_bytes(bytes: &[u8], modified: Option<SystemTime>) -> Self {
+        Self {
+            modified,
+            hash: hash_bytes(bytes),
+        }
+    }
+
+    /// Returns true if the file at `path` no longer matches this stamp.
+    /// The mtime is checked first so we only hash the file when it was actually touched.
+    pub fn is_outdated(&self, path: &Path) -> bool {
+        let modified = match fs::metadata(path) {
+            Ok(meta) => meta.modified().ok(),
+            Err(_) => return false, //Deleted files can't be overwritten by accident
+        };
+        if modified.is_some() && modified == self.modified {
+            return false;
+        }
+        match fs::read(path) {
+            Ok(bytes) => hash_bytes(&bytes) != self.hash,
+            Err(_) => false,
+        }
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Path of a hidden sibling file, for example `src/.main.rs.rocket-tmp`
+pub fn sibling_path(path: &Path, prefix: &str, suffix: &str) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no filename!"))?;
+    let name = format!("{}{}{}", prefix, file_name.to_string_lossy(), suffix);
+    Ok(match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.join(name),
+        _ => PathBuf::from(name),
+    })
+}
+
+/// Swap file for a buffer that has a path, vim style: `src/.main.rs.swp`
+pub fn swap_path(path: &Path) -> io::Result<PathBuf> {
+    sibling_path(path, ".", ".swp")
+}
+
+/// Swap file for a buffer that was never saved, kept in the user's cache directory
+pub fn unnamed_swap_path(id: usize) -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir).join("rocket"),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache").join("rocket"),
+    };
+    Some(dir.join(format!("unnamed-{}-{}.swp", std::process::id(), id)))
+}
+
+/// Returns true if `swap` exists and was written after `path` was last modified
+pub fn swap_is_newer(swap: &Path, path: &Path) -> bool {
+    let swap_time = match fs::metadata(swap).and_then(|meta| meta.modified()) {
+        Ok(time) => time,
+        Err(_) => return false,
+    };
+    match fs::metadata(path).and_then(|meta| meta.modified()) {
+        Ok(time) => swap_time > time,
+        Err(_) => true,
+    }
+}
+
+/// Writes a swap file. Swap files can contain anything the user typed,
+/// so they are only readable by the owner.
+pub fn write_swap(swap: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = swap.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(swap)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Safely replaces the content of `path` with `data`.
+/// The data is written to a temporary file in the same directory, synced to disk and then
+/// renamed over the original, so a crash or full disk never leaves a truncated file behind.
+/// The original file's permissions and ownership are carried over, and if `backup` is set
+/// the previous version is kept as `path~`.
+pub fn write_atomic(path: &Path, data: &[u8], backup: bool) -> io::Result<()> {
+    //Write through symlinks instead of replacing the link with a regular file
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = fs::metadata(&path).ok();
+    let tmp_path = sibling_path(&path, ".", ".rocket-tmp")?;
+
+    if let Err(err) = write_tmp(&tmp_path, data, original.as_ref()) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    if backup && original.is_some() {
+        let mut backup_path = path.clone().into_os_string();
+        backup_path.push("~");
+        if let Err(err) = fs::copy(&path, &backup_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    }
+
+    if let Err(err) = fs::rename(&tmp_path, &path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    sync_parent_dir(&path);
+    Ok(())
+}
+
+/// Whether `bytes` look like a binary file rather than text: a NUL early on, like git checks for, or anything that isn't UTF-8
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(8000)].contains(&0) || std::str::from_utf8(bytes).is_err()
+}
+
+/// Whether we may write to the file at `path`
+pub fn is_writable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        //Opening for appending checks ownership too, which the permission bits alone don't tell.
+        //Only for regular files, opening a pipe for writing would wait for a reader.
+        Ok(meta) if meta.is_file() => fs::OpenOptions::new().append(true).open(path).is_ok(),
+        Ok(meta) => !meta.permissions().readonly(),
+        Err(_) => true,
+    }
+}
+
+/// Replaces the content of `path` with `data` as another user, for files we aren't allowed to write.
+/// The data goes to a private temporary file that `helper` (like `sudo`) feeds to `tee`, which writes the file
+/// in place so its owner and permissions stay the same. `helper` may ask for a password on the terminal.
+pub fn write_privileged(path: &Path, data: &[u8], helper: &str) -> io::Result<()> {
+    let tmp_path = std::env::temp_dir().join(format!("rocket-{}.tmp", std::process::id()));
+    let _ = fs::remove_file(&tmp_path);
+    let result = write_private(&tmp_path, data).and_then(|_| {
+        let mut args = helper.split_whitespace();
+        let program = args.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty sudo command!"))?;
+        let status = Command::new(program)
+            .args(args)
+            .arg("tee")
+            .arg("--")
+            .arg(path)
+            .stdin(fs::File::open(&tmp_path)?)
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|err| io::Error::new(err.kind(), format!("Couldn't run `{}`: {}", program, err)))?;
+        if !status.success() {
+            return Err(io::Error::other(format!("`{} tee` failed!", helper)));
+        }
+        Ok(())
+    });
+    let _ = fs::remove_file(&tmp_path);
+    result
+}
+
+/// Creates `path` readable only by us, failing if something is there already, as it could be planted in a shared directory
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data)
+}
+
+/// Writes the temporary file that replaces the original. It is created with the permissions of the
+/// original (or only readable by us for a new file) before any data goes in, so nobody else can read it meanwhile.
+fn write_tmp(tmp_path: &Path, data: &[u8], original: Option<&fs::Metadata>) -> io::Result<()> {
+    //Left behind by a crash, `create_new` would fail on it forever
+    let _ = fs::remove_file(tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(original.map_or(0o600, |meta| meta.permissions().mode() & 0o777));
+    }
+    let mut file = options.open(tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    if let Some(meta) = original {
+        //Changing the owner may clear setuid bits, so the permissions come after it.
+        //The umask may also have taken some away when creating the file.
+        copy_ownership(tmp_path, meta);
+        fs::set_permissions(tmp_path, meta.permissions())?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_ownership(path: &Path, meta: &fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+    //Only root can hand files to other users, so a failure here is expected and harmless
+    let _ = std::os::unix::fs::chown(path, Some(meta.uid()), Some(meta.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_ownership(_path: &Path, _meta: &fs::Metadata) {}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) {
+    //Makes sure the rename itself survives a crash
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) {}
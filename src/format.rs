@@ -0,0 +1,17 @@
+use std::io;
+use std::process::Command;
+
+use crate::shell;
+
+/// Formatter setting that asks the file's language server instead of running a program
+pub const LSP: &str = "lsp";
+
+/// Pipes `text` through the formatter `command`, a command line split on whitespace, and returns what it printed.
+/// `{path}` in the arguments is replaced with `path`, for formatters that pick their style or parser by file name.
+pub fn run(command: &str, path: &str, text: &str) -> io::Result<String> {
+    let mut args = command.split_whitespace().map(|arg| arg.replace("{path}", path));
+    let program = args.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty formatter command!"))?;
+    let mut formatter = Command::new(&program);
+    formatter.args(args);
+    shell::pipe(formatter, &program, Some(text))
+}
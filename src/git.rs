@@ -0,0 +1,143 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::diff::{self, Hunk};
+
+/// How a line differs from the version in the HEAD commit, shown in the gutter
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines were removed right before this one
+    Deleted,
+}
+
+/// Runs `git` in `dir`, feeding it `stdin` if given, and returns what it printed
+fn git(dir: &Path, args: &[&str], stdin: Option<&str>) -> io::Result<String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        let input = input.to_string();
+        std::thread::spawn(move || pipe.write_all(input.as_bytes()));
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("git: {}", stderr.lines().next().unwrap_or("failed").trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Directory and file name of `path`, git is run in the directory so any repository it is in is found
+fn split_path(path: &str) -> (&Path, String) {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    (dir, path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default())
+}
+
+/// Lines of `path` in the HEAD commit, `None` outside a repository or if the file isn't committed
+pub fn head_lines(path: &str) -> Option<Vec<String>> {
+    let (dir, name) = split_path(path);
+    let content = git(dir, &["show", &format!("HEAD:./{}", name)], None).ok()?;
+    Some(content.lines().map(str::to_string).collect())
+}
+
+/// Branch checked out in the repository at `dir` and the commit HEAD points to,
+/// the branch being the short commit hash when HEAD is detached
+pub fn head(dir: &Path) -> Option<(String, String)> {
+    let output = git(dir, &["rev-parse", "--abbrev-ref", "HEAD", "HEAD"], None).ok()?;
+    let mut lines = output.lines();
+    let (branch, commit) = (lines.next()?, lines.next()?);
+    let branch = if branch == "HEAD" { &commit[..commit.len().min(7)] } else { branch };
+    Some((branch.to_string(), commit.to_string()))
+}
+
+/// Hunks turning `head` into `lines`, without context
+fn hunks(head: &[String], lines: &[String]) -> Vec<Hunk> {
+    diff::hunks(&diff::diff(head, lines), 0)
+}
+
+/// Change marker of every line of `lines` compared to `head`
+pub fn line_changes(head: &[String], lines: &[String]) -> Vec<Option<LineChange>> {
+    let mut changes = vec![None; lines.len()];
+    for hunk in hunks(head, lines) {
+        if hunk.new_len == 0 {
+            //Nothing is left to mark where lines were removed, so the next line gets it (or the last one at the end)
+            let y = hunk.new_start.min(lines.len().saturating_sub(1));
+            if let Some(change) = changes.get_mut(y) {
+                change.get_or_insert(LineChange::Deleted);
+            }
+            continue;
+        }
+        let change = if hunk.old_len == 0 { LineChange::Added } else { LineChange::Modified };
+        for change_slot in &mut changes[hunk.new_start..hunk.new_start + hunk.new_len] {
+            *change_slot = Some(change);
+        }
+    }
+    changes
+}
+
+/// The change around line `y` of `lines` as (range of lines in `lines`, the lines from `head` they replaced)
+pub fn hunk_at(head: &[String], lines: &[String], y: usize) -> Option<(std::ops::Range<usize>, Vec<String>)> {
+    hunks(head, lines).into_iter()
+        .find(|hunk| {
+            let end = hunk.new_start + hunk.new_len;
+            (hunk.new_start..end).contains(&y) || (hunk.new_len == 0 && hunk.new_start.min(lines.len().saturating_sub(1)) == y)
+        })
+        .map(|hunk| (hunk.new_start..hunk.new_start + hunk.new_len, head[hunk.old_start..hunk.old_start + hunk.old_len].to_vec()))
+}
+
+/// Who last changed line `y` of `path`, when and in which commit, blaming `lines` rather than the file on disk
+/// so the line numbers match the buffer
+pub fn blame(path: &str, lines: &[String], y: usize) -> io::Result<String> {
+    let (dir, name) = split_path(path);
+    let range = format!("{},{}", y + 1, y + 1);
+    let mut contents: String = lines.join("\n");
+    contents.push('\n');
+    let output = git(dir, &["blame", "--porcelain", "-L", &range, "--contents", "-", "--", &name], Some(&contents))?;
+
+    let mut commit = "";
+    let mut author = "";
+    let mut time = 0;
+    let mut summary = "";
+    for (i, line) in output.lines().enumerate() {
+        if i == 0 {
+            commit = line.split(' ').next().unwrap_or("");
+        } else if let Some(value) = line.strip_prefix("author ") {
+            author = value;
+        } else if let Some(value) = line.strip_prefix("author-time ") {
+            time = value.parse().unwrap_or(0);
+        } else if let Some(value) = line.strip_prefix("summary ") {
+            summary = value;
+        }
+    }
+    if commit.is_empty() || commit.chars().all(|c| c == '0') {
+        return Ok("Not committed yet".to_string());
+    }
+    Ok(format!("{}, {} · {} {}", author, date(time), &commit[..commit.len().min(7)], summary))
+}
+
+/// `YYYY-MM-DD` of a Unix timestamp, in UTC
+fn date(secs: i64) -> String {
+    //Civil from days, as in Howard Hinnant's date algorithms
+    let days = secs.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,298 @@
+use std::collections::HashSet;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use tui::text::{Span, Spans, Text};
+
+use crate::style;
+
+pub const BYTES_PER_ROW: usize = 16;
+/// Width of the offset column, `00000000` and two spaces
+pub const GUTTER_WIDTH: u16 = 10;
+/// Column the ASCII part of a row starts at, after the hex bytes and the `|` before it
+const ASCII_COLUMN: usize = BYTES_PER_ROW * 3 + 2;
+
+/// Column of the `i`th byte of a row in the hex part, there is an extra space after the first eight
+fn byte_column(i: usize) -> usize {
+    i * 3 + if i >= BYTES_PER_ROW / 2 { 1 } else { 0 }
+}
+
+/// A binary file shown as rows of hex bytes with an ASCII column. Bytes are overwritten nibble by nibble,
+/// the length never changes so the file saves back byte for byte.
+pub struct HexView {
+    bytes: Vec<u8>,
+    /// Byte the cursor is on, and whether on its low nibble
+    cursor: usize,
+    low_nibble: bool,
+    /// First row on screen
+    scroll: usize,
+    /// Offsets changed since the file was loaded or saved, highlighted
+    modified: HashSet<usize>,
+    /// Overwritten bytes as (offset, old value), the last one being the most recent
+    undo_stack: Vec<(usize, u8)>,
+    redo_stack: Vec<(usize, u8)>,
+}
+
+impl HexView {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, cursor: 0, low_nibble: false, scroll: 0, modified: HashSet::new(), undo_stack: Vec::new(), redo_stack: Vec::new() }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Replaces the bytes with a new version from disk, keeping the cursor where it was as far as possible
+    pub fn set_bytes(&mut self, bytes: Vec<u8>) {
+        self.bytes = bytes;
+        self.cursor = self.cursor.min(self.bytes.len().saturating_sub(1));
+        self.low_nibble = false;
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.mark_saved();
+    }
+
+    /// Forgets which bytes were changed, after saving
+    pub fn mark_saved(&mut self) {
+        self.modified.clear();
+    }
+
+    fn rows(&self) -> usize {
+        self.bytes.len().div_ceil(BYTES_PER_ROW).max(1)
+    }
+
+    fn move_to(&mut self, offset: usize) {
+        self.cursor = offset.min(self.bytes.len().saturating_sub(1));
+        self.low_nibble = false;
+    }
+
+    fn move_by(&mut self, bytes: isize) {
+        self.move_to(self.cursor.saturating_add_signed(bytes));
+    }
+
+    /// Moves the cursor to `offset`, which can be decimal or hex with `0x` in front
+    pub fn goto(&mut self, offset: &str) -> Result<(), std::io::Error> {
+        let offset = offset.trim();
+        let parsed = match offset.strip_prefix("0x").or_else(|| offset.strip_prefix("0X")) {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => offset.parse(),
+        };
+        match parsed {
+            Ok(offset) if offset < self.bytes.len() => {
+                self.move_to(offset);
+                Ok(())
+            },
+            Ok(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("The file is only {} bytes long!", self.bytes.len()))),
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Not an offset: {}", offset))),
+        }
+    }
+
+    /// Handles a key, `height` being the rows on screen. Returns true if a byte was changed.
+    pub fn handle_key(&mut self, key: KeyEvent, height: usize) -> bool {
+        let row = BYTES_PER_ROW as isize;
+        match key.code {
+            KeyCode::Left => self.move_by(-1),
+            KeyCode::Right => self.move_by(1),
+            KeyCode::Up => self.move_by(-row),
+            KeyCode::Down => self.move_by(row),
+            KeyCode::PageUp => self.move_by(-row * height.max(1) as isize),
+            KeyCode::PageDown => self.move_by(row * height.max(1) as isize),
+            KeyCode::Home => self.move_to(self.cursor - self.cursor % BYTES_PER_ROW),
+            KeyCode::End => self.move_to(self.cursor - self.cursor % BYTES_PER_ROW + BYTES_PER_ROW - 1),
+            KeyCode::Char(c) if c.is_ascii_hexdigit() => return self.type_nibble(c.to_digit(16).unwrap_or(0) as u8),
+            _ => {},
+        }
+        false
+    }
+
+    /// Overwrites the nibble at the cursor, going on to the next byte after the low one
+    fn type_nibble(&mut self, nibble: u8) -> bool {
+        let Some(&old) = self.bytes.get(self.cursor) else { return false };
+        let new = if self.low_nibble { (old & 0xf0) | nibble } else { (old & 0x0f) | (nibble << 4) };
+        self.undo_stack.push((self.cursor, old));
+        self.redo_stack.clear();
+        self.bytes[self.cursor] = new;
+        self.modified.insert(self.cursor);
+        if self.low_nibble {
+            self.move_by(1);
+        } else {
+            self.low_nibble = true;
+        }
+        true
+    }
+
+    /// Puts back the last overwritten byte, returning false if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some((offset, old)) = self.undo_stack.pop() else { return false };
+        self.redo_stack.push((offset, self.bytes[offset]));
+        self.bytes[offset] = old;
+        self.modified.insert(offset);
+        self.move_to(offset);
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let Some((offset, new)) = self.redo_stack.pop() else { return false };
+        self.undo_stack.push((offset, self.bytes[offset]));
+        self.bytes[offset] = new;
+        self.modified.insert(offset);
+        self.move_to(offset);
+        true
+    }
+
+    pub fn scroll_to_cursor(&mut self, height: usize) {
+        let row = self.cursor / BYTES_PER_ROW;
+        if row < self.scroll {
+            self.scroll = row;
+        } else if height > 0 && row >= self.scroll + height {
+            self.scroll = row + 1 - height;
+        }
+    }
+
+    pub fn scroll_by(&mut self, dy: isize) {
+        self.scroll = self.scroll.saturating_add_signed(dy).min(self.rows() - 1);
+    }
+
+    /// Moves the cursor to the byte shown at `col`, `row` of the view, in either the hex or the ASCII part
+    pub fn click(&mut self, col: u16, row: u16) {
+        let col = col as usize;
+        let i = if col >= ASCII_COLUMN {
+            col - ASCII_COLUMN
+        } else {
+            (0..BYTES_PER_ROW).rev().find(|&i| byte_column(i) <= col).unwrap_or(0)
+        };
+        let offset = (self.scroll + row as usize) * BYTES_PER_ROW + i.min(BYTES_PER_ROW - 1);
+        self.move_to(offset);
+        self.low_nibble = col < ASCII_COLUMN && col == byte_column(i) + 1;
+    }
+
+    /// Where the cursor is on screen, if it is within the `height` rows shown
+    pub fn screen_cursor(&self, height: usize) -> Option<(u16, u16)> {
+        let row = (self.cursor / BYTES_PER_ROW).checked_sub(self.scroll).filter(|&row| row < height)?;
+        let col = byte_column(self.cursor % BYTES_PER_ROW) + self.low_nibble as usize;
+        Some((col as u16, row as u16))
+    }
+
+    /// Cursor offset and file size, for the footer
+    pub fn position(&self) -> String {
+        format!("0x{:x} of 0x{:x} bytes", self.cursor, self.bytes.len())
+    }
+
+    /// The offsets and the rows shown, `height` rows from the scroll position
+    pub fn render(&self, height: usize) -> (Text<'static>, Text<'static>) {
+        let mut gutter = Vec::new();
+        let mut body = Vec::new();
+        for row in self.scroll..(self.scroll + height).min(self.rows()) {
+            let start = row * BYTES_PER_ROW;
+            gutter.push(Spans::from(Span::styled(format!("{:08x}  ", start), style::diff_number_style())));
+
+            let mut spans = Vec::new();
+            let mut ascii = Vec::new();
+            for i in 0..BYTES_PER_ROW {
+                let offset = start + i;
+                let Some(&byte) = self.bytes.get(offset) else { break };
+                let byte_style = style::hex_byte_style(byte, self.modified.contains(&offset));
+                let gap = if i + 1 == BYTES_PER_ROW / 2 { "  " } else { " " };
+                spans.push(Span::styled(format!("{:02x}", byte), byte_style));
+                spans.push(Span::raw(gap));
+                let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                //The cursor is in the hex part, its byte is marked in the ASCII part too
+                let ascii_style = if offset == self.cursor { style::selection_style() } else { byte_style };
+                ascii.push(Span::styled(c.to_string(), ascii_style));
+            }
+            let used: usize = spans.iter().map(|span| span.content.len()).sum();
+            spans.push(Span::raw(" ".repeat(ASCII_COLUMN - 1 - used)));
+            spans.push(Span::styled("|", style::diff_number_style()));
+            spans.extend(ascii);
+            spans.push(Span::styled("|", style::diff_number_style()));
+            body.push(Spans::from(spans));
+        }
+        (Text::from(gutter), Text::from(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn press(view: &mut HexView, code: KeyCode) -> bool {
+        view.handle_key(KeyEvent::new(code, KeyModifiers::NONE), 4)
+    }
+
+    fn type_hex(view: &mut HexView, digits: &str) {
+        for c in digits.chars() {
+            press(view, KeyCode::Char(c));
+        }
+    }
+
+    #[test]
+    fn nibbles() {
+        let mut view = HexView::new(vec![0x00, 0x11, 0x22]);
+        type_hex(&mut view, "aB");
+        assert_eq!(view.bytes(), &[0xab, 0x11, 0x22]);
+        //An odd number of digits leaves the low nibble of the last byte alone
+        type_hex(&mut view, "f");
+        assert_eq!(view.bytes(), &[0xab, 0xf1, 0x22]);
+        assert_eq!(view.screen_cursor(4), Some((4, 0)));
+        //Moving away starts on a high nibble again
+        press(&mut view, KeyCode::Right);
+        type_hex(&mut view, "3");
+        assert_eq!(view.bytes(), &[0xab, 0xf1, 0x32]);
+        //Not hex digits
+        assert!(!press(&mut view, KeyCode::Char('g')));
+    }
+
+    #[test]
+    fn stays_in_the_file() {
+        let mut view = HexView::new(vec![1, 2]);
+        type_hex(&mut view, "ffff00");
+        //The last byte is typed over again, the length never changes
+        assert_eq!(view.bytes(), &[0xff, 0x00]);
+
+        let mut empty = HexView::new(Vec::new());
+        type_hex(&mut empty, "1");
+        press(&mut empty, KeyCode::End);
+        assert!(empty.bytes().is_empty());
+        assert_eq!(empty.position(), "0x0 of 0x0 bytes");
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut view = HexView::new(vec![0x12, 0x34]);
+        type_hex(&mut view, "ab");
+        assert!(view.undo());
+        assert_eq!(view.bytes(), &[0xa2, 0x34]);
+        assert!(view.undo());
+        assert_eq!(view.bytes(), &[0x12, 0x34]);
+        assert!(!view.undo());
+        assert!(view.redo());
+        assert!(view.redo());
+        assert_eq!(view.bytes(), &[0xab, 0x34]);
+        assert!(!view.redo());
+    }
+
+    #[test]
+    fn goto_offsets() {
+        let mut view = HexView::new(vec![0; 64]);
+        view.goto("0x1f").unwrap();
+        assert_eq!(view.position(), "0x1f of 0x40 bytes");
+        view.goto(" 17 ").unwrap();
+        assert_eq!(view.screen_cursor(4), Some((byte_column(1) as u16, 1)));
+        view.goto("0X3F").unwrap();
+        assert!(view.goto("64").is_err());
+        assert!(view.goto("0x").is_err());
+        assert!(view.goto("ten").is_err());
+    }
+
+    #[test]
+    fn clicks() {
+        let mut view = HexView::new((0..32).collect());
+        //Second digit of the ninth byte, past the gap in the middle
+        view.click(byte_column(8) as u16 + 1, 1);
+        assert_eq!(view.position(), "0x18 of 0x20 bytes");
+        assert_eq!(view.screen_cursor(4), Some((byte_column(8) as u16 + 1, 1)));
+        //ASCII part
+        view.click(ASCII_COLUMN as u16 + 3, 0);
+        assert_eq!(view.position(), "0x3 of 0x20 bytes");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::style::{self, LexState, Lexeme};
+
+/// One level of indentation, what tab inserts
+pub const INDENT: &str = "    ";
+
+/// Characters that open an indented block at the end of a line, per file extension
+fn openers(extension: &str) -> &'static [char] {
+    match extension {
+        "py" | "pyw" | "yaml" | "yml" => &[':', '(', '[', '{'],
+        "md" | "txt" | "" => &[],
+        _ => &['{', '(', '['],
+    }
+}
+
+pub fn closing_bracket(open: char) -> Option<char> {
+    match open {
+        '{' => Some('}'),
+        '(' => Some(')'),
+        '[' => Some(']'),
+        _ => None,
+    }
+}
+
+pub fn is_closing_bracket(c: char) -> bool {
+    matches!(c, '}' | ')' | ']')
+}
+
+pub fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Indentation unit to use next to `indent`: a tab for lines indented with tabs, spaces otherwise
+pub fn unit_for(indent: &str) -> &'static str {
+    if indent.starts_with('\t') { "\t" } else { INDENT }
+}
+
+/// The block opener `line` ends with, if the next line should be indented further.
+/// `state` is the lexer state at the start of the line, openers in comments and strings don't count.
+pub fn opens_block(line: &str, extension: &str, state: LexState) -> Option<char> {
+    let line = line.trim_end();
+    let (lexemes, _) = style::lex_line(line, extension, state);
+    if lexemes.last() != Some(&Lexeme::Code) {
+        return None;
+    }
+    line.chars().last().filter(|c| openers(extension).contains(c))
+}
+
+/// `indent` with one level removed
+pub fn dedent(indent: &str) -> &str {
+    if let Some(rest) = indent.strip_suffix('\t') {
+        return rest;
+    }
+    let spaces = indent.len() - indent.trim_end_matches(' ').len();
+    &indent[..indent.len() - spaces.min(INDENT.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(leading_whitespace("\t  x = 1"), "\t  ");
+        assert_eq!(leading_whitespace("x"), "");
+        assert_eq!(leading_whitespace("   "), "   ");
+        assert_eq!(unit_for("\t"), "\t");
+        assert_eq!(unit_for("\t    "), "\t");
+        assert_eq!(unit_for("  "), INDENT);
+        assert_eq!(unit_for(""), INDENT);
+    }
+
+    #[test]
+    fn dedents_one_level() {
+        assert_eq!(dedent("\t\t"), "\t");
+        assert_eq!(dedent("        "), "    ");
+        assert_eq!(dedent("      "), "  ");
+        //Less than a full level of spaces goes away entirely
+        assert_eq!(dedent("  "), "");
+        //Only the spaces after a tab are removed, the tab stays
+        assert_eq!(dedent("\t  "), "\t");
+        assert_eq!(dedent(""), "");
+    }
+
+    #[test]
+    fn block_openers() {
+        assert_eq!(opens_block("fn main() {", "rs", LexState::Code), Some('{'));
+        assert_eq!(opens_block("foo(  ", "rs", LexState::Code), Some('('));
+        assert_eq!(opens_block("let x = 1;", "rs", LexState::Code), None);
+        //Openers in comments and strings don't count
+        assert_eq!(opens_block("x = 1; // {", "rs", LexState::Code), None);
+        assert_eq!(opens_block("let s = \"{\"", "rs", LexState::Code), None);
+        assert_eq!(opens_block("if x:", "py", LexState::Code), Some(':'));
+        assert_eq!(opens_block("if x:", "rs", LexState::Code), None);
+        assert_eq!(opens_block("a list [", "md", LexState::Code), None);
+    }
+}
@@ -0,0 +1,513 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+use crossterm::event::{KeyCode, KeyEvent};
+use tui::text::{Span, Spans, Text};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::style::{self, LexState};
+
+/// Lines between two entries of the line index, lines in between are found by reading on from the entry before them
+const INDEX_STEP: u64 = 1024;
+/// Bytes read at a time when indexing or searching
+const CHUNK: usize = 1 << 20;
+/// Lines longer than this many bytes are cut when shown
+const MAX_LINE: usize = 16 * 1024;
+
+/// Where lines start, filled in by a background thread so the file can be shown right away
+#[derive(Default)]
+struct LineIndex {
+    /// Byte offset of every `INDEX_STEP`th line, starting with line 0
+    starts: Vec<u64>,
+    /// Lines found so far, counting a last line without a line break once the end is reached
+    lines: u64,
+    /// Bytes scanned so far
+    scanned: u64,
+    done: bool,
+    /// Why indexing stopped early, if it failed
+    error: Option<String>,
+}
+
+/// A search match in the file
+#[derive(Clone, Copy)]
+struct Match {
+    /// Byte offset of the line the match is on
+    line_start: u64,
+    line: u64,
+    /// Columns the match covers, tabs expanded like on screen
+    start: usize,
+    end: usize,
+}
+
+/// A file too big to load, read from disk as it is scrolled through. It is shown read-only,
+/// only the lines on screen are kept in memory and highlighted.
+pub struct LargeFile {
+    file: fs::File,
+    len: u64,
+    index: Arc<Mutex<LineIndex>>,
+    /// Cursor and scroll position as (column, line), like the positions of a loaded file but without their limits
+    cursor: (usize, u64),
+    scroll: (usize, u64),
+    /// Lines on screen, tabs expanded
+    view: Vec<String>,
+    /// Last search match, highlighted. Also where lines can be read from before the index gets there.
+    found: Option<Match>,
+}
+
+/// Reads the next line from `reader` without its line break, keeping at most `MAX_LINE` bytes of it
+/// (none with `keep` unset, for skipping lines). `None` at the end of the file.
+fn next_line(reader: &mut impl BufRead, keep: bool) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let mut read_any = false;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(read_any.then_some(line));
+        }
+        read_any = true;
+        let newline = buf.iter().position(|&b| b == b'\n');
+        let take = newline.unwrap_or(buf.len());
+        if keep && line.len() < MAX_LINE {
+            line.extend_from_slice(&buf[..take.min(MAX_LINE - line.len())]);
+        }
+        reader.consume(take + newline.is_some() as usize);
+        if newline.is_some() {
+            return Ok(Some(line));
+        }
+    }
+}
+
+/// A line as shown: lossily decoded, without a trailing `\r` and with tabs expanded
+fn display_line(bytes: &[u8]) -> String {
+    let line = String::from_utf8_lossy(bytes);
+    line.strip_suffix('\r').unwrap_or(&line).replace('\t', "    ")
+}
+
+/// Counts the line breaks in `buf`, which starts at byte `buf_start`, moving `line` and `line_start` to the last line in it
+fn count_lines(buf: &[u8], buf_start: u64, line: &mut u64, line_start: &mut u64) {
+    for (i, &b) in buf.iter().enumerate() {
+        if b == b'\n' {
+            *line += 1;
+            *line_start = buf_start + i as u64 + 1;
+        }
+    }
+}
+
+/// Scans the file at `path` for line starts, stopping early once the view is gone
+fn build_index(path: String, index: Arc<Mutex<LineIndex>>) {
+    let result = (|| -> io::Result<()> {
+        let mut file = fs::File::open(path)?;
+        let mut buf = vec![0; CHUNK];
+        let (mut lines, mut offset, mut last) = (0u64, 0u64, b'\n');
+        loop {
+            let n = file.read(&mut buf)?;
+            let mut starts = Vec::new();
+            for (i, &b) in buf[..n].iter().enumerate() {
+                if b == b'\n' {
+                    lines += 1;
+                    if lines % INDEX_STEP == 0 {
+                        starts.push(offset + i as u64 + 1);
+                    }
+                }
+            }
+            offset += n as u64;
+            let mut shared = index.lock().expect("Failed to get lock on line index!");
+            shared.starts.extend(starts);
+            shared.scanned = offset;
+            if n == 0 {
+                //A last line without a line break is a line too
+                shared.lines = lines + (last != b'\n') as u64;
+                shared.done = true;
+                return Ok(());
+            }
+            shared.lines = lines;
+            last = buf[n - 1];
+            drop(shared);
+            if Arc::strong_count(&index) == 1 {
+                return Ok(());
+            }
+        }
+    })();
+    if let Err(err) = result {
+        index.lock().expect("Failed to get lock on line index!").error = Some(err.to_string());
+    }
+}
+
+impl LargeFile {
+    /// Opens the file at `path` and starts indexing its lines in the background
+    pub fn open(path: &str) -> Result<Self, io::Error> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let index = Arc::new(Mutex::new(LineIndex { starts: vec![0], ..Default::default() }));
+        let shared = Arc::clone(&index);
+        let path = path.to_string();
+        std::thread::spawn(move || build_index(path, shared));
+        Ok(Self { file, len, index, cursor: (0, 0), scroll: (0, 0), view: Vec::new(), found: None })
+    }
+
+    /// Lines the cursor can go to: those indexed so far, or up to the last search match if it is further
+    fn known_lines(&self) -> u64 {
+        let indexed = self.index.lock().expect("Failed to get lock on line index!").lines;
+        indexed.max(self.found.map(|found| found.line + 1).unwrap_or(0)).max(1)
+    }
+
+    /// Offset of the closest line at or before `line` that we know the start of, and which line that is
+    fn seek_point(&self, line: u64) -> (u64, u64) {
+        let index = self.index.lock().expect("Failed to get lock on line index!");
+        let entry = ((line / INDEX_STEP) as usize).min(index.starts.len() - 1);
+        let mut point = (index.starts[entry], entry as u64 * INDEX_STEP);
+        if let Some(found) = self.found.filter(|found| found.line <= line && found.line > point.1) {
+            point = (found.line_start, found.line);
+        }
+        point
+    }
+
+    /// Up to `count` lines starting at line `first`, as shown
+    fn read_lines(&self, first: u64, count: usize) -> io::Result<Vec<String>> {
+        let (offset, mut line) = self.seek_point(first);
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::with_capacity(64 * 1024, file);
+        while line < first {
+            if next_line(&mut reader, false)?.is_none() {
+                return Ok(Vec::new());
+            }
+            line += 1;
+        }
+        let mut lines = Vec::with_capacity(count);
+        while lines.len() < count {
+            match next_line(&mut reader, true)? {
+                Some(bytes) => lines.push(display_line(&bytes)),
+                None => break,
+            }
+        }
+        Ok(lines)
+    }
+
+    fn line_length(&self, line: u64) -> usize {
+        self.read_lines(line, 1).ok()
+            .and_then(|lines| lines.first().map(|line| line.graphemes(true).count()))
+            .unwrap_or(0)
+    }
+
+    fn move_to(&mut self, line: u64) {
+        self.cursor.1 = line.min(self.known_lines() - 1);
+    }
+
+    /// Handles a key, `height` being the lines on screen. Returns false for keys that don't move around.
+    pub fn handle_key(&mut self, key: KeyEvent, height: usize) -> bool {
+        let page = height.max(1) as u64;
+        match key.code {
+            KeyCode::Up => self.move_to(self.cursor.1.saturating_sub(1)),
+            KeyCode::Down => self.move_to(self.cursor.1 + 1),
+            KeyCode::PageUp => self.move_to(self.cursor.1.saturating_sub(page)),
+            KeyCode::PageDown => self.move_to(self.cursor.1 + page),
+            KeyCode::Char('g') => self.move_to(0),
+            KeyCode::Char('G') => self.move_to(u64::MAX),
+            KeyCode::Left => self.cursor.0 = self.cursor.0.min(self.line_length(self.cursor.1)).saturating_sub(1),
+            KeyCode::Right => self.cursor.0 = (self.cursor.0 + 1).min(self.line_length(self.cursor.1)),
+            KeyCode::Home => self.cursor.0 = 0,
+            KeyCode::End => self.cursor.0 = self.line_length(self.cursor.1),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Moves the cursor to the start of line `line`, counting from 0 like the line numbers
+    pub fn goto_line(&mut self, line: u64) -> Result<(), io::Error> {
+        let index = self.index.lock().expect("Failed to get lock on line index!");
+        let (lines, done) = (index.lines, index.done);
+        drop(index);
+        if line >= lines {
+            let message = if done { format!("The file only has {} lines!", lines) } else { format!("Only {} lines are indexed so far!", lines) };
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        self.cursor = (0, line);
+        Ok(())
+    }
+
+    /// Offset where line `line` starts, reading on from the closest line we know the start of
+    fn line_start(&self, line: u64) -> io::Result<u64> {
+        let (mut offset, first) = self.seek_point(line);
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::with_capacity(64 * 1024, file);
+        for _ in first..line {
+            let mut skipped = Vec::new();
+            offset += reader.read_until(b'\n', &mut skipped)? as u64;
+        }
+        Ok(offset)
+    }
+
+    /// Length in bytes of the line starting at byte `line_start`, its line break included
+    fn line_bytes(&self, line_start: u64) -> io::Result<u64> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(line_start))?;
+        let mut reader = BufReader::with_capacity(64 * 1024, file);
+        let mut len = 0;
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(len);
+            }
+            let newline = buf.iter().position(|&b| b == b'\n');
+            let take = newline.map_or(buf.len(), |i| i + 1);
+            len += take as u64;
+            reader.consume(take);
+            if newline.is_some() {
+                return Ok(len);
+            }
+        }
+    }
+
+    /// Moves the cursor to the next occurrence of `needle` after it, wrapping around at the end, and returns whether there was one.
+    /// Reads on from the cursor line rather than waiting for the index, so it works while indexing too.
+    pub fn find_next(&mut self, needle: &str) -> Result<bool, io::Error> {
+        if needle.is_empty() || needle.contains('\n') {
+            return Ok(false);
+        }
+        let (x, y) = self.cursor;
+        let line_start = match self.found.filter(|found| found.line == y) {
+            Some(found) => found.line_start,
+            None => self.line_start(y)?,
+        };
+        let needle = needle.as_bytes();
+        let found = match self.search(needle, line_start, y, self.len, |found| found.line > y || found.start > x)? {
+            Some(found) => Some(found),
+            //Nothing is after the cursor, so the first match up to the end of its line is before it or right at it.
+            //The column of the cursor doesn't tell how many bytes are before it on the line.
+            None => {
+                let line_end = line_start + self.line_bytes(line_start)?;
+                self.search(needle, 0, 0, line_end, |_| true)?
+            },
+        };
+        let Some(found) = found else { return Ok(false) };
+        self.cursor = (found.start, found.line);
+        self.found = Some(found);
+        Ok(true)
+    }
+
+    /// First match of `needle` that `accept` agrees to, from byte `offset` which is the start of line `line`.
+    /// Matches starting at or after byte `end` aren't looked for.
+    fn search(&self, needle: &[u8], offset: u64, mut line: u64, end: u64, accept: impl Fn(&Match) -> bool) -> io::Result<Option<Match>> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        //The end of the last chunk is kept, for matches that span two chunks
+        let mut buf: Vec<u8> = Vec::with_capacity(CHUNK + needle.len());
+        let mut buf_start = offset;
+        let mut line_start = offset;
+        //Bytes at the start of `buf` whose line breaks are counted in `line`
+        let mut counted = 0;
+        while buf_start < end {
+            let kept = buf.len();
+            buf.resize(kept + CHUNK, 0);
+            let n = file.read(&mut buf[kept..])?;
+            buf.truncate(kept + n);
+            if n == 0 {
+                break;
+            }
+            let last = buf.len().saturating_sub(needle.len() - 1);
+            for pos in 0..last {
+                if buf_start + pos as u64 >= end {
+                    return Ok(None);
+                }
+                if buf[pos] != needle[0] || !buf[pos..].starts_with(needle) {
+                    continue;
+                }
+                count_lines(&buf[counted..pos], buf_start + counted as u64, &mut line, &mut line_start);
+                counted = pos;
+                let found = self.match_at(needle, line_start, line, buf_start + pos as u64)?;
+                if accept(&found) {
+                    return Ok(Some(found));
+                }
+            }
+            count_lines(&buf[counted..last], buf_start + counted as u64, &mut line, &mut line_start);
+            buf.drain(..last);
+            buf_start += last as u64;
+            counted = 0;
+        }
+        Ok(None)
+    }
+
+    /// The match of `needle` at byte `offset`, on line `line` which starts at byte `line_start`.
+    /// Reads the line up to the match to find its column, putting the file position back afterwards.
+    fn match_at(&self, needle: &[u8], line_start: u64, line: u64, offset: u64) -> io::Result<Match> {
+        let mut file = &self.file;
+        let resume = file.stream_position()?;
+        let mut before = vec![0; (offset - line_start).min(MAX_LINE as u64) as usize];
+        file.seek(SeekFrom::Start(line_start))?;
+        file.read_exact(&mut before)?;
+        file.seek(SeekFrom::Start(resume))?;
+        let start = display_line(&before).graphemes(true).count();
+        Ok(Match { line_start, line, start, end: start + display_line(needle).graphemes(true).count() })
+    }
+
+    /// Scrolls just far enough to have the cursor inside a view of `width` columns and `height` lines
+    pub fn scroll_to_cursor(&mut self, width: usize, height: usize) {
+        if self.cursor.1 < self.scroll.1 {
+            self.scroll.1 = self.cursor.1;
+        } else if height > 0 && self.cursor.1 >= self.scroll.1 + height as u64 {
+            self.scroll.1 = self.cursor.1 + 1 - height as u64;
+        }
+        if self.cursor.0 < self.scroll.0 {
+            self.scroll.0 = self.cursor.0;
+        } else if width > 0 && self.cursor.0 >= self.scroll.0 + width {
+            self.scroll.0 = self.cursor.0 + 1 - width;
+        }
+    }
+
+    /// Scrolls by `dy` lines without moving the cursor
+    pub fn scroll_by(&mut self, dy: isize) {
+        self.scroll.1 = self.scroll.1.saturating_add_signed(dy as i64).min(self.known_lines() - 1);
+    }
+
+    /// Reads the `height` lines on screen
+    pub fn load_view(&mut self, height: usize) {
+        self.view = self.read_lines(self.scroll.1, height).unwrap_or_default();
+    }
+
+    /// Columns the view is scrolled sideways
+    pub fn scroll_x(&self) -> u16 {
+        self.scroll.0.min(u16::MAX as usize) as u16
+    }
+
+    /// Moves the cursor to the spot shown at `col`, `row` of the text, the gutter not included
+    pub fn click(&mut self, col: u16, row: u16) {
+        let Some(line) = self.view.get(row as usize) else { return };
+        self.cursor = ((self.scroll.0 + col as usize).min(line.graphemes(true).count()), self.scroll.1 + row as u64);
+    }
+
+    /// Where the cursor is in a view of `width` columns and `height` lines, if it is in view
+    pub fn screen_cursor(&self, width: usize, height: usize) -> Option<(u16, u16)> {
+        let len = self.view.get(self.cursor.1.checked_sub(self.scroll.1)? as usize).map(|line| line.graphemes(true).count()).unwrap_or(0);
+        let x = self.cursor.0.min(len).checked_sub(self.scroll.0).filter(|&x| x < width)?;
+        let y = self.cursor.1.checked_sub(self.scroll.1).filter(|&y| y < height as u64)?;
+        Some((x as u16, y as u16))
+    }
+
+    /// Width of the line numbers in front of the lines on screen
+    pub fn gutter_width(&self) -> u16 {
+        (self.scroll.1 + self.view.len().max(1) as u64 - 1).to_string().len() as u16 + 2
+    }
+
+    /// Line count, or how far indexing got while it is still going, for the footer
+    pub fn status(&self) -> String {
+        let index = self.index.lock().expect("Failed to get lock on line index!");
+        match &index.error {
+            Some(err) => format!("large file \\\\ indexing failed: {}", err),
+            None if index.done => format!("large file \\\\ {} lines", index.lines),
+            None => format!("large file \\\\ indexing {}%", index.scanned * 100 / self.len.max(1)),
+        }
+    }
+
+    /// Cursor position, for the footer
+    pub fn position(&self) -> String {
+        format!("{}:{}", self.cursor.0, self.cursor.1)
+    }
+
+    /// Line numbers and the lines on screen, highlighted as if the first one started outside any comment or string
+    pub fn render(&self, extension: &str) -> (Text<'static>, Text<'static>) {
+        let width = self.gutter_width() as usize - 2;
+        let mut gutter = Vec::new();
+        let mut text = Vec::new();
+        let mut state = LexState::Code;
+        for (i, line) in self.view.iter().enumerate() {
+            let y = self.scroll.1 + i as u64;
+            gutter.push(Spans::from(vec![style::editor_style(format!("{:width$}", y, width = width)), style::editor_style("~ ".to_string())]));
+            let (mut spans, next_state) = style::style_line(line.clone(), extension, state);
+            state = next_state;
+            if let Some(found) = self.found.filter(|found| found.line == y) {
+                style::patch_style(&mut spans, found.start..found.end, style::selection_style());
+            }
+            text.push(spans);
+        }
+        if text.is_empty() {
+            text.push(Spans::from(Span::raw("")));
+        }
+        (Text::from(gutter), Text::from(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Writes `content` to a temporary file and opens it, waiting for the index to be built
+    fn open(name: &str, content: &[u8]) -> LargeFile {
+        let path = std::env::temp_dir().join(format!("rocket-test-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        let file = LargeFile::open(path.to_str().unwrap()).unwrap();
+        let started = Instant::now();
+        while !file.index.lock().unwrap().done {
+            assert!(started.elapsed() < Duration::from_secs(10), "Indexing took too long");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let _ = fs::remove_file(&path);
+        file
+    }
+
+    /// `count` lines of 8 bytes each, so line `n` starts at byte `8 * n`
+    fn numbered_lines(count: u64) -> Vec<u8> {
+        (0..count).flat_map(|n| format!("{:07}\n", n).into_bytes()).collect()
+    }
+
+    #[test]
+    fn line_index() {
+        let file = open("index", &numbered_lines(3000));
+        let index = file.index.lock().unwrap();
+        assert_eq!(index.lines, 3000);
+        assert_eq!(index.starts, vec![0, 8 * INDEX_STEP, 16 * INDEX_STEP]);
+        drop(index);
+
+        let file = open("no-final-break", b"one\ntwo");
+        assert_eq!(file.index.lock().unwrap().lines, 2);
+        assert_eq!(file.read_lines(1, 5).unwrap(), vec!["two".to_string()]);
+    }
+
+    #[test]
+    fn seek_points() {
+        let file = open("seek", &numbered_lines(3000));
+        assert_eq!(file.seek_point(5), (0, 0));
+        assert_eq!(file.seek_point(INDEX_STEP), (8 * INDEX_STEP, INDEX_STEP));
+        assert_eq!(file.seek_point(2 * INDEX_STEP + 7), (16 * INDEX_STEP, 2 * INDEX_STEP));
+        assert_eq!(file.line_start(2 * INDEX_STEP + 7).unwrap(), 8 * (2 * INDEX_STEP + 7));
+        assert_eq!(file.read_lines(2999, 3).unwrap(), vec!["0002999".to_string()]);
+        assert_eq!(file.read_lines(1500, 1).unwrap(), vec!["0001500".to_string()]);
+    }
+
+    #[test]
+    fn match_across_chunks() {
+        //The line before the chunk boundary ends with the start of the needle
+        let lines = CHUNK as u64 / 8 - 1;
+        let mut content = numbered_lines(lines);
+        content.extend_from_slice(b"\tab:needle\nafter\n");
+        assert_eq!(content.iter().position(|&b| b == b'n').unwrap() as u64, CHUNK as u64 - 4);
+        let mut file = open("chunks", &content);
+        assert!(file.find_next("needle").unwrap());
+        //The tab counts as 4 columns, like on screen
+        assert_eq!(file.cursor, (7, lines));
+        let found = file.found.unwrap();
+        assert_eq!((found.line_start, found.start, found.end), (8 * lines, 7, 13));
+        assert!(!file.find_next("missing").unwrap());
+    }
+
+    #[test]
+    fn wraps_around_multibyte_text() {
+        let mut file = open("wrap", "first\n€€€€needle x\nlast\n".as_bytes());
+        //Right at the only match, the search wraps around to it
+        file.cursor = (4, 1);
+        assert!(file.find_next("needle").unwrap());
+        assert_eq!(file.cursor, (4, 1));
+        //Past it on the same line
+        file.cursor = (11, 1);
+        file.found = None;
+        assert!(file.find_next("needle").unwrap());
+        assert_eq!(file.cursor, (4, 1));
+        //From the line after
+        file.cursor = (0, 2);
+        assert!(file.find_next("needle").unwrap());
+        assert_eq!(file.cursor, (4, 1));
+    }
+}
@@ -0,0 +1,968 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::completion;
+
+/// How long a server gets to exit after `exit` before it is killed
+const EXIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A position as language servers count it: line and UTF-16 code unit in that line
+pub type LspPos = (u32, u32);
+
+/// Writes a JSON-RPC message with its `Content-Length` header
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Reads one JSON-RPC message, `None` once the stream is closed
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Language server message without Content-Length!"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// `file://` URI of a path, made absolute first
+pub fn path_to_uri(path: &str) -> String {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).to_path_buf());
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Path of a `file://` URI, `None` for other schemes
+pub fn uri_to_path(uri: &str) -> Option<String> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (encoded[i], hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Language id sent with opened documents
+fn language_id(extension: &str) -> &str {
+    match extension {
+        "rs" => "rust",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "py" => "python",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "sh" => "shellscript",
+        extension => extension,
+    }
+}
+
+fn lsp_pos(value: &Value) -> Option<LspPos> {
+    Some((value.get("line")?.as_u64()? as u32, value.get("character")?.as_u64()? as u32))
+}
+
+fn lsp_range(value: &Value) -> Option<(LspPos, LspPos)> {
+    Some((lsp_pos(value.get("start")?)?, lsp_pos(value.get("end")?)?))
+}
+
+fn utf16_len(line: &str) -> u32 {
+    line.encode_utf16().count() as u32
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+pub struct Diagnostic {
+    pub start: LspPos,
+    pub end: LspPos,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A place in a file a server pointed us to
+pub struct Location {
+    pub path: String,
+    pub pos: LspPos,
+}
+
+/// Replace the text between `start` and `end` with `text`
+pub struct TextEdit {
+    pub start: LspPos,
+    pub end: LspPos,
+    pub text: String,
+}
+
+/// Something a language server told us, for the editor to act on
+pub enum Event {
+    /// Diagnostics of a file changed
+    Diagnostics,
+    Hover(String),
+    Definition(Vec<Location>),
+    References(Vec<Location>),
+    /// Edits to make for a rename, per path
+    Rename(Vec<(String, Vec<TextEdit>)>),
+    Completion(Vec<completion::Item>),
+    /// Edits that format the file at `path`, which is saved afterwards if `save` is set
+    Formatting { path: String, edits: Vec<TextEdit>, save: bool },
+    Error(String),
+}
+
+/// What a request we are waiting on an answer for was
+enum Request {
+    Initialize,
+    Shutdown,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    Completion,
+    Formatting { uri: String, save: bool },
+}
+
+/// An open document, as the server last heard of it
+struct Document {
+    version: i64,
+    /// `File::version` of the content the server has, unchanged files aren't compared line by line
+    edit: u64,
+    language_id: String,
+    lines: Vec<String>,
+}
+
+/// A running language server, talked to over its stdin and stdout.
+/// A thread reads its messages, `poll` picks them up without blocking, and another one writes ours,
+/// so a server that is slow to read never holds up the editor.
+pub struct Client {
+    /// `None` for a server that isn't a process of its own, like the one the tests talk to
+    child: Option<Child>,
+    outgoing: Sender<Value>,
+    messages: Receiver<Value>,
+    next_id: u64,
+    pending: HashMap<u64, Request>,
+    /// The server answered `initialize`, until then documents are only recorded
+    initialized: bool,
+    /// The server accepts changed ranges instead of the whole text on every change
+    incremental: bool,
+    /// The server went away, no more messages are sent
+    dead: bool,
+    /// Open documents by URI
+    documents: HashMap<String, Document>,
+    /// Latest diagnostics by URI
+    diagnostics: HashMap<String, Vec<Diagnostic>>,
+}
+
+impl Client {
+    /// Starts the server `command`, a command line split on whitespace, rooted at the working directory
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        let mut args = command.split_whitespace();
+        let program = args.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty language server command!"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            //Anything written to stderr would end up on top of our screen
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("No stdin for the language server!"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("No stdout for the language server!"))?;
+        Self::start(Some(child), stdin, stdout)
+    }
+
+    /// Talks to a server through `input` and `output`, starting with `initialize`
+    fn start(child: Option<Child>, mut input: impl Write + Send + 'static, output: impl Read + Send + 'static) -> io::Result<Self> {
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(output);
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        //Closing the channel when the client goes away also closes the server's input
+        let (outgoing, unsent) = mpsc::channel::<Value>();
+        std::thread::spawn(move || {
+            for message in unsent {
+                if write_message(&mut input, &message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            child,
+            outgoing,
+            messages,
+            next_id: 1,
+            pending: HashMap::new(),
+            initialized: false,
+            incremental: false,
+            dead: false,
+            documents: HashMap::new(),
+            diagnostics: HashMap::new(),
+        };
+        let root = path_to_uri(".");
+        client.request(Request::Initialize, "initialize", json!({
+            "processId": std::process::id(),
+            "rootUri": root,
+            "workspaceFolders": [{ "uri": root, "name": "root" }],
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-16"] },
+                "textDocument": {
+                    "synchronization": { "dynamicRegistration": false },
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "definition": { "linkSupport": true },
+                    "references": {},
+                    "rename": { "prepareSupport": false },
+                    "completion": { "completionItem": { "snippetSupport": true } },
+                    "formatting": {},
+                    "publishDiagnostics": { "relatedInformation": false },
+                },
+            },
+        }))?;
+        Ok(client)
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        if self.dead {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "The language server exited!"));
+        }
+        self.outgoing.send(message).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The language server exited!"))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn request(&mut self, kind: Request, method: &str, params: Value) -> io::Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        self.pending.insert(id, kind);
+        Ok(())
+    }
+
+    fn did_open(&mut self, uri: &str) -> io::Result<()> {
+        let document = &self.documents[uri];
+        let params = json!({ "textDocument": {
+            "uri": uri,
+            "languageId": document.language_id,
+            "version": document.version,
+            "text": document.lines.join("\n"),
+        }});
+        self.notify("textDocument/didOpen", params)
+    }
+
+    /// Tells the server about the current content of a document, opening it if the server doesn't know it yet.
+    /// `edit` is the file's version, nothing is done if the server already has that one.
+    /// Only the lines that changed since the last sync are sent, if the server allows that.
+    pub fn sync(&mut self, uri: &str, extension: &str, edit: u64, lines: &[String]) -> io::Result<()> {
+        let document = match self.documents.get_mut(uri) {
+            Some(document) => document,
+            None => {
+                self.documents.insert(uri.to_string(), Document { version: 0, edit, language_id: language_id(extension).to_string(), lines: lines.to_vec() });
+                return if self.initialized { self.did_open(uri) } else { Ok(()) };
+            },
+        };
+        if document.edit == edit {
+            return Ok(());
+        }
+        document.edit = edit;
+        if document.lines == lines {
+            return Ok(());
+        }
+        let change = if self.incremental { line_change(&document.lines, lines) } else { json!({ "text": lines.join("\n") }) };
+        document.lines = lines.to_vec();
+        document.version += 1;
+        if !self.initialized {
+            return Ok(());
+        }
+        let params = json!({
+            "textDocument": { "uri": uri, "version": document.version },
+            "contentChanges": [change],
+        });
+        self.notify("textDocument/didChange", params)
+    }
+
+    /// Closes the documents that aren't in `uris` anymore
+    pub fn retain_documents(&mut self, uris: &[String]) {
+        let closed: Vec<String> = self.documents.keys().filter(|uri| !uris.contains(uri)).cloned().collect();
+        for uri in closed {
+            self.documents.remove(&uri);
+            self.diagnostics.remove(&uri);
+            if self.initialized {
+                let _ = self.notify("textDocument/didClose", json!({ "textDocument": { "uri": uri } }));
+            }
+        }
+    }
+
+    pub fn has_documents(&self) -> bool {
+        !self.documents.is_empty()
+    }
+
+    /// The server exited or closed its output, it won't answer anymore
+    pub fn is_dead(&self) -> bool {
+        self.dead
+    }
+
+    pub fn diagnostics(&self, uri: &str) -> &[Diagnostic] {
+        self.diagnostics.get(uri).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    fn position_request(&mut self, kind: Request, method: &str, uri: &str, pos: LspPos, extra: Value) -> io::Result<()> {
+        if !self.initialized {
+            return Err(io::Error::other("The language server is still starting up!"));
+        }
+        let mut params = json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": pos.0, "character": pos.1 },
+        });
+        if let (Some(params), Value::Object(extra)) = (params.as_object_mut(), extra) {
+            params.extend(extra);
+        }
+        self.request(kind, method, params)
+    }
+
+    pub fn hover(&mut self, uri: &str, pos: LspPos) -> io::Result<()> {
+        self.position_request(Request::Hover, "textDocument/hover", uri, pos, Value::Null)
+    }
+
+    pub fn definition(&mut self, uri: &str, pos: LspPos) -> io::Result<()> {
+        self.position_request(Request::Definition, "textDocument/definition", uri, pos, Value::Null)
+    }
+
+    pub fn references(&mut self, uri: &str, pos: LspPos) -> io::Result<()> {
+        self.position_request(Request::References, "textDocument/references", uri, pos, json!({ "context": { "includeDeclaration": true } }))
+    }
+
+    pub fn rename(&mut self, uri: &str, pos: LspPos, new_name: &str) -> io::Result<()> {
+        self.position_request(Request::Rename, "textDocument/rename", uri, pos, json!({ "newName": new_name }))
+    }
+
+    pub fn completion(&mut self, uri: &str, pos: LspPos) -> io::Result<()> {
+        self.position_request(Request::Completion, "textDocument/completion", uri, pos, Value::Null)
+    }
+
+    /// Asks for edits formatting the whole document, `save` is handed back with them
+    pub fn formatting(&mut self, uri: &str, save: bool) -> io::Result<()> {
+        if !self.initialized {
+            return Err(io::Error::other("The language server is still starting up!"));
+        }
+        self.request(Request::Formatting { uri: uri.to_string(), save }, "textDocument/formatting", json!({
+            "textDocument": { "uri": uri },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        }))
+    }
+
+    /// Handles the messages that arrived since the last call, without waiting for more
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => self.handle_message(message, &mut events),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    //One that exits before answering `initialize` most likely isn't installed, like a rustup proxy
+                    //without its component, which is no reason to bother the user
+                    if !self.dead && self.initialized {
+                        events.push(Event::Error("The language server exited!".to_string()));
+                    }
+                    self.dead = true;
+                    break;
+                },
+            }
+        }
+        events
+    }
+
+    fn handle_message(&mut self, message: Value, events: &mut Vec<Event>) {
+        let id = message.get("id").cloned();
+        match (message.get("method").and_then(Value::as_str), id) {
+            //Requests from the server, answered with nothing but what they need to carry on
+            (Some(method), Some(id)) => {
+                let result = match method {
+                    "workspace/configuration" => {
+                        let items = message["params"]["items"].as_array().map(Vec::len).unwrap_or(0);
+                        Value::Array(vec![Value::Null; items])
+                    },
+                    _ => Value::Null,
+                };
+                let _ = self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+            },
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let params = &message["params"];
+                if let Some(uri) = params["uri"].as_str() {
+                    let diagnostics = params["diagnostics"].as_array().map(|list| list.iter().filter_map(parse_diagnostic).collect()).unwrap_or_default();
+                    self.diagnostics.insert(uri.to_string(), diagnostics);
+                    events.push(Event::Diagnostics);
+                }
+            },
+            (Some(_), None) => {},
+            (None, Some(id)) => {
+                let Some(request) = id.as_u64().and_then(|id| self.pending.remove(&id)) else { return };
+                if let Some(error) = message.get("error") {
+                    let text = error["message"].as_str().unwrap_or("Request failed");
+                    events.push(Event::Error(format!("Language server: {}", text)));
+                    return;
+                }
+                self.handle_response(request, &message["result"], events);
+            },
+            (None, None) => {},
+        }
+    }
+
+    fn handle_response(&mut self, request: Request, result: &Value, events: &mut Vec<Event>) {
+        match request {
+            Request::Initialize => {
+                let sync = &result["capabilities"]["textDocumentSync"];
+                let change = sync.get("change").unwrap_or(sync);
+                self.incremental = change.as_u64() == Some(2);
+                self.initialized = true;
+                let _ = self.notify("initialized", json!({}));
+                let uris: Vec<String> = self.documents.keys().cloned().collect();
+                for uri in uris {
+                    let _ = self.did_open(&uri);
+                }
+            },
+            Request::Shutdown => {},
+            Request::Hover => {
+                let text = hover_text(&result["contents"]);
+                if !text.trim().is_empty() {
+                    events.push(Event::Hover(text.trim().to_string()));
+                }
+            },
+            Request::Definition => events.push(Event::Definition(parse_locations(result))),
+            Request::References => events.push(Event::References(parse_locations(result))),
+            Request::Rename => events.push(Event::Rename(parse_workspace_edit(result))),
+            Request::Completion => events.push(Event::Completion(parse_completions(result))),
+            Request::Formatting { uri, save } => {
+                if let Some(path) = uri_to_path(&uri) {
+                    events.push(Event::Formatting { path, edits: parse_text_edits(result), save });
+                }
+            },
+        }
+    }
+}
+
+impl Drop for Client {
+    /// Asks the server to shut down, and kills it if it doesn't exit in time
+    fn drop(&mut self) {
+        if !self.dead {
+            let _ = self.request(Request::Shutdown, "shutdown", Value::Null);
+            let _ = self.notify("exit", Value::Null);
+        }
+        let Some(child) = &mut self.child else { return };
+        let start = Instant::now();
+        while start.elapsed() < EXIT_TIMEOUT {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// The change turning `old` into `new`, as one range covering the lines between their common start and end
+fn line_change(old: &[String], new: &[String]) -> Value {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old.iter().rev().zip(new.iter().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    let changed = &new[prefix..new.len() - suffix];
+
+    let (start, end, text) = if suffix > 0 {
+        //Whole lines up to the start of the first unchanged line at the end
+        let text: String = changed.iter().map(|line| format!("{}\n", line)).collect();
+        ((prefix, 0), (old.len() - suffix, 0), text)
+    } else if prefix > 0 {
+        //Everything from the end of the last unchanged line on
+        let text: String = changed.iter().map(|line| format!("\n{}", line)).collect();
+        let last = old.len() - 1;
+        ((prefix - 1, utf16_len(&old[prefix - 1])), (last, utf16_len(&old[last])), text)
+    } else {
+        let last = old.len().saturating_sub(1);
+        ((0, 0), (last, old.get(last).map(|line| utf16_len(line)).unwrap_or(0)), new.join("\n"))
+    };
+    json!({
+        "range": {
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        },
+        "text": text,
+    })
+}
+
+fn parse_diagnostic(value: &Value) -> Option<Diagnostic> {
+    let (start, end) = lsp_range(value.get("range")?)?;
+    let severity = match value.get("severity").and_then(Value::as_u64) {
+        Some(1) => Severity::Error,
+        Some(2) => Severity::Warning,
+        _ => Severity::Info,
+    };
+    let message = value.get("message")?.as_str()?.to_string();
+    Some(Diagnostic { start, end, severity, message })
+}
+
+/// Text of hover contents, which can be a string, markup or a list of either
+fn hover_text(contents: &Value) -> String {
+    match contents {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().map(hover_text).collect::<Vec<_>>().join("\n"),
+        Value::Object(markup) => markup.get("value").and_then(Value::as_str).unwrap_or("").to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Completion items from either a plain list or a `CompletionList`
+fn parse_completions(result: &Value) -> Vec<completion::Item> {
+    let items = result.as_array().or_else(|| result.get("items").and_then(Value::as_array));
+    items.map(|items| items.iter().filter_map(|item| {
+        let label = item.get("label")?.as_str()?.to_string();
+        //The range of a text edit is ignored, the completed word is always what gets replaced
+        let text = item.get("textEdit").and_then(|edit| edit.get("newText"))
+            .or_else(|| item.get("insertText"))
+            .and_then(Value::as_str)
+            .unwrap_or(&label)
+            .replace("\r\n", "\n");
+        let insert = match item.get("insertTextFormat").and_then(Value::as_u64) {
+            Some(2) => completion::Insert::Snippet(text),
+            _ => completion::Insert::Text(text),
+        };
+        Some(completion::Item {
+            icon: completion::kind_icon(item.get("kind").and_then(Value::as_u64).unwrap_or(0)),
+            detail: item.get("detail").and_then(Value::as_str).unwrap_or("").lines().next().unwrap_or("").to_string(),
+            label,
+            insert,
+        })
+    }).collect()).unwrap_or_default()
+}
+
+/// Locations from a location, a list of them or a list of location links
+fn parse_locations(result: &Value) -> Vec<Location> {
+    let parse = |value: &Value| -> Option<Location> {
+        let uri = value.get("uri").or_else(|| value.get("targetUri"))?.as_str()?;
+        let range = value.get("range").or_else(|| value.get("targetSelectionRange"))?;
+        Some(Location { path: uri_to_path(uri)?, pos: lsp_range(range)?.0 })
+    };
+    match result {
+        Value::Array(list) => list.iter().filter_map(parse).collect(),
+        value => parse(value).into_iter().collect(),
+    }
+}
+
+fn parse_text_edits(edits: &Value) -> Vec<TextEdit> {
+    edits.as_array().map(|edits| edits.iter().filter_map(|edit| {
+        let (start, end) = lsp_range(edit.get("range")?)?;
+        Some(TextEdit { start, end, text: edit.get("newText")?.as_str()?.replace("\r\n", "\n") })
+    }).collect()).unwrap_or_default()
+}
+
+/// Edits per path of a workspace edit, from either its `changes` or its `documentChanges`
+fn parse_workspace_edit(result: &Value) -> Vec<(String, Vec<TextEdit>)> {
+    let mut files = Vec::new();
+    if let Some(changes) = result.get("changes").and_then(Value::as_object) {
+        for (uri, edits) in changes {
+            if let Some(path) = uri_to_path(uri) {
+                files.push((path, parse_text_edits(edits)));
+            }
+        }
+    }
+    if let Some(changes) = result.get("documentChanges").and_then(Value::as_array) {
+        //Creating, renaming and deleting files has no `edits` and is left out
+        for change in changes {
+            if let Some(path) = change["textDocument"]["uri"].as_str().and_then(uri_to_path) {
+                files.push((path, parse_text_edits(&change["edits"])));
+            }
+        }
+    }
+    files
+}
+
+/// `lines` with `edits` made to them, the edits being in the positions of the original lines and not overlapping
+pub fn apply_text_edits(lines: &[String], mut edits: Vec<TextEdit>) -> Vec<String> {
+    let mut lines = lines.to_vec();
+    //Back to front, so each edit leaves the positions of the ones before it alone
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+    for edit in edits {
+        let (start, end) = (from_lsp_pos(&lines, edit.start), from_lsp_pos(&lines, edit.end));
+        let left: String = lines[start.1 as usize].graphemes(true).take(start.0 as usize).collect();
+        let right: String = lines[end.1 as usize].graphemes(true).skip(end.0 as usize).collect();
+        let replacement = format!("{}{}{}", left, edit.text, right);
+        lines.splice(start.1 as usize..=end.1 as usize, replacement.split('\n').map(str::to_string));
+    }
+    lines
+}
+
+/// Position of grapheme `pos` as (x, y) in `lines`, as a language server counts it
+pub fn to_lsp_pos(lines: &[String], pos: (u16, u16)) -> LspPos {
+    let line = lines.get(pos.1 as usize).map(String::as_str).unwrap_or("");
+    (pos.1 as u32, line.graphemes(true).take(pos.0 as usize).map(utf16_len).sum())
+}
+
+/// Grapheme position as (x, y) of a position from a language server, clamped to `lines`
+pub fn from_lsp_pos(lines: &[String], pos: LspPos) -> (u16, u16) {
+    let last = lines.len().saturating_sub(1);
+    if pos.0 as usize > last {
+        return (lines.get(last).map(|line| line.graphemes(true).count()).unwrap_or(0) as u16, last as u16);
+    }
+    let line = &lines[pos.0 as usize];
+    let mut units = 0;
+    for (x, grapheme) in line.graphemes(true).enumerate() {
+        if units >= pos.1 {
+            return (x as u16, pos.0 as u16);
+        }
+        units += utf16_len(grapheme);
+    }
+    (line.graphemes(true).count() as u16, pos.0 as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(str::to_string).collect()
+    }
+
+    fn range(start: LspPos, end: LspPos) -> Value {
+        json!({ "start": { "line": start.0, "character": start.1 }, "end": { "line": end.0, "character": end.1 } })
+    }
+
+    /// An answer to the request the script entry is for, the `null` id is filled in when it is sent
+    fn answer(result: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": null, "result": result })
+    }
+
+    /// A language server replaying `script`: when the client sends the next method in it, the messages that go with it are sent back.
+    /// Everything the client sends ends up in the returned receiver.
+    fn fake_server(script: Vec<(&'static str, Vec<Value>)>) -> (Client, Receiver<Value>) {
+        let (from_client, client_input) = io::pipe().unwrap();
+        let (client_output, mut to_client) = io::pipe().unwrap();
+        let (seen, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(from_client);
+            let mut script = script.into_iter().peekable();
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if script.peek().is_some_and(|(method, _)| message["method"] == *method) {
+                    for mut reply in script.next().unwrap().1 {
+                        if reply.get("id") == Some(&Value::Null) {
+                            reply["id"] = message["id"].clone();
+                        }
+                        if write_message(&mut to_client, &reply).is_err() {
+                            return;
+                        }
+                    }
+                }
+                if seen.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        (Client::start(None, client_input, client_output).unwrap(), received)
+    }
+
+    /// A client that went through `initialize` with a server syncing documents the `sync` way, replaying `script` afterwards
+    fn initialized_server(sync: u64, script: Vec<(&'static str, Vec<Value>)>) -> (Client, Receiver<Value>) {
+        let mut full_script = vec![("initialize", vec![answer(json!({ "capabilities": { "textDocumentSync": sync } }))])];
+        full_script.extend(script);
+        let (mut client, received) = fake_server(full_script);
+        let start = Instant::now();
+        while !client.initialized {
+            assert!(start.elapsed() < Duration::from_secs(5), "The client never got the answer to initialize!");
+            client.poll();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        next_message(&received, "initialized");
+        (client, received)
+    }
+
+    /// The next message with `method` the client sent, skipping the others
+    fn next_message(received: &Receiver<Value>, method: &str) -> Value {
+        loop {
+            let message = received.recv_timeout(Duration::from_secs(5)).unwrap_or_else(|_| panic!("The client never sent {}!", method));
+            if message["method"] == method {
+                return message;
+            }
+        }
+    }
+
+    /// Polls the client until something happened
+    fn next_events(client: &mut Client) -> Vec<Event> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            let events = client.poll();
+            if !events.is_empty() {
+                return events;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("The client never got an answer!");
+    }
+
+    #[test]
+    fn initialize_opens_documents_synced_before() {
+        let (mut client, received) = fake_server(vec![("initialize", vec![answer(json!({ "capabilities": { "textDocumentSync": { "change": 2 } } }))])]);
+        let initialize = next_message(&received, "initialize");
+        assert!(initialize["params"]["rootUri"].as_str().unwrap().starts_with("file:///"));
+        assert_eq!(initialize["params"]["capabilities"]["general"]["positionEncodings"], json!(["utf-16"]));
+
+        client.sync("file:///a.rs", "rs", 1, &lines("fn main() {}")).unwrap();
+        let start = Instant::now();
+        while !client.initialized {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            client.poll();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(client.incremental);
+        next_message(&received, "initialized");
+        let open = next_message(&received, "textDocument/didOpen");
+        assert_eq!(open["params"]["textDocument"], json!({ "uri": "file:///a.rs", "languageId": "rust", "version": 0, "text": "fn main() {}" }));
+    }
+
+    #[test]
+    fn did_open_after_initialize() {
+        let (mut client, received) = initialized_server(1, vec![]);
+        client.sync("file:///a.py", "py", 1, &lines("a = 1\nb = 2")).unwrap();
+        let open = next_message(&received, "textDocument/didOpen");
+        assert_eq!(open["params"]["textDocument"]["languageId"], "python");
+        assert_eq!(open["params"]["textDocument"]["text"], "a = 1\nb = 2");
+    }
+
+    #[test]
+    fn incremental_did_change() {
+        let (mut client, received) = initialized_server(2, vec![]);
+        client.sync("file:///a.rs", "rs", 1, &lines("a\nb\nc")).unwrap();
+        client.sync("file:///a.rs", "rs", 2, &lines("a\nx\nc")).unwrap();
+        let change = next_message(&received, "textDocument/didChange");
+        assert_eq!(change["params"]["textDocument"]["version"], 1);
+        assert_eq!(change["params"]["contentChanges"], json!([{ "range": range((1, 0), (2, 0)), "text": "x\n" }]));
+
+        //The same version isn't compared again, the next change is against what the server has
+        client.sync("file:///a.rs", "rs", 2, &lines("not\nsent")).unwrap();
+        client.sync("file:///a.rs", "rs", 3, &lines("a\nx\nc\nd")).unwrap();
+        let change = next_message(&received, "textDocument/didChange");
+        assert_eq!(change["params"]["textDocument"]["version"], 2);
+        assert_eq!(change["params"]["contentChanges"], json!([{ "range": range((2, 1), (2, 1)), "text": "\nd" }]));
+    }
+
+    #[test]
+    fn full_did_change() {
+        let (mut client, received) = initialized_server(1, vec![]);
+        client.sync("file:///a.rs", "rs", 1, &lines("a")).unwrap();
+        client.sync("file:///a.rs", "rs", 2, &lines("a\nb")).unwrap();
+        let change = next_message(&received, "textDocument/didChange");
+        assert_eq!(change["params"]["contentChanges"], json!([{ "text": "a\nb" }]));
+    }
+
+    #[test]
+    fn publish_diagnostics() {
+        let diagnostics = json!({ "jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+            "uri": "file:///a.rs",
+            "diagnostics": [
+                { "range": range((0, 3), (0, 7)), "severity": 1, "message": "cannot find value" },
+                { "range": range((1, 0), (1, 1)), "message": "no severity" },
+            ],
+        }});
+        let (mut client, _received) = initialized_server(2, vec![("textDocument/didOpen", vec![diagnostics])]);
+        client.sync("file:///a.rs", "rs", 1, &lines("let main\n}")).unwrap();
+        assert!(matches!(next_events(&mut client)[..], [Event::Diagnostics]));
+        let diagnostics = client.diagnostics("file:///a.rs");
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].start == (0, 3) && diagnostics[0].end == (0, 7));
+        assert!(diagnostics[0].severity == Severity::Error && diagnostics[0].message == "cannot find value");
+        assert!(diagnostics[1].severity == Severity::Info);
+        assert!(client.diagnostics("file:///b.rs").is_empty());
+    }
+
+    #[test]
+    fn hover() {
+        let contents = json!({ "contents": { "kind": "markdown", "value": "fn main()\n" } });
+        let (mut client, received) = initialized_server(2, vec![("textDocument/hover", vec![answer(contents)])]);
+        client.hover("file:///a.rs", (2, 5)).unwrap();
+        let request = next_message(&received, "textDocument/hover");
+        assert_eq!(request["params"], json!({ "textDocument": { "uri": "file:///a.rs" }, "position": { "line": 2, "character": 5 } }));
+        match &next_events(&mut client)[..] {
+            [Event::Hover(text)] => assert_eq!(text, "fn main()"),
+            _ => panic!("Expected hover text!"),
+        }
+    }
+
+    #[test]
+    fn definition() {
+        let links = json!([{ "targetUri": "file:///src/a%20b.rs", "targetRange": range((0, 0), (9, 0)), "targetSelectionRange": range((3, 4), (3, 8)) }]);
+        let (mut client, _received) = initialized_server(2, vec![("textDocument/definition", vec![answer(links)])]);
+        client.definition("file:///a.rs", (0, 0)).unwrap();
+        match &next_events(&mut client)[..] {
+            [Event::Definition(locations)] => {
+                assert_eq!(locations.len(), 1);
+                assert_eq!(locations[0].path, "/src/a b.rs");
+                assert_eq!(locations[0].pos, (3, 4));
+            },
+            _ => panic!("Expected a definition!"),
+        }
+    }
+
+    #[test]
+    fn rename() {
+        let edit = json!({ "documentChanges": [
+            { "textDocument": { "uri": "file:///a.rs", "version": 1 }, "edits": [
+                { "range": range((0, 4), (0, 5)), "newText": "count" },
+                { "range": range((1, 0), (1, 1)), "newText": "count" },
+            ]},
+            { "kind": "create", "uri": "file:///new.rs" },
+        ]});
+        let (mut client, received) = initialized_server(2, vec![("textDocument/rename", vec![answer(edit)])]);
+        client.rename("file:///a.rs", (0, 4), "count").unwrap();
+        assert_eq!(next_message(&received, "textDocument/rename")["params"]["newName"], "count");
+        match &next_events(&mut client)[..] {
+            [Event::Rename(files)] => {
+                assert_eq!(files.len(), 1);
+                assert_eq!(files[0].0, "/a.rs");
+                let texts: Vec<(LspPos, &str)> = files[0].1.iter().map(|edit| (edit.start, edit.text.as_str())).collect();
+                assert_eq!(texts, vec![((0, 4), "count"), ((1, 0), "count")]);
+            },
+            _ => panic!("Expected rename edits!"),
+        }
+    }
+
+    #[test]
+    fn error_answers() {
+        let error = json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32600, "message": "no symbol here" } });
+        let (mut client, _received) = initialized_server(2, vec![("textDocument/rename", vec![error])]);
+        client.rename("file:///a.rs", (0, 0), "b").unwrap();
+        match &next_events(&mut client)[..] {
+            [Event::Error(text)] => assert_eq!(text, "Language server: no symbol here"),
+            _ => panic!("Expected an error!"),
+        }
+    }
+
+    #[test]
+    fn line_change_of_lines_in_between() {
+        let change = line_change(&lines("a\nb\nc"), &lines("a\nx\ny\nc"));
+        assert_eq!(change, json!({ "range": range((1, 0), (2, 0)), "text": "x\ny\n" }));
+        let change = line_change(&lines("a\nb\nc"), &lines("a\nc"));
+        assert_eq!(change, json!({ "range": range((1, 0), (2, 0)), "text": "" }));
+    }
+
+    #[test]
+    fn line_change_at_the_end() {
+        let change = line_change(&lines("é😀\nb"), &lines("é😀\nc"));
+        assert_eq!(change, json!({ "range": range((0, 3), (1, 1)), "text": "\nc" }));
+        let change = line_change(&lines("a\nb"), &lines("a"));
+        assert_eq!(change, json!({ "range": range((0, 1), (1, 1)), "text": "" }));
+    }
+
+    #[test]
+    fn line_change_of_everything() {
+        let change = line_change(&lines("a\nb"), &lines("c"));
+        assert_eq!(change, json!({ "range": range((0, 0), (1, 1)), "text": "c" }));
+    }
+
+    #[test]
+    fn text_edits() {
+        let edit = |start, end, text: &str| TextEdit { start, end, text: text.to_string() };
+        let original = lines("let a = 1;\na + a");
+        let renamed = apply_text_edits(&original, vec![edit((1, 0), (1, 1), "b"), edit((0, 4), (0, 5), "b"), edit((1, 4), (1, 5), "b")]);
+        assert_eq!(renamed, lines("let b = 1;\nb + b"));
+
+        let split = apply_text_edits(&original, vec![edit((0, 10), (0, 10), "\nlet c = 2;")]);
+        assert_eq!(split, lines("let a = 1;\nlet c = 2;\na + a"));
+        let joined = apply_text_edits(&original, vec![edit((0, 8), (1, 4), "")]);
+        assert_eq!(joined, lines("let a = a"));
+        //Positions count UTF-16 units, the emoji takes two
+        let wide = apply_text_edits(&lines("😀a"), vec![edit((0, 2), (0, 3), "b")]);
+        assert_eq!(wide, lines("😀b"));
+    }
+
+    #[test]
+    fn lsp_positions() {
+        let text = lines("a😀b\nx");
+        assert_eq!(to_lsp_pos(&text, (2, 0)), (0, 3));
+        assert_eq!(to_lsp_pos(&text, (3, 0)), (0, 4));
+        assert_eq!(to_lsp_pos(&text, (1, 1)), (1, 1));
+        assert_eq!(to_lsp_pos(&text, (0, 5)), (5, 0));
+
+        assert_eq!(from_lsp_pos(&text, (0, 3)), (2, 0));
+        assert_eq!(from_lsp_pos(&text, (0, 1)), (1, 0));
+        //In the middle of the emoji
+        assert_eq!(from_lsp_pos(&text, (0, 2)), (2, 0));
+        assert_eq!(from_lsp_pos(&text, (0, 99)), (3, 0));
+        assert_eq!(from_lsp_pos(&text, (7, 0)), (1, 1));
+    }
+
+    #[test]
+    fn read_messages() {
+        let message = |body: &str, header: &str| format!("{}: {}\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n{}", header, body.len(), body);
+        let stream = message(r#"{"id":1}"#, "Content-Length") + &message(r#"{"method":"é"}"#, "content-length");
+        let mut reader = Cursor::new(stream.into_bytes());
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({ "method": "é" })));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut reader = Cursor::new(b"Content-Type: text\r\n\r\n{}".to_vec());
+        assert_eq!(read_message(&mut reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut written = Vec::new();
+        write_message(&mut written, &json!({ "id": 2 })).unwrap();
+        assert_eq!(read_message(&mut Cursor::new(written)).unwrap(), Some(json!({ "id": 2 })));
+    }
+
+    #[test]
+    fn uri_paths() {
+        assert_eq!(uri_to_path("file:///tmp/a%20b.rs").as_deref(), Some("/tmp/a b.rs"));
+        assert_eq!(uri_to_path("file:///%E2%82%AC").as_deref(), Some("/€"));
+        assert_eq!(uri_to_path("file:///100%").as_deref(), Some("/100%"));
+        assert_eq!(uri_to_path("file:///a%zz").as_deref(), Some("/a%zz"));
+        assert_eq!(uri_to_path("https://example.com/a.rs"), None);
+        assert_eq!(uri_to_path("file:///%FF"), None);
+
+        let dir = std::env::temp_dir().join("rocket lsp ü");
+        std::fs::create_dir_all(&dir).unwrap();
+        let uri = path_to_uri(&dir.to_string_lossy());
+        assert!(uri.contains("rocket%20lsp%20%C3%BC"));
+        assert_eq!(uri_to_path(&uri).map(std::path::PathBuf::from), Some(std::fs::canonicalize(&dir).unwrap()));
+    }
+}
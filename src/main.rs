@@ -1,7 +1,10 @@
 #[macro_use] extern crate lazy_static;
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::io;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use crossterm::event::{poll, read, Event, KeyCode};
@@ -9,10 +12,12 @@ use crossterm::execute;
 
 use tui::Terminal;
 use tui::text::Span;
-use tui::backend::CrosstermBackend;
-use tui::widgets::{Widget, Tabs, Paragraph, Block, Borders};
-use tui::layout::{Layout, Constraint, Direction, Alignment};
-use tui::text::{Text, Spans};
+use tui::widgets::{Paragraph, Block, Clear, Wrap};
+use tui::layout::{Layout, Constraint, Direction, Alignment, Rect};
+use tui::text::Spans;
+
+pub(crate) mod config;
+use config::CONFIG;
 
 pub(crate) mod style;
 
@@ -24,25 +29,206 @@ use popup::{Popup, PopupKind};
 
 pub(crate) mod util;
 
+pub(crate) mod backend;
+use backend::CrosstermBackend;
+
+pub(crate) mod fileio;
+
+pub(crate) mod diff;
+
+pub(crate) mod diff_view;
+
+pub(crate) mod session;
+use session::Session;
+
+pub(crate) mod command;
+
+pub(crate) mod mouse;
+use mouse::{HitAreas, MouseState};
+
+pub(crate) mod modal;
+
+pub(crate) mod word;
+
+pub(crate) mod indent;
+
+pub(crate) mod pairs;
+
+pub(crate) mod comment;
+
+pub(crate) mod lsp;
+
+pub(crate) mod completion;
+
+pub(crate) mod format;
+
+pub(crate) mod shell;
+
+pub(crate) mod git;
+
+pub(crate) mod terminal;
+
+pub(crate) mod hex;
+
+pub(crate) mod large_file;
+
+/// Below this size only a "terminal too small" message is drawn
+const MIN_WIDTH: u16 = 20;
+const MIN_HEIGHT: u16 = 4;
+/// Popups get this wide if the terminal allows it
+const POPUP_WIDTH: u16 = 52;
+/// Put after the tab of a read-only file
+const RO_MARKER: &str = "[RO]";
+
 lazy_static! {
     static ref POPUP_STACK: Mutex<Vec<Popup>> = Mutex::new(Vec::new());
 }
 
-fn main() -> Result<(), io::Error> {
+fn restore_terminal() {
+    let _ = crossterm::terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), crossterm::event::DisableFocusChange, crossterm::event::DisableMouseCapture, crossterm::event::DisableBracketedPaste, crossterm::style::ResetColor, crossterm::cursor::Show, crossterm::terminal::LeaveAlternateScreen);
+}
+
+fn setup_terminal() -> Result<(), io::Error> {
     crossterm::terminal::enable_raw_mode()?;
+    execute!(io::stdout(), crossterm::terminal::EnterAlternateScreen, crossterm::event::EnableFocusChange, crossterm::event::EnableMouseCapture, crossterm::event::EnableBracketedPaste)
+}
+
+/// Hands the terminal to whatever `f` runs, like a program asking for a password, and takes it back afterwards
+fn with_terminal_released<T>(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, f: impl FnOnce() -> T) -> Result<T, io::Error> {
+    restore_terminal();
+    let result = f();
+    setup_terminal()?;
+    //Others drew over everything, so the next draw has to start from scratch
+    terminal.clear()?;
+    Ok(result)
+}
+
+/// Gives the terminal back to the shell and stops like a program outside raw mode would on ctrl + z,
+/// taking it over again once the shell continues us
+#[cfg(unix)]
+fn suspend(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<(), io::Error> {
+    with_terminal_released(terminal, || signal_hook::low_level::emulate_default_handler(signal_hook::consts::SIGTSTP))?
+}
+
+#[cfg(not(unix))]
+fn suspend(_terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<(), io::Error> {
+    Ok(())
+}
+
+/// Sets `flag` when someone else sends us SIGTSTP, so we can suspend properly instead of stopping mid raw mode
+#[cfg(unix)]
+fn watch_suspend(flag: &Arc<AtomicBool>) -> Result<(), io::Error> {
+    signal_hook::flag::register(signal_hook::consts::SIGTSTP, Arc::clone(flag)).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn watch_suspend(_flag: &Arc<AtomicBool>) -> Result<(), io::Error> {
+    Ok(())
+}
+
+fn main() -> Result<(), io::Error> {
+    let mut use_session = true;
+    let mut read_only = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-session" => use_session = false,
+            "--readonly" => read_only = true,
+            _ => paths.push(arg),
+        }
+    }
+
+    //Without files to open, pick up where we left off in this directory
+    let mut editor = if paths.is_empty() && use_session {
+        session::cwd_session_path()
+            .and_then(|path| Session::load(&path).ok())
+            .and_then(|session| session.open().ok())
+            .unwrap_or_else(|| Editor::from_files(Vec::new()))
+    } else {
+        Editor::from_paths(paths.iter().map(String::as_str).collect())?
+    };
+    if read_only {
+        editor.set_all_read_only();
+    }
 
-    execute!(io::stdout(), crossterm::terminal::EnterAlternateScreen)?;
+    //Leave raw mode and the alternate screen before the panic message is printed, otherwise it's lost
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+    }));
+
+    setup_terminal()?;
 
     let stdout = io::stdout();
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut editor = Editor::from_paths(vec!["test.rs", "test.txt", "path_test/test.c"])?;
+    {
+        let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+        for path in editor.files_with_newer_swap() {
+            stack.push(Popup::from_kind(PopupKind::RecoverSwap(path)));
+        }
+    }
+
+    match std::panic::catch_unwind(AssertUnwindSafe(|| run(&mut terminal, &mut editor))) {
+        Ok(Ok(())) => {
+            editor.remove_swap_files();
+            restore_terminal();
+            if use_session {
+                if let Some(path) = session::cwd_session_path() {
+                    Session::from_editor(&editor).save(&path)?;
+                }
+            }
+            Ok(())
+        },
+        Ok(Err(err)) => {
+            restore_terminal();
+            report_swap_files(editor.write_swap_files());
+            Err(err)
+        },
+        Err(panic) => {
+            //The panic hook already restored the terminal
+            report_swap_files(editor.write_swap_files());
+            std::panic::resume_unwind(panic)
+        },
+    }
+}
+
+fn report_swap_files(paths: Vec<PathBuf>) {
+    if !paths.is_empty() {
+        eprintln!("rocket exited unexpectedly, unsaved buffers were written to:");
+        for path in paths {
+            eprintln!("    {}", path.display());
+        }
+    }
+}
+
+fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, editor: &mut Editor) -> Result<(), io::Error> {
+    let mut hit_areas = HitAreas::default();
+    let mut mouse_state = MouseState::default();
+    let suspend_requested = Arc::new(AtomicBool::new(false));
+    watch_suspend(&suspend_requested)?;
 
     'main: loop {
+        //Everything but the header and footer is text
+        let size = terminal.size()?;
+        editor.set_view_size(size.width, size.height.saturating_sub(2));
+
         terminal.draw(|f| {
-            let cursor_pos = editor.cursor();
-            f.set_cursor(cursor_pos.0 + 3, cursor_pos.1 + 1);
+            hit_areas.tabs.clear();
+            hit_areas.buttons.clear();
+
+            if f.size().width < MIN_WIDTH || f.size().height < MIN_HEIGHT {
+                let message = Paragraph::new("terminal too small")
+                    .alignment(Alignment::Center)
+                    .wrap(Wrap { trim: true })
+                    .style(style::style_background());
+                f.render_widget(message, f.size());
+                hit_areas.text = Rect::default();
+                return;
+            }
 
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
@@ -56,35 +242,139 @@ fn main() -> Result<(), io::Error> {
                 )
                 .split(f.size());
 
+            let filenames: Vec<String> = editor.all_filenames_modified().collect();
+            let read_only = editor.read_only_files();
+            //Read-only tabs end in a `[RO]` marker
+            let marker_width = |i: usize| if read_only[i] { RO_MARKER.len() as u16 } else { 0 };
+            let tab_widths: Vec<u16> = filenames.iter().enumerate().map(|(i, name)| name.chars().count() as u16 + 3 + marker_width(i)).collect();
+            let visible = util::visible_tabs(&tab_widths, editor.cur_file_idx, chunks[0].width);
+            let unstyle = style::header_style(false);
             let mut file_header_span = Spans::from(vec![]);
-            for (i, filename) in editor.all_filenames_modified().enumerate() {
+            let mut tab_x = chunks[0].x;
+            if visible.start > 0 {
+                //Clicking the marker goes to the closest hidden tab
+                hit_areas.tabs.push((visible.start - 1, tab_x, tab_x + 1));
+                file_header_span.0.push(Span::styled("< ", style::header_style(true)));
+                tab_x += util::TAB_MARKER_WIDTH;
+            }
+            for (i, filename) in filenames.iter().enumerate().take(visible.end).skip(visible.start) {
                 let style = style::header_style(i == editor.cur_file_idx);
-                let unstyle = style::header_style(false);
-                if i > 0 { file_header_span.0.push(Span::styled(" ", unstyle)); }
+                if i > visible.start { file_header_span.0.push(Span::styled(" ", unstyle)); tab_x += 1; }
+                let tab_width = filename.chars().count() as u16 + 2 + marker_width(i);
+                hit_areas.tabs.push((i, tab_x, tab_x + tab_width));
+                tab_x += tab_width + 1;
                 file_header_span.0.push(Span::styled("[", style));
-                file_header_span.0.push(Span::styled(filename, style));
+                file_header_span.0.push(Span::styled(filename.clone(), style));
                 file_header_span.0.push(Span::styled("]", style));
+                if read_only[i] {
+                    file_header_span.0.push(Span::styled(RO_MARKER, style));
+                }
                 file_header_span.0.push(Span::styled(" ", unstyle));
             }
+            if visible.end < filenames.len() {
+                let marker_x = chunks[0].x + chunks[0].width - 1;
+                file_header_span.0.push(Span::styled(" ".repeat(marker_x.saturating_sub(tab_x) as usize), unstyle));
+                file_header_span.0.push(Span::styled(">", style::header_style(true)));
+                hit_areas.tabs.push((visible.end, marker_x, marker_x + 1));
+            }
             let file_header = Paragraph::new(file_header_span).style(style::header_style(false));
             f.render_widget(file_header, chunks[0]);
 
-            let cur_file_content = Paragraph::new(editor.styled_text.clone()).style(style::style_background());
-            f.render_widget(cur_file_content, chunks[1]);
+            //The terminal pane, if open, goes below the text
+            let pane_height = if editor.terminal.is_some() { terminal::pane_height(chunks[1].height) } else { 0 };
+            let body = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(1), Constraint::Length(pane_height)].as_ref())
+                .split(chunks[1]);
+            let text_area = body[0];
+
+            let text_chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(editor.gutter_width()), Constraint::Min(1)].as_ref())
+                .split(text_area);
+            if let Some(view) = &editor.diff_view {
+                //The diff takes the place of the text, no cursor or clicking into it
+                f.render_widget(Paragraph::new(view.render(text_area.width)).style(style::style_background()), text_area);
+                hit_areas.text = Rect::default();
+            } else {
+                let scroll = editor.scroll();
+                let gutter = Paragraph::new(editor.styled_gutter.clone()).style(style::style_background()).scroll((scroll.1, 0));
+                f.render_widget(gutter, text_chunks[0]);
+                let cur_file_content = Paragraph::new(editor.styled_text.clone()).style(style::style_background()).scroll((scroll.1, scroll.0));
+                f.render_widget(cur_file_content, text_chunks[1]);
+                hit_areas.text = text_area;
+
+                if let Some(cursor_pos) = editor.cursor().filter(|_| !editor.terminal_focused) {
+                    f.set_cursor(text_chunks[1].x + cursor_pos.0, text_chunks[1].y + cursor_pos.1);
+                }
+            }
+
+            hit_areas.terminal = Rect::default();
+            if let Some(pane) = editor.terminal.as_ref().filter(|_| pane_height >= 2) {
+                let pane_chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(1), Constraint::Min(1)].as_ref())
+                    .split(body[1]);
+                let header = Paragraph::new(" terminal // alt + t switches focus").style(style::header_style(editor.terminal_focused));
+                f.render_widget(header, pane_chunks[0]);
+                let (screen, cursor) = pane.render();
+                f.render_widget(Paragraph::new(screen).style(style::style_background()), pane_chunks[1]);
+                hit_areas.terminal = pane_chunks[1];
+                if let Some(cursor) = cursor.filter(|_| editor.terminal_focused) {
+                    f.set_cursor(pane_chunks[1].x + cursor.0, pane_chunks[1].y + cursor.1);
+                }
+            }
+
+            if let Some((completion, anchor)) = editor.completion_menu().filter(|_| editor.diff_view.is_none()) {
+                let (items, selected) = completion.visible();
+                let label_width = items.iter().map(|item| item.label.chars().count()).max().unwrap_or(0);
+                let detail_width = items.iter().map(|item| item.detail.chars().count()).max().unwrap_or(0);
+                //Icon, label and detail with a space around each, the detail cut short before the label is
+                let width = (label_width + detail_width + 5).min(text_chunks[1].width as usize) as u16;
+                let height = items.len() as u16;
+                //Below the cursor line if it fits, otherwise above it
+                let below = text_chunks[1].y + anchor.1 + 1;
+                let y = if below + height <= text_chunks[1].y + text_chunks[1].height { below } else { (text_chunks[1].y + anchor.1).saturating_sub(height) };
+                let x = (text_chunks[1].x + anchor.0).min(text_chunks[1].x + text_chunks[1].width - width);
+                let rect = Rect::new(x, y, width, height.min(f.size().height.saturating_sub(y)));
+
+                let lines: Vec<Spans> = items.iter().enumerate().map(|(i, item)| {
+                    let label: String = item.label.chars().take(width.saturating_sub(3) as usize).collect();
+                    let room = (width as usize).saturating_sub(label.chars().count() + 5);
+                    let detail: String = item.detail.chars().take(room).collect();
+                    let padding = room - detail.chars().count();
+                    Spans::from(vec![
+                        Span::styled(format!(" {} ", item.icon), style::completion_detail_style(i == selected)),
+                        Span::styled(label, style::completion_style(i == selected)),
+                        Span::styled(format!("{} {} ", " ".repeat(padding), detail), style::completion_detail_style(i == selected)),
+                    ])
+                }).collect();
+                f.render_widget(Clear, rect);
+                f.render_widget(Paragraph::new(lines).style(style::completion_style(false)), rect);
+            }
 
             let footer = Layout::default()
                 .direction(Direction::Horizontal)
                 .margin(0)
                 .constraints(
                     [
-                        Constraint::Percentage(50),
-                        Constraint::Percentage(50),
+                        Constraint::Min(1),
+                        Constraint::Length(16),
                     ].as_ref()
                 )
                 .split(chunks[2]);
 
+            let mode_text = editor.mode_status().map(|status| format!("{} \\\\ ", status)).unwrap_or_default();
+            let branch_text = editor.git_branch().map(|branch| format!("\\\\ {} ", branch)).unwrap_or_default();
+            let cursors_text = if editor.cursor_count() > 1 { format!("\\\\ {} cursors ", editor.cursor_count()) } else { String::new() };
+            let diagnostic_text = editor.diagnostic_at_cursor().map(|message| format!("\\\\ {} ", message)).unwrap_or_default();
+            let status_text = editor.status_message().map(|message| format!("\\\\ {} ", message)).unwrap_or_default();
             let saved_text = if !editor.is_dirty() && editor.seconds_since_save().unwrap_or(9999) < 1 { "\\\\ saved!" } else { "" };
-            let cursor_pos_text = Paragraph::new(Spans::from(Span::from(format!("[{}] \\\\ ({}:{}) {}", editor.path().unwrap_or(&"unsaved".to_string()), cursor_pos.0, cursor_pos.1, saved_text))))
+            let footer_text = match &editor.diff_view {
+                Some(view) => view.status(),
+                None => format!("{}[{}] {}\\\\ ({}) {}{}{}{}", mode_text, editor.path().unwrap_or(&"unsaved".to_string()), branch_text, editor.position_text(), cursors_text, status_text, diagnostic_text, saved_text),
+            };
+            let cursor_pos_text = Paragraph::new(Spans::from(Span::from(footer_text)))
                 .style(style::footer_style());
             f.render_widget(cursor_pos_text, footer[0]);
 
@@ -94,13 +384,13 @@ fn main() -> Result<(), io::Error> {
             f.render_widget(rocket_text, footer[1]);
 
             {
-                let stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
-                if stack.len() > 0 {
-                    let popup = &stack.last().unwrap();
+                let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                if let Some(popup) = stack.last_mut() {
+                    let content = popup.content();
+                    let lines = content.lines().count().max(1);
+                    let h = 3 + lines;
 
-                    let h = 3 + popup.content().lines().count().max(1);
-
-                    let popup_rect = util::centered_rect_set(52, h as u16, f.size());
+                    let popup_rect = util::centered_rect_set(POPUP_WIDTH, h.min(u16::MAX as usize) as u16, f.size());
                     let popup_layout = Layout::default()
                         .direction(Direction::Vertical)
                         .margin(0)
@@ -113,26 +403,28 @@ fn main() -> Result<(), io::Error> {
                         )
                         .split(popup_rect);
 
-                    let popup_header = Paragraph::new(popup.title()).style(style::popup_style(true));
+                    //Content taller than the terminal scrolls, the title says so
+                    let visible = popup_layout[1].height as usize;
+                    popup.scroll = popup.scroll.min(lines.saturating_sub(visible));
+                    let title = if lines > visible {
+                        format!("{} // up/down to scroll, {}/{}", popup.title(), popup.scroll + visible, lines)
+                    } else {
+                        popup.title().to_string()
+                    };
+
+                    f.render_widget(Clear, popup_rect);
+                    let popup_header = Paragraph::new(title).style(style::popup_style(true));
                     f.render_widget(popup_header, popup_layout[0]);
-                    let popup_content = Paragraph::new(popup.content()).style(style::popup_style(false));
+                    let popup_content = Paragraph::new(content).style(style::popup_style(false)).scroll((popup.scroll.min(u16::MAX as usize) as u16, 0));
                     f.render_widget(popup_content, popup_layout[1]);
 
-                    let button_spacing = 5;
-                    let button_perc = ((1f32 / popup.buttons.len() as f32) * 100f32) as u16 - ((1f32 / (popup.buttons.len()-1) as f32) * 2.5f32) as u16; //Adds 5 percent spacing to the calculation
-                    let button_constraints: Vec<Constraint> = (0..popup.buttons.len()*2-1).enumerate().map(|(i, _)| if i%2==0 { Constraint::Percentage(button_perc) } else { Constraint::Percentage(button_spacing) }).collect();
-
                     f.render_widget(Block::default().style(style::popup_style(false)), popup_layout[2]);
 
-                    let popup_button_layout = Layout::default()
-                        .direction(Direction::Horizontal)
-                        .horizontal_margin(button_spacing)
-                        .constraints(button_constraints)
-                        .split(popup_layout[2]);
-
-                    for (i, button) in popup.buttons.iter().enumerate() {
+                    let button_rects = util::button_rects(popup.buttons.len(), 5, 2, popup_layout[2]);
+                    for (i, (button, rect)) in popup.buttons.iter().zip(button_rects).enumerate() {
                         let button_widget = Paragraph::new(button.get_text()).style(style::button_style(i == popup.button_idx)).alignment(Alignment::Center);
-                        f.render_widget(button_widget, popup_button_layout[i*2]);
+                        f.render_widget(button_widget, rect);
+                        hit_areas.buttons.push(rect);
                     }
                 }
             }
@@ -141,14 +433,40 @@ fn main() -> Result<(), io::Error> {
         if poll(Duration::from_millis(50))? {
             match read()? {
                 Event::Key(key) => {
-                    if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) {
+                    editor.register_input();
+                    let recent_file_key = CONFIG.is_recent_file_key(&key);
+                    if !recent_file_key {
+                        editor.end_recent_walk();
+                    }
+                    let terminal_key = editor.terminal_focused
+                        && !(key.modifiers.contains(crossterm::event::KeyModifiers::ALT) && key.code == KeyCode::Char('t'))
+                        && POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!").is_empty();
+                    if let Some(pane) = editor.terminal.as_mut().filter(|_| terminal_key) {
+                        //Everything but switching the focus back goes to the shell
+                        pane.handle_key(key);
+                    } else if recent_file_key {
+                        editor.switch_to_recent_file();
+                    } else if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) {
                         match key.code {
-                            KeyCode::Char('q') => break 'main,
-                            KeyCode::Char('r') => {}, //TODO: Command runner
+                            KeyCode::Char('q') => {
+                                let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                //Asking again when the question is already up would just be in the way
+                                if editor.dirty_count() == 0 || stack.last().is_some_and(Popup::is_quit_prompt) {
+                                    break 'main;
+                                }
+                                stack.push(Popup::from_kind(PopupKind::QuitUnsaved(editor.dirty_count())));
+                            },
+                            KeyCode::Char('r') => {
+                                let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                stack.push(Popup::from_kind(PopupKind::Command(String::new())));
+                            },
                             KeyCode::Char('s') => {
-                                if let Err(err) = editor.save_file() {
+                                if editor.changed_on_disk() {
                                     let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
-                                    stack.push(Popup::from_kind(PopupKind::IOError(err.to_string())));
+                                    stack.push(Popup::from_kind(PopupKind::OverwriteFile(editor.path().cloned().unwrap_or_default())));
+                                } else if let Err(err) = editor.save_file() {
+                                    let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                    stack.push(Popup::from_kind(PopupKind::save_error(err, editor.path())));
                                 }
                             },
                             KeyCode::Char('o') => {
@@ -163,33 +481,150 @@ fn main() -> Result<(), io::Error> {
                                 let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
                                 stack.push(Popup::from_kind(PopupKind::SaveFile(String::new())));
                             },
+                            KeyCode::Char('f') => {
+                                let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                stack.push(Popup::from_kind(PopupKind::Search(String::new(), None)));
+                            },
+                            KeyCode::Char('d') => editor.add_next_occurrence(),
+                            //Terminals send ctrl + space as a null byte, which comes in as ctrl + ' '
+                            KeyCode::Char(' ') => editor.trigger_completion(),
+                            //Most terminals send ctrl + / as ctrl + 7
+                            KeyCode::Char('/') | KeyCode::Char('7') => {
+                                if let Err(err) = editor.toggle_comment() {
+                                    let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                    stack.push(Popup::from_kind(PopupKind::IOError(err.to_string())));
+                                }
+                            },
+                            KeyCode::Char('u') => editor.undo(),
+                            KeyCode::Char('y') => editor.redo(),
+                            KeyCode::Char('z') => suspend(terminal)?,
                             KeyCode::Backspace => editor.ctrl_backspace(),
+                            KeyCode::Delete => editor.ctrl_delete(),
+                            KeyCode::Left => editor.move_word(false, key.modifiers.contains(crossterm::event::KeyModifiers::SHIFT)),
+                            KeyCode::Right => editor.move_word(true, key.modifiers.contains(crossterm::event::KeyModifiers::SHIFT)),
                             _ => {}
                         }
                     } else if key.modifiers.contains(crossterm::event::KeyModifiers::ALT) {
                         match key.code {
                             KeyCode::Char('u') => editor.decrement_file_idx(), //TODO: Bad shortcut
                             KeyCode::Char('i') => editor.increment_file_idx(), //TODO: Bad shortcut
+                            KeyCode::Left => editor.move_tab(-1),
+                            KeyCode::Right => editor.move_tab(1),
+                            KeyCode::Up if key.modifiers.contains(crossterm::event::KeyModifiers::SHIFT) => editor.add_cursor_vertically(-1),
+                            KeyCode::Down if key.modifiers.contains(crossterm::event::KeyModifiers::SHIFT) => editor.add_cursor_vertically(1),
+                            KeyCode::Up => editor.move_lines(-1),
+                            KeyCode::Down => editor.move_lines(1),
+                            KeyCode::Char('d') => editor.duplicate_lines(),
+                            KeyCode::Char('k') => editor.delete_lines(),
+                            KeyCode::Char('j') => editor.join_lines(),
+                            KeyCode::Char('m') => editor.jump_to_matching_bracket(),
+                            KeyCode::Char('/') => {
+                                if let Err(err) = editor.toggle_comment() {
+                                    let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                    stack.push(Popup::from_kind(PopupKind::IOError(err.to_string())));
+                                }
+                            },
+                            KeyCode::Char(c @ ('h' | 'g' | 'r' | 'f' | 'b' | 't')) => {
+                                let result = match c {
+                                    't' => editor.toggle_terminal(),
+                                    'h' => editor.hover(),
+                                    'g' => editor.goto_definition(),
+                                    'f' => editor.format_buffer(),
+                                    'b' => editor.blame_line(),
+                                    _ => editor.find_references(),
+                                };
+                                if let Err(err) = result {
+                                    let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                    stack.push(Popup::from_kind(PopupKind::IOError(err.to_string())));
+                                }
+                            },
                             _ => {}
                         }
-                    } else if POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!").len() > 0 {
+                    } else if key.code == KeyCode::F(2) {
+                        let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                        stack.push(Popup::from_kind(PopupKind::Command("rename ".to_string())));
+                    } else if !POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!").is_empty() {
                         let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
-                        if stack.len() > 0 {
-                            if stack.last_mut().unwrap().handle_key(key, &mut editor) {
-                                stack.pop();
+                        if !stack.is_empty() && stack.last_mut().unwrap().handle_key(key, editor) {
+                            stack.pop();
+                        }
+                    } else if let Some(view) = &mut editor.diff_view {
+                        if view.handle_key(key) {
+                            if let Some(kind) = editor.close_diff_view() {
+                                let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                                stack.push(Popup::from_kind(kind));
                             }
                         }
                     } else {
                         editor.handle_key(key);
                     }
+                    if editor.quit_requested() {
+                        break 'main;
+                    }
+                    editor.scroll_to_cursor();
+                },
+                Event::Paste(text) => {
+                    editor.register_input();
+                    let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                    match stack.last_mut() {
+                        Some(popup) => popup.paste(&text),
+                        None if editor.terminal_focused => {
+                            if let Some(pane) = &mut editor.terminal {
+                                pane.paste(&text);
+                            }
+                        },
+                        None if editor.diff_view.is_none() => editor.paste(&text),
+                        None => {},
+                    }
+                    editor.scroll_to_cursor();
                 },
+                Event::Mouse(mouse) => mouse_state.handle(mouse, editor, &hit_areas),
+                Event::Resize(width, height) => {
+                    editor.set_view_size(width, height.saturating_sub(2));
+                    editor.scroll_to_cursor();
+                },
+                Event::FocusLost if CONFIG.autosave_on_focus_lost => editor.autosave_all(),
                 _ => {},
             }
         }
-    }
 
-    crossterm::terminal::disable_raw_mode()?;
-    execute!(io::stdout(), crossterm::style::ResetColor, crossterm::cursor::Show, crossterm::terminal::LeaveAlternateScreen)?;
+        if suspend_requested.swap(false, Ordering::Relaxed) {
+            suspend(terminal)?;
+        }
+
+        if editor.take_sudo_save_request() {
+            if let Err(err) = with_terminal_released(terminal, || editor.save_with_sudo())? {
+                let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+                stack.push(Popup::from_kind(PopupKind::IOError(err.to_string())));
+            }
+        }
+
+        editor.track_focus();
+        editor.autosave_if_idle();
+
+        editor.update_swap_files();
+
+        editor.check_git();
+
+        editor.check_terminal();
+
+        editor.sync_lsp();
+        let notices = editor.poll_lsp();
+        if !notices.is_empty() {
+            let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+            for kind in notices {
+                stack.push(Popup::from_kind(kind));
+            }
+        }
+
+        let conflicts = editor.check_disk_changes();
+        if !conflicts.is_empty() {
+            let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+            for path in conflicts {
+                stack.push(Popup::from_kind(PopupKind::FileChanged(path)));
+            }
+        }
+    }
 
     Ok(())
 }
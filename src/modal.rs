@@ -0,0 +1,932 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::{EditKind, Editor, File};
+use crate::indent;
+use crate::pairs;
+use crate::word::{char_class, Class};
+
+/// Position in a file as (grapheme, line), like the cursor
+type Pos = (u16, u16);
+
+/// Counts are clamped to this, so a typo like `99999999p` doesn't exhaust memory
+const MAX_COUNT: usize = 9999;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `w`
+    WordStart,
+    /// `e`
+    WordEnd,
+    /// `b`
+    WordBack,
+    /// `0`
+    LineStart,
+    /// `^`
+    FirstNonBlank,
+    /// `$`
+    LineEnd,
+    /// `gg`
+    FileStart,
+    /// `G`
+    FileEnd,
+    /// `f`
+    Find(char),
+    /// `t`
+    Till(char),
+    /// `F`
+    FindBack(char),
+    /// `T`
+    TillBack(char),
+    /// `%`
+    MatchBracket,
+}
+
+/// How the text between the cursor and the end of a motion is covered by an operator
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Up to but not including the end
+    Exclusive,
+    /// Including the character at the end
+    Inclusive,
+    /// Whole lines
+    Linewise,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Motion(Motion),
+    /// The operator repeated, like `dd`
+    Line,
+    /// A text object like `iw`, true for the inner variant
+    Object(bool, char),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Move(Motion),
+    Operate(Operator, Target),
+    /// `iw` and friends in visual mode
+    SelectObject(bool, char),
+    /// Any other single key command
+    Key(char),
+}
+
+enum Parse {
+    Incomplete,
+    Invalid,
+    /// Count (1 if none was typed), whether a count was typed and the command
+    Done(usize, bool, Command),
+}
+
+/// Text being operated on
+#[derive(Clone, Copy)]
+enum Region {
+    /// From start up to but not including end
+    Chars(Pos, Pos),
+    /// First and last line
+    Lines(u16, u16),
+}
+
+/// Text yanked or deleted, put back with `p` and `P`
+#[derive(Default)]
+struct Register {
+    text: String,
+    linewise: bool,
+}
+
+/// Vim style modal editing, enabled with `modal = true` in the config.
+/// Keys typed in normal and visual mode are collected until they form a complete command,
+/// insert mode edits like the regular modeless editor.
+pub struct Modal {
+    mode: Mode,
+    /// Keys of the command being typed
+    pending: Vec<char>,
+    register: Register,
+    /// Keys of the command being typed, and of the insert mode it started
+    recording: Vec<KeyEvent>,
+    /// The current insert mode was started by a change, so its keys belong to `recording`
+    recording_insert: bool,
+    /// Keys of the last change, replayed by `.`
+    last_change: Vec<KeyEvent>,
+    replaying: bool,
+}
+
+impl Modal {
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            register: Register::default(),
+            recording: Vec::new(),
+            recording_insert: false,
+            last_change: Vec::new(),
+            replaying: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Mode name for the footer, followed by the keys of an unfinished command
+    pub fn status(&self) -> String {
+        let pending: String = self.pending.iter().collect();
+        if pending.is_empty() {
+            self.mode.name().to_string()
+        } else {
+            format!("{} {}", self.mode.name(), pending)
+        }
+    }
+
+    /// Selected range to highlight in visual mode, as (start, end) with the end excluded
+    pub fn visual_range(&self, file: &File) -> Option<(Pos, Pos)> {
+        match visual_region(self.mode, file)? {
+            Region::Chars(start, end) => Some((start, end)),
+            Region::Lines(first, last) => Some(((0, first), (line_len(file, last), last))),
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent, editor: &mut Editor) {
+        if self.mode == Mode::Insert {
+            if key.code == KeyCode::Esc {
+                self.exit_insert(editor);
+            } else {
+                if self.recording_insert {
+                    self.recording.push(key);
+                }
+                editor.edit_key(key);
+            }
+            return;
+        }
+
+        let c = match key.code {
+            KeyCode::Esc => {
+                self.pending.clear();
+                self.recording.clear();
+                if editor.clear_extra_cursors() {
+                    editor.update_styled_text();
+                }
+                if self.mode != Mode::Normal {
+                    self.set_mode(Mode::Normal, editor);
+                }
+                return;
+            },
+            KeyCode::Char(c) => c,
+            KeyCode::Left | KeyCode::Backspace => 'h',
+            KeyCode::Right => 'l',
+            KeyCode::Up => 'k',
+            KeyCode::Down | KeyCode::Enter => 'j',
+            KeyCode::Home => '0',
+            KeyCode::End => '$',
+            KeyCode::Delete => 'x',
+            _ => return,
+        };
+        if self.pending.is_empty() {
+            self.recording.clear();
+        }
+        self.pending.push(c);
+        self.recording.push(key);
+
+        let visual = self.mode != Mode::Normal;
+        match parse(&self.pending, visual) {
+            Parse::Incomplete => {},
+            Parse::Invalid => {
+                self.pending.clear();
+                self.recording.clear();
+            },
+            Parse::Done(count, counted, command) => {
+                self.pending.clear();
+                let changed = if visual {
+                    self.run_visual(command, count, counted, editor)
+                } else {
+                    self.run(command, count, counted, editor)
+                };
+                if changed && self.mode == Mode::Insert {
+                    self.recording_insert = true;
+                } else if changed && !visual && !self.replaying {
+                    self.last_change = std::mem::take(&mut self.recording);
+                }
+                if self.mode != Mode::Insert {
+                    let file = &mut editor.open_files[editor.cur_file_idx];
+                    file.end_edit_group();
+                    clamp_cursor(file);
+                }
+                editor.update_styled_text();
+            },
+        }
+    }
+
+    fn set_mode(&mut self, mode: Mode, editor: &mut Editor) {
+        let file = &mut editor.open_files[editor.cur_file_idx];
+        match mode {
+            Mode::Visual | Mode::VisualLine if self.mode == Mode::Normal => {
+                let cursor = file.cursor();
+                file.set_selection(cursor, cursor);
+            },
+            Mode::Visual | Mode::VisualLine => {},
+            _ => file.clear_selection(),
+        }
+        self.mode = mode;
+        clamp_cursor(file);
+        editor.update_styled_text();
+    }
+
+    fn exit_insert(&mut self, editor: &mut Editor) {
+        if self.recording_insert {
+            self.recording.push(KeyEvent::from(KeyCode::Esc));
+            self.recording_insert = false;
+            if !self.replaying {
+                self.last_change = std::mem::take(&mut self.recording);
+            }
+        }
+        //Like vim, leaving insert mode puts the cursor on the last inserted character
+        let file = &mut editor.open_files[editor.cur_file_idx];
+        file.end_edit_group();
+        let cursor = file.cursor();
+        file.set_cursor((cursor.0.saturating_sub(1), cursor.1));
+        self.set_mode(Mode::Normal, editor);
+    }
+
+    /// Runs a normal mode command, returning whether it changed the text
+    fn run(&mut self, command: Command, count: usize, counted: bool, editor: &mut Editor) -> bool {
+        if let Command::Key('.') = command {
+            self.repeat(if counted { count } else { 1 }, editor);
+            return false;
+        }
+
+        let file = &mut editor.open_files[editor.cur_file_idx];
+        let cursor = file.cursor();
+        match command {
+            Command::Move(motion) => {
+                if let Some((pos, _)) = apply_motion(&Doc::new(file), cursor, motion, count, counted) {
+                    file.set_cursor(pos);
+                }
+                false
+            },
+            Command::Operate(op, target) => {
+                let region = match target_region(file, cursor, op, target, count, counted) {
+                    Some(region) => region,
+                    None => return false,
+                };
+                self.operate(op, region, editor)
+            },
+            Command::SelectObject(..) => false,
+            Command::Key(c) => self.run_key(c, count, editor),
+        }
+    }
+
+    /// Runs a single key command in normal mode, returning whether it changed the text
+    fn run_key(&mut self, c: char, count: usize, editor: &mut Editor) -> bool {
+        let extension = editor.extension().to_string();
+        let file = &mut editor.open_files[editor.cur_file_idx];
+        let (x, y) = file.cursor();
+        let len = line_len(file, y);
+        //The command and the insert mode it may start are undone as one step
+        if "iaIAoOpPJ".contains(c) && !file.begin_edit(Some(EditKind::Group)) {
+            return false;
+        }
+        match c {
+            'i' => self.mode = Mode::Insert,
+            'a' => {
+                file.set_cursor(((x + 1).min(len), y));
+                self.mode = Mode::Insert;
+            },
+            'I' => {
+                file.set_cursor((first_non_blank(file, y), y));
+                self.mode = Mode::Insert;
+            },
+            'A' => {
+                file.set_cursor((len, y));
+                self.mode = Mode::Insert;
+            },
+            'o' | 'O' => {
+                //The new line is indented like the current one, one level more below a block opener
+                let line = &file.content()[y as usize];
+                let mut new_indent = indent::leading_whitespace(line).to_string();
+                let state = pairs::line_states(&file.content()[..=y as usize], &extension)[y as usize];
+                if c == 'o' && indent::opens_block(line, &extension, state).is_some() {
+                    new_indent.push_str(indent::unit_for(&new_indent));
+                }
+                let x = new_indent.graphemes(true).count() as u16;
+                let at = if c == 'o' { y + 1 } else { y };
+                file.replace_lines(at as usize..at as usize, vec![new_indent]);
+                file.set_cursor((x, at));
+                self.mode = Mode::Insert;
+            },
+            'x' | 'X' => {
+                let count = count.min(u16::MAX as usize) as u16;
+                let (start, end) = if c == 'x' { (x, x.saturating_add(count).min(len)) } else { (x.saturating_sub(count), x) };
+                if start == end {
+                    return false;
+                }
+                return self.operate(Operator::Delete, Region::Chars((start, y), (end, y)), editor);
+            },
+            'D' | 'C' | 'Y' => {
+                let (op, target) = match c {
+                    'D' => (Operator::Delete, Target::Motion(Motion::LineEnd)),
+                    'C' => (Operator::Change, Target::Motion(Motion::LineEnd)),
+                    _ => (Operator::Yank, Target::Line),
+                };
+                return self.run(Command::Operate(op, target), count, false, editor);
+            },
+            's' => return self.run(Command::Operate(Operator::Change, Target::Motion(Motion::Right)), count, false, editor),
+            'S' => return self.run(Command::Operate(Operator::Change, Target::Line), count, false, editor),
+            'p' | 'P' => {
+                if self.register.text.is_empty() {
+                    return false;
+                }
+                self.put(c == 'p', count, file);
+            },
+            'J' => {
+                let lines = file.content().len();
+                for _ in 0..count.max(2) - 1 {
+                    file.join_lines();
+                }
+                return file.content().len() != lines;
+            },
+            'u' => {
+                file.undo();
+                return false;
+            },
+            'v' => {
+                self.set_mode(Mode::Visual, editor);
+                return false;
+            },
+            'V' => {
+                self.set_mode(Mode::VisualLine, editor);
+                return false;
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Runs a command in visual mode, returning whether it changed the text
+    fn run_visual(&mut self, command: Command, count: usize, counted: bool, editor: &mut Editor) -> bool {
+        let file = &mut editor.open_files[editor.cur_file_idx];
+        let cursor = file.cursor();
+        match command {
+            Command::Move(motion) => {
+                if let Some((pos, _)) = apply_motion(&Doc::new(file), cursor, motion, count, counted) {
+                    let anchor = file.selection_anchor().unwrap_or(cursor);
+                    file.set_selection(anchor, pos);
+                }
+                false
+            },
+            Command::SelectObject(inner, object) => {
+                let doc = Doc::new(file);
+                if let Some((start, end)) = text_object(&doc, cursor, inner, object) {
+                    //The cursor sits on the last selected character
+                    let last = doc.prev(end).filter(|&last| (last.1, last.0) >= (start.1, start.0)).unwrap_or(start);
+                    file.set_selection(start, last);
+                    self.mode = Mode::Visual;
+                }
+                false
+            },
+            Command::Key(c) => {
+                let op = match c {
+                    'd' | 'x' => Operator::Delete,
+                    'c' | 's' => Operator::Change,
+                    'y' => Operator::Yank,
+                    'o' => {
+                        if let Some(anchor) = file.selection_anchor() {
+                            file.set_selection(cursor, anchor);
+                        }
+                        return false;
+                    },
+                    'v' | 'V' => {
+                        let mode = if c == 'v' { Mode::Visual } else { Mode::VisualLine };
+                        self.set_mode(if self.mode == mode { Mode::Normal } else { mode }, editor);
+                        return false;
+                    },
+                    _ => return false,
+                };
+                let region = match visual_region(self.mode, file) {
+                    Some(region) => region,
+                    None => return false,
+                };
+                file.clear_selection();
+                self.mode = Mode::Normal;
+                self.operate(op, region, editor)
+            },
+            Command::Operate(..) => false,
+        }
+    }
+
+    /// Applies an operator to a region of the current file, returning whether it changed the text
+    fn operate(&mut self, op: Operator, region: Region, editor: &mut Editor) -> bool {
+        let file = &mut editor.open_files[editor.cur_file_idx];
+        if op != Operator::Yank && !file.begin_edit(Some(EditKind::Group)) {
+            return false;
+        }
+        self.register = match region {
+            Region::Chars(start, end) => Register { text: file.text_range(start, end), linewise: false },
+            Region::Lines(first, last) => Register { text: file.content()[first as usize..=last as usize].join("\n"), linewise: true },
+        };
+
+        match (op, region) {
+            (Operator::Yank, Region::Chars(start, _)) => file.set_cursor(start),
+            (Operator::Yank, Region::Lines(first, _)) => {
+                let x = file.cursor().0;
+                file.set_cursor((x, first));
+            },
+            (_, Region::Chars(start, end)) => {
+                file.set_selection(start, end);
+                file.delete_selection();
+            },
+            (Operator::Delete, Region::Lines(first, last)) => {
+                file.replace_lines(first as usize..last as usize + 1, Vec::new());
+                let y = first.min(file.content().len() as u16 - 1);
+                file.set_cursor((first_non_blank(file, y), y));
+            },
+            (Operator::Change, Region::Lines(first, last)) => {
+                //Keep the indentation of the first line
+                let indent: String = file.content()[first as usize].chars().take_while(|c| c.is_whitespace()).collect();
+                let x = indent.graphemes(true).count() as u16;
+                file.replace_lines(first as usize..last as usize + 1, vec![indent]);
+                file.set_cursor((x, first));
+            },
+        }
+        if op == Operator::Change {
+            self.mode = Mode::Insert;
+        }
+        op != Operator::Yank
+    }
+
+    /// Puts the register after (`p`) or before (`P`) the cursor, `count` times
+    fn put(&mut self, after: bool, count: usize, file: &mut File) {
+        let (x, y) = file.cursor();
+        if self.register.linewise {
+            let lines: Vec<String> = self.register.text.split('\n').map(String::from).collect();
+            let at = if after { y + 1 } else { y };
+            let mut all = Vec::with_capacity(lines.len() * count);
+            for _ in 0..count {
+                all.extend(lines.iter().cloned());
+            }
+            file.replace_lines(at as usize..at as usize, all);
+            file.set_cursor((0, at));
+            let x = first_non_blank(file, at);
+            file.set_cursor((x, at));
+        } else {
+            if after && line_len(file, y) > 0 {
+                file.set_cursor((x + 1, y));
+            }
+            file.insert_text(&self.register.text.repeat(count));
+            //Like vim, the cursor ends up on the last character that was put
+            file.move_cursor(-1, 0);
+        }
+    }
+
+    /// Replays the last change `count` times for `.`
+    fn repeat(&mut self, count: usize, editor: &mut Editor) {
+        if self.replaying {
+            return;
+        }
+        self.replaying = true;
+        let keys = self.last_change.clone();
+        for _ in 0..count {
+            for &key in &keys {
+                self.handle_key(key, editor);
+            }
+        }
+        self.replaying = false;
+    }
+}
+
+impl Default for Modal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses the keys typed so far into a command
+fn parse(keys: &[char], visual: bool) -> Parse {
+    let (count, counted, rest) = parse_count(keys);
+    let c = match rest.first() {
+        Some(&c) => c,
+        None => return Parse::Incomplete,
+    };
+
+    if visual && (c == 'i' || c == 'a') {
+        return match rest.get(1) {
+            Some(&object) => Parse::Done(count, counted, Command::SelectObject(c == 'i', object)),
+            None => Parse::Incomplete,
+        };
+    }
+
+    let op = match c {
+        'd' if !visual => Operator::Delete,
+        'c' if !visual => Operator::Change,
+        'y' if !visual => Operator::Yank,
+        _ => {
+            return match parse_motion(rest) {
+                Ok(Some(motion)) => Parse::Done(count, counted, Command::Move(motion)),
+                Ok(None) => Parse::Incomplete,
+                Err(()) if rest.len() == 1 => Parse::Done(count, counted, Command::Key(c)),
+                Err(()) => Parse::Invalid,
+            };
+        },
+    };
+
+    //Counts before and after the operator multiply, `2d3w` deletes six words
+    let (count2, counted2, rest) = parse_count(&rest[1..]);
+    let count = (count * count2).min(MAX_COUNT);
+    let counted = counted || counted2;
+    match rest {
+        [] => Parse::Incomplete,
+        [o, ..] if *o == c => Parse::Done(count, counted, Command::Operate(op, Target::Line)),
+        ['i', object] | ['a', object] => Parse::Done(count, counted, Command::Operate(op, Target::Object(rest[0] == 'i', *object))),
+        ['i'] | ['a'] => Parse::Incomplete,
+        _ => match parse_motion(rest) {
+            Ok(Some(motion)) => Parse::Done(count, counted, Command::Operate(op, Target::Motion(motion))),
+            Ok(None) => Parse::Incomplete,
+            Err(()) => Parse::Invalid,
+        },
+    }
+}
+
+/// Splits off a leading count, a `0` without digits before it is the `0` motion
+fn parse_count(keys: &[char]) -> (usize, bool, &[char]) {
+    let digits = keys.iter().enumerate().take_while(|&(i, c)| c.is_ascii_digit() && (i > 0 || *c != '0')).count();
+    if digits == 0 {
+        return (1, false, keys);
+    }
+    let count = keys[..digits].iter().fold(0usize, |n, c| n.saturating_mul(10).saturating_add(c.to_digit(10).unwrap() as usize));
+    (count.min(MAX_COUNT), true, &keys[digits..])
+}
+
+/// Parses a motion, `Ok(None)` if more keys are needed and `Err` if the keys aren't a motion
+fn parse_motion(keys: &[char]) -> Result<Option<Motion>, ()> {
+    let motion = match keys[0] {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'k' => Motion::Up,
+        'j' => Motion::Down,
+        'w' => Motion::WordStart,
+        'e' => Motion::WordEnd,
+        'b' => Motion::WordBack,
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'G' => Motion::FileEnd,
+        '%' => Motion::MatchBracket,
+        'g' => return match keys.get(1) {
+            None => Ok(None),
+            Some('g') => Ok(Some(Motion::FileStart)),
+            Some(_) => Err(()),
+        },
+        'f' | 't' | 'F' | 'T' => return Ok(keys.get(1).map(|&c| match keys[0] {
+            'f' => Motion::Find(c),
+            't' => Motion::Till(c),
+            'F' => Motion::FindBack(c),
+            _ => Motion::TillBack(c),
+        })),
+        _ => return Err(()),
+    };
+    Ok(Some(motion))
+}
+
+/// Region an operator covers when applied from `cursor`
+fn target_region(file: &File, cursor: Pos, op: Operator, target: Target, count: usize, counted: bool) -> Option<Region> {
+    let doc = Doc::new(file);
+    match target {
+        Target::Line => {
+            let last = (cursor.1 as usize + count - 1).min(doc.lines.len() - 1) as u16;
+            Some(Region::Lines(cursor.1, last))
+        },
+        Target::Object(inner, object) => {
+            let (start, end) = text_object(&doc, cursor, inner, object)?;
+            Some(Region::Chars(start, end))
+        },
+        Target::Motion(motion) => {
+            //Like vim, `cw` on a word changes just the word, not the space after it
+            let motion = if op == Operator::Change && motion == Motion::WordStart && doc.class(cursor) != Class::Whitespace { Motion::WordEnd } else { motion };
+            let (mut target, kind) = apply_motion(&doc, cursor, motion, count, counted)?;
+            //A `w` that moves onto the next line stops at the end of the current one instead
+            if motion == Motion::WordStart && target.1 > cursor.1 {
+                target = (doc.len(target.1 - 1), target.1 - 1);
+            }
+            let (start, end) = if (target.1, target.0) < (cursor.1, cursor.0) { (target, cursor) } else { (cursor, target) };
+            match kind {
+                Kind::Exclusive => Some(Region::Chars(start, end)),
+                Kind::Inclusive => Some(Region::Chars(start, ((end.0 + 1).min(doc.len(end.1)), end.1))),
+                Kind::Linewise => Some(Region::Lines(start.1, end.1)),
+            }
+        },
+    }
+}
+
+/// What an operator in visual mode works on
+fn visual_region(mode: Mode, file: &File) -> Option<Region> {
+    let cursor = file.cursor();
+    let anchor = file.selection_anchor().unwrap_or(cursor);
+    let (start, end) = if (anchor.1, anchor.0) < (cursor.1, cursor.0) { (anchor, cursor) } else { (cursor, anchor) };
+    match mode {
+        //The character under the cursor is part of the selection, past the end of a line that is the line break
+        Mode::Visual => Some(Region::Chars(start, Doc::new(file).next(end).unwrap_or((line_len(file, end.1), end.1)))),
+        Mode::VisualLine => Some(Region::Lines(start.1, end.1)),
+        _ => None,
+    }
+}
+
+/// Where a motion from `pos` ends up, `None` if it can't move (like `f` without a match)
+fn apply_motion(doc: &Doc, pos: Pos, motion: Motion, count: usize, counted: bool) -> Option<(Pos, Kind)> {
+    let (x, y) = pos;
+    let last_line = doc.lines.len() as u16 - 1;
+    let count16 = count.min(u16::MAX as usize) as u16;
+    let result = match motion {
+        Motion::Left => ((x.saturating_sub(count16), y), Kind::Exclusive),
+        Motion::Right => ((x.saturating_add(count16).min(doc.len(y)), y), Kind::Exclusive),
+        Motion::Up => ((x, y.saturating_sub(count16)), Kind::Linewise),
+        Motion::Down => ((x, y.saturating_add(count16).min(last_line)), Kind::Linewise),
+        Motion::WordStart => ((0..count).fold(pos, |p, _| doc.word_start(p)), Kind::Exclusive),
+        Motion::WordEnd => ((0..count).fold(pos, |p, _| doc.word_end(p)), Kind::Inclusive),
+        Motion::WordBack => ((0..count).fold(pos, |p, _| doc.word_back(p)), Kind::Exclusive),
+        Motion::LineStart => ((0, y), Kind::Exclusive),
+        Motion::FirstNonBlank => ((doc.first_non_blank(y), y), Kind::Exclusive),
+        Motion::LineEnd => {
+            let y = y.saturating_add(count16 - 1).min(last_line);
+            ((doc.len(y).saturating_sub(1), y), Kind::Inclusive)
+        },
+        Motion::FileStart | Motion::FileEnd => {
+            let y = if counted { count16 - 1 } else if motion == Motion::FileStart { 0 } else { last_line };
+            let y = y.min(last_line);
+            ((doc.first_non_blank(y), y), Kind::Linewise)
+        },
+        Motion::Find(c) | Motion::Till(c) => {
+            let line = &doc.lines[y as usize];
+            let found = (x as usize + 1..line.len()).filter(|&i| line[i] == c).nth(count - 1)? as u16;
+            let x = if let Motion::Till(_) = motion { found - 1 } else { found };
+            ((x, y), Kind::Inclusive)
+        },
+        Motion::MatchBracket => {
+            let (content, extension) = (doc.file.content(), doc.file.extension());
+            let (_, target) = pairs::matching_bracket(content, extension, &pairs::line_states(content, extension), pos)?;
+            (target, Kind::Inclusive)
+        },
+        Motion::FindBack(c) | Motion::TillBack(c) => {
+            let line = &doc.lines[y as usize];
+            let found = (0..(x as usize).min(line.len())).rev().filter(|&i| line[i] == c).nth(count - 1)? as u16;
+            let x = if let Motion::TillBack(_) = motion { found + 1 } else { found };
+            ((x, y), Kind::Exclusive)
+        },
+    };
+    Some(result)
+}
+
+/// Range of a text object around `pos` as (start, end) with the end excluded.
+/// Supports words (`w`), brackets (`(`, `[`, `{`, `<` and `b`, `B`) and quotes.
+fn text_object(doc: &Doc, pos: Pos, inner: bool, object: char) -> Option<(Pos, Pos)> {
+    let (open, close) = match object {
+        'w' => return word_object(doc, pos, inner),
+        '"' | '\'' | '`' => return quote_object(doc, pos, inner, object),
+        '(' | ')' | 'b' => ('(', ')'),
+        '[' | ']' => ('[', ']'),
+        '{' | '}' | 'B' => ('{', '}'),
+        '<' | '>' => ('<', '>'),
+        _ => return None,
+    };
+
+    //Walk back to the unmatched opening bracket, then forward to its partner
+    let mut depth = 0;
+    let mut start = pos;
+    loop {
+        match doc.get(start) {
+            Some(c) if c == open => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            },
+            Some(c) if c == close && start != pos => depth += 1,
+            _ => {},
+        }
+        start = doc.prev(start)?;
+    }
+    let mut end = doc.next(start)?;
+    loop {
+        match doc.get(end) {
+            Some(c) if c == close => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            },
+            Some(c) if c == open => depth += 1,
+            _ => {},
+        }
+        end = doc.next(end)?;
+    }
+
+    if inner {
+        Some((doc.next(start)?, end))
+    } else {
+        Some((start, doc.next(end)?))
+    }
+}
+
+fn word_object(doc: &Doc, pos: Pos, inner: bool) -> Option<(Pos, Pos)> {
+    let line = &doc.lines[pos.1 as usize];
+    if line.is_empty() {
+        return None;
+    }
+    let x = (pos.0 as usize).min(line.len() - 1);
+    let class = char_class(line[x]);
+    let mut start = (0..x).rev().take_while(|&i| char_class(line[i]) == class).last().unwrap_or(x);
+    let mut end = (x..line.len()).take_while(|&i| char_class(line[i]) == class).last().unwrap_or(x) + 1;
+    if !inner {
+        //`aw` takes the whitespace after the word, or before it if there is none
+        let trailing = (end..line.len()).take_while(|&i| char_class(line[i]) == Class::Whitespace).count();
+        if trailing > 0 {
+            end += trailing;
+        } else {
+            start -= (0..start).rev().take_while(|&i| char_class(line[i]) == Class::Whitespace).count();
+        }
+    }
+    Some(((start as u16, pos.1), (end as u16, pos.1)))
+}
+
+fn quote_object(doc: &Doc, pos: Pos, inner: bool, quote: char) -> Option<(Pos, Pos)> {
+    let line = &doc.lines[pos.1 as usize];
+    let quotes: Vec<usize> = (0..line.len()).filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != '\\')).collect();
+    let x = pos.0 as usize;
+    //The first pair that ends at or after the cursor, so `ci"` also works from in front of a string
+    let pair = quotes.chunks_exact(2).find(|pair| pair[1] >= x)?;
+    let (open, close) = (pair[0] as u16, pair[1] as u16);
+    if inner {
+        Some(((open + 1, pos.1), (close, pos.1)))
+    } else {
+        Some(((open, pos.1), (close + 1, pos.1)))
+    }
+}
+
+/// In normal and visual mode the cursor sits on a character, never behind the last one
+fn clamp_cursor(file: &mut File) {
+    let (x, y) = file.cursor();
+    let len = line_len(file, y);
+    if x >= len && len > 0 {
+        file.set_cursor((len - 1, y));
+    }
+}
+
+fn line_len(file: &File, y: u16) -> u16 {
+    file.content()[y as usize].graphemes(true).count() as u16
+}
+
+fn first_non_blank(file: &File, y: u16) -> u16 {
+    file.content()[y as usize].graphemes(true).take_while(|g| g.chars().all(char::is_whitespace)).count() as u16
+}
+
+/// The lines of a file as one character per grapheme, which is what the motions walk over.
+/// Every line has one extra position past its last character for the line break.
+struct Doc<'a> {
+    file: &'a File,
+    lines: Vec<Vec<char>>,
+}
+
+impl<'a> Doc<'a> {
+    fn new(file: &'a File) -> Self {
+        let lines = file.content().iter().map(|line| line.graphemes(true).map(|g| g.chars().next().unwrap_or(' ')).collect()).collect();
+        Self { file, lines }
+    }
+
+    fn len(&self, y: u16) -> u16 {
+        self.lines[y as usize].len() as u16
+    }
+
+    /// Character at `pos`, `None` for the line break
+    fn get(&self, pos: Pos) -> Option<char> {
+        self.lines[pos.1 as usize].get(pos.0 as usize).copied()
+    }
+
+    fn class(&self, pos: Pos) -> Class {
+        self.get(pos).map(char_class).unwrap_or(Class::Whitespace)
+    }
+
+    fn is_empty_line(&self, pos: Pos) -> bool {
+        self.lines[pos.1 as usize].is_empty()
+    }
+
+    fn next(&self, (x, y): Pos) -> Option<Pos> {
+        if x < self.len(y) {
+            Some((x + 1, y))
+        } else if (y as usize) + 1 < self.lines.len() {
+            Some((0, y + 1))
+        } else {
+            None
+        }
+    }
+
+    fn prev(&self, (x, y): Pos) -> Option<Pos> {
+        if x > 0 {
+            Some((x - 1, y))
+        } else if y > 0 {
+            Some((self.len(y - 1), y - 1))
+        } else {
+            None
+        }
+    }
+
+    fn first_non_blank(&self, y: u16) -> u16 {
+        self.lines[y as usize].iter().take_while(|c| c.is_whitespace()).count() as u16
+    }
+
+    /// Start of the next word, empty lines count as words
+    fn word_start(&self, start: Pos) -> Pos {
+        let mut pos = start;
+        let class = self.class(pos);
+        if class != Class::Whitespace {
+            while let Some(next) = self.next(pos) {
+                pos = next;
+                if self.class(pos) != class {
+                    break;
+                }
+            }
+        }
+        while self.class(pos) == Class::Whitespace && !(self.is_empty_line(pos) && pos != start) {
+            match self.next(pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        pos
+    }
+
+    /// End of the current or next word
+    fn word_end(&self, start: Pos) -> Pos {
+        let mut pos = match self.next(start) {
+            Some(pos) => pos,
+            None => return start,
+        };
+        while self.class(pos) == Class::Whitespace {
+            match self.next(pos) {
+                Some(next) => pos = next,
+                None => return pos,
+            }
+        }
+        let class = self.class(pos);
+        while let Some(next) = self.next(pos) {
+            if self.class(next) != class {
+                break;
+            }
+            pos = next;
+        }
+        pos
+    }
+
+    /// Start of the current or previous word
+    fn word_back(&self, start: Pos) -> Pos {
+        let mut pos = match self.prev(start) {
+            Some(pos) => pos,
+            None => return start,
+        };
+        while self.class(pos) == Class::Whitespace && !self.is_empty_line(pos) {
+            match self.prev(pos) {
+                Some(prev) => pos = prev,
+                None => return pos,
+            }
+        }
+        let class = self.class(pos);
+        if class == Class::Whitespace {
+            return pos;
+        }
+        while let Some(prev) = self.prev(pos) {
+            if self.class(prev) != class {
+                break;
+            }
+            pos = prev;
+        }
+        pos
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use crossterm::event::{MouseEvent, MouseEventKind, MouseButton};
+use tui::layout::Rect;
+
+use crate::editor::Editor;
+use crate::POPUP_STACK;
+
+/// Clicks closer together than this count as a double or triple click
+const MULTI_CLICK_TIME: Duration = Duration::from_millis(400);
+/// Lines scrolled per mouse wheel step
+const SCROLL_LINES: i16 = 3;
+
+/// Where things ended up on screen during the last draw, so clicks can be mapped back to them
+#[derive(Default)]
+pub struct HitAreas {
+    /// Text view, including the line number gutter
+    pub text: Rect,
+    /// Screen of the terminal pane, if it is open
+    pub terminal: Rect,
+    /// Visible file tabs in the header as (file index, start, end)
+    pub tabs: Vec<(usize, u16, u16)>,
+    /// Buttons of the popup on top of the stack
+    pub buttons: Vec<Rect>,
+}
+
+#[derive(Default)]
+pub struct MouseState {
+    last_click: Option<(Instant, u16, u16)>,
+    clicks: u8,
+}
+
+fn contains(rect: Rect, col: u16, row: u16) -> bool {
+    col >= rect.x && col < rect.x + rect.width && row >= rect.y && row < rect.y + rect.height
+}
+
+impl MouseState {
+    /// Counts consecutive clicks on the same cell
+    fn register_click(&mut self, col: u16, row: u16) -> u8 {
+        self.clicks = match self.last_click {
+            Some((time, c, r)) if c == col && r == row && time.elapsed() < MULTI_CLICK_TIME => self.clicks % 3 + 1,
+            _ => 1,
+        };
+        self.last_click = Some((Instant::now(), col, row));
+        self.clicks
+    }
+
+    pub fn handle(&mut self, event: MouseEvent, editor: &mut Editor, areas: &HitAreas) {
+        let (col, row) = (event.column, event.row);
+
+        {
+            let mut stack = POPUP_STACK.lock().expect("Failed to get lock on POPUP_STACK!");
+            if !stack.is_empty() {
+                //Popups are modal, only their buttons react to the mouse
+                if let MouseEventKind::Down(MouseButton::Left) = event.kind {
+                    if let Some(i) = areas.buttons.iter().position(|&rect| contains(rect, col, row)) {
+                        if stack.last_mut().unwrap().click_button(i, editor) {
+                            stack.pop();
+                        }
+                    }
+                }
+                return;
+            }
+        }
+
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                if row == 0 {
+                    if let Some(&(i, _, _)) = areas.tabs.iter().find(|&&(_, start, end)| col >= start && col < end) {
+                        editor.switch_to_file(i);
+                    }
+                } else if contains(areas.terminal, col, row) {
+                    editor.terminal_focused = true;
+                } else if contains(areas.text, col, row) {
+                    editor.terminal_focused = false;
+                    let clicks = self.register_click(col, row);
+                    editor.click(col - areas.text.x, row - areas.text.y, clicks);
+                }
+            },
+            MouseEventKind::Drag(MouseButton::Left) if contains(areas.text, col, row) => {
+                editor.drag(col - areas.text.x, row - areas.text.y);
+            },
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown if contains(areas.terminal, col, row) => {
+                if let Some(pane) = &mut editor.terminal {
+                    pane.scroll_by(if event.kind == MouseEventKind::ScrollUp { SCROLL_LINES as isize } else { -SCROLL_LINES as isize });
+                }
+            },
+            MouseEventKind::ScrollUp => editor.scroll_by(-SCROLL_LINES),
+            MouseEventKind::ScrollDown => editor.scroll_by(SCROLL_LINES),
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::style::{self, LexState, Lexeme};
+
+/// How far to look for a matching bracket, so a stray bracket in a huge file doesn't scan all of it
+const MAX_SCAN_LINES: usize = 5000;
+
+/// The closing character for an auto-paired opener
+pub fn closer(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        _ => None,
+    }
+}
+
+pub fn is_quote(c: char) -> bool {
+    c == '"' || c == '\''
+}
+
+/// Whether typing `c` should also insert its closer, given the characters around the cursor and
+/// the lexer state there. Nothing is paired inside comments and strings, quotes aren't paired
+/// right after a word (`don't`) and nothing is paired right in front of a word.
+pub fn should_pair(c: char, before: Option<char>, after: Option<char>, state: LexState, extension: &str) -> bool {
+    if closer(c).is_none() || state != LexState::Code {
+        return false;
+    }
+    if after.is_some_and(|a| a.is_alphanumeric() || a == '_') {
+        return false;
+    }
+    if is_quote(c) {
+        //In Rust a single quote is usually a lifetime
+        if c == '\'' && extension == "rs" {
+            return false;
+        }
+        return !before.is_some_and(|b| b.is_alphanumeric() || b == '_' || b == c);
+    }
+    true
+}
+
+/// The states at the start of each line
+pub fn line_states(lines: &[String], extension: &str) -> Vec<LexState> {
+    let mut states = Vec::with_capacity(lines.len());
+    let mut state = LexState::Code;
+    for line in lines {
+        states.push(state);
+        state = style::lex_line(line, extension, state).1;
+    }
+    states
+}
+
+fn bracket_partner(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
+/// Finds the bracket at the cursor, or right before it, and the one matching it.
+/// Brackets in comments and strings are ignored. `states` are the lexer states at the start of each line.
+/// Returns the positions of both brackets as (x, y).
+pub fn matching_bracket(lines: &[String], extension: &str, states: &[LexState], pos: (u16, u16)) -> Option<((u16, u16), (u16, u16))> {
+    let y = pos.1 as usize;
+    let chars = |y: usize| -> Vec<char> { lines[y].graphemes(true).map(|g| g.chars().next().unwrap_or(' ')).collect() };
+    let lexemes = |y: usize| style::lex_line(&lines[y], extension, states.get(y).copied().unwrap_or(LexState::Code)).0;
+
+    let line = chars(y);
+    let line_lexemes = lexemes(y);
+    let x = pos.0 as usize;
+    let candidates = [Some(x), x.checked_sub(1)];
+    let (x, c) = candidates.iter().flatten()
+        .filter(|&&x| x < line.len() && line_lexemes.get(x) == Some(&Lexeme::Code))
+        .map(|&x| (x, line[x]))
+        .find(|&(_, c)| bracket_partner(c).is_some())?;
+    let (partner, forward) = bracket_partner(c)?;
+
+    let mut depth = 0usize;
+    let mut scan_y = y;
+    let (mut scan_line, mut scan_lexemes) = (line, line_lexemes);
+    let mut scan_x = x as isize;
+    for _ in 0..MAX_SCAN_LINES {
+        loop {
+            scan_x += if forward { 1 } else { -1 };
+            if scan_x < 0 || scan_x as usize >= scan_line.len() {
+                break;
+            }
+            let i = scan_x as usize;
+            if scan_lexemes.get(i) != Some(&Lexeme::Code) {
+                continue;
+            }
+            if scan_line[i] == c {
+                depth += 1;
+            } else if scan_line[i] == partner {
+                if depth == 0 {
+                    return Some(((x as u16, pos.1), (i as u16, scan_y as u16)));
+                }
+                depth -= 1;
+            }
+        }
+        if forward {
+            scan_y += 1;
+            if scan_y >= lines.len() {
+                return None;
+            }
+        } else {
+            scan_y = scan_y.checked_sub(1)?;
+        }
+        scan_line = chars(scan_y);
+        scan_lexemes = lexemes(scan_y);
+        scan_x = if forward { -1 } else { scan_line.len() as isize };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(str::to_string).collect()
+    }
+
+    fn find(text: &str, pos: (u16, u16)) -> Option<((u16, u16), (u16, u16))> {
+        let lines = lines(text);
+        let states = line_states(&lines, "rs");
+        matching_bracket(&lines, "rs", &states, pos)
+    }
+
+    #[test]
+    fn pairing() {
+        assert!(should_pair('(', None, None, LexState::Code, "rs"));
+        assert!(should_pair('"', Some(' '), Some(')'), LexState::Code, "rs"));
+        //Not in front of a word, after one for quotes, or in comments and strings
+        assert!(!should_pair('(', None, Some('x'), LexState::Code, "rs"));
+        assert!(!should_pair('\'', Some('n'), None, LexState::Code, "txt"));
+        assert!(should_pair('\'', Some(' '), None, LexState::Code, "txt"));
+        assert!(!should_pair('\'', Some(' '), None, LexState::Code, "rs"));
+        assert!(!should_pair('{', None, None, LexState::BlockComment, "rs"));
+        assert!(!should_pair('x', None, None, LexState::Code, "rs"));
+    }
+
+    #[test]
+    fn nested_brackets() {
+        let text = "f(a, (b), [c]);";
+        //On the bracket, or right after it
+        assert_eq!(find(text, (1, 0)), Some(((1, 0), (13, 0))));
+        assert_eq!(find(text, (14, 0)), Some(((13, 0), (1, 0))));
+        assert_eq!(find(text, (5, 0)), Some(((5, 0), (7, 0))));
+        assert_eq!(find(text, (3, 0)), None);
+    }
+
+    #[test]
+    fn across_lines() {
+        let text = "fn x() {\n    if y {\n    }\n}";
+        assert_eq!(find(text, (7, 0)), Some(((7, 0), (0, 3))));
+        assert_eq!(find(text, (4, 2)), Some(((4, 2), (9, 1))));
+    }
+
+    #[test]
+    fn unbalanced() {
+        assert_eq!(find("f(a, (b)", (1, 0)), None);
+        assert_eq!(find("a))", (2, 0)), None);
+        assert_eq!(find("}\n{", (0, 1)), None);
+    }
+
+    #[test]
+    fn ignores_comments_and_strings() {
+        assert_eq!(find("f(\")\" // )\n)", (1, 0)), Some(((1, 0), (0, 1))));
+        assert_eq!(find("// (\n()", (3, 0)), None);
+        //Graphemes are counted, not bytes
+        assert_eq!(find("(é)", (0, 0)), Some(((0, 0), (2, 0))));
+    }
+}
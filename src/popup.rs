@@ -1,12 +1,23 @@
-use unicode_segmentation::UnicodeSegmentation;
-
 use crossterm::event::{KeyEvent, KeyCode};
 
+/// How many lines of a language server's answer fit in a popup
+const MAX_LINES: usize = 16;
+/// Lines page up and page down scroll a popup by
+const PAGE_LINES: usize = 10;
+
 pub enum PopupButton {
     Ok,
     /// Quirky version of Ok
     IGotIt,
     Cancel,
+    Overwrite,
+    Recover,
+    Discard,
+    Reload,
+    KeepMine,
+    Diff,
+    Quit,
+    Sudo,
 }
 
 impl PopupButton {
@@ -15,6 +26,14 @@ impl PopupButton {
             Self::Ok => "okay",
             Self::IGotIt => "i got it!",
             Self::Cancel => "cancel",
+            Self::Overwrite => "overwrite",
+            Self::Recover => "recover",
+            Self::Discard => "discard",
+            Self::Reload => "reload",
+            Self::KeepMine => "keep mine",
+            Self::Diff => "diff",
+            Self::Quit => "quit",
+            Self::Sudo => "sudo",
         }
     }
 }
@@ -22,13 +41,38 @@ impl PopupButton {
 #[non_exhaustive]
 pub enum PopupKind {
     Help,
+    #[allow(dead_code)]
     Dialogue(String),
     SaveFile(String),
     LoadFile(String),
     IOError(String),
+    /// The file at this path changed on disk since it was loaded, ask before clobbering it
+    OverwriteFile(String),
+    /// A swap file newer than this file was found, probably left behind by a crash
+    RecoverSwap(String),
+    /// This file was changed by another program while we had unsaved changes
+    FileChanged(String),
+    /// Command runner input
+    Command(String),
+    /// Something a language server told us, with a title
+    Info(String, String),
+    /// Text to put a cursor on every match of, and which search history entry it came from while going through them
+    Search(String, Option<usize>),
+    /// Quitting would throw away the unsaved changes of this many files
+    QuitUnsaved(usize),
+    /// We aren't allowed to write the current file at this path, offer saving it with `sudo_command`
+    SudoSave(String),
 }
 
 impl PopupKind {
+    /// Popup for a failed save of the current file, offering to save with sudo if permission was denied
+    pub fn save_error(err: std::io::Error, path: Option<&String>) -> Self {
+        match path {
+            Some(path) if err.kind() == std::io::ErrorKind::PermissionDenied => Self::SudoSave(path.clone()),
+            _ => Self::IOError(err.to_string()),
+        }
+    }
+
     pub fn get_buttons(&self) -> Vec<PopupButton> {
         match self {
             Self::Help => vec![PopupButton::IGotIt],
@@ -36,7 +80,14 @@ impl PopupKind {
             Self::SaveFile(_) => vec![PopupButton::Cancel, PopupButton::Ok],
             Self::LoadFile(_) => vec![PopupButton::Cancel, PopupButton::Ok],
             Self::IOError(_) => vec![PopupButton::Ok],
-            _ => vec![],
+            Self::OverwriteFile(_) => vec![PopupButton::Cancel, PopupButton::Overwrite],
+            Self::RecoverSwap(_) => vec![PopupButton::Discard, PopupButton::Recover],
+            Self::FileChanged(_) => vec![PopupButton::Reload, PopupButton::KeepMine, PopupButton::Diff],
+            Self::Command(_) => vec![PopupButton::Cancel, PopupButton::Ok],
+            Self::Search(..) => vec![PopupButton::Cancel, PopupButton::Ok],
+            Self::Info(..) => vec![PopupButton::Ok],
+            Self::QuitUnsaved(_) => vec![PopupButton::Cancel, PopupButton::Diff, PopupButton::Quit],
+            Self::SudoSave(_) => vec![PopupButton::Cancel, PopupButton::Sudo],
         }
     }
 
@@ -47,39 +98,103 @@ impl PopupKind {
             Self::SaveFile(_) => "save file",
             Self::LoadFile(_) => "load file",
             Self::IOError(_) => "io error",
+            Self::OverwriteFile(_) => "file changed on disk",
+            Self::RecoverSwap(_) => "recover unsaved changes",
+            Self::FileChanged(_) => "file changed on disk",
+            Self::Command(_) => "run command",
+            Self::Search(..) => "select matches",
+            Self::Info(title, _) => title,
+            Self::QuitUnsaved(_) => "unsaved changes",
+            Self::SudoSave(_) => "permission denied",
         }
     }
 
     pub fn content(&self) -> String {
         match self {
             Self::Help => {
-"ctrl + h // this menu   |   alt + u  // next file
-ctrl + q // quit        |   alt + i  // next file
-ctrl + s // save        |   ctrl + w // close file
-ctrl + t // save as     |   ctrl + k // goto file
-ctrl + o // open file   |".to_string()
+"ctrl + h // this menu     alt + u // previous file
+ctrl + q // quit          alt + i // next file
+ctrl + s // save          ctrl + w // close file
+ctrl + t // save as       ctrl + k // goto file
+ctrl + o // open file     ctrl + r // run command
+ctrl + u // undo          ctrl + y // redo
+ctrl + d // next match
+ctrl + f // cursor on every match
+ctrl + tab // recent file, again for older ones
+  (many terminals send a plain tab for ctrl + tab,
+  set e.g. recent_file_key = alt+` in the config)
+alt + left/right // move tab
+alt + up/down // move lines
+alt + shift + up/down // add cursor
+alt + d // duplicate lines
+alt + k // delete lines   alt + j // join lines
+tab // indent             shift + tab // dedent
+alt + m // jump to matching bracket
+ctrl + / or alt + / // toggle comment
+ctrl + space // complete, tab or enter accepts
+alt + h // hover          alt + g // definition
+alt + r // references     f2 // rename symbol
+alt + f // format file    alt + b // blame line
+alt + t // terminal pane, again to switch focus
+ctrl + z // suspend to the shell
+
+commands, run with ctrl + r:
+readonly // toggle read-only
+sudo save // save as root
+session save/load <name> // named sessions
+sort [-i] [-n], uniq, reverse, comment
+  // change the selected lines
+revert hunk // undo the change at the cursor
+  since the last commit
+diff [disk|head|<tab>] // compare versions
+changes // unsaved changes of all files
+!<cmd> // filter selection or file through cmd
+r !<cmd> // insert output of cmd at the cursor
+goto <n> // line, or byte offset in a binary
+  file (0x for hex)".to_string()
             }
             Self::Dialogue(s) => s.clone(),
             Self::SaveFile(s) => format!("path >> {}", &s),
             Self::LoadFile(s) => format!("path >> {}", &s),
             Self::IOError(s) => s.clone(),
+            Self::OverwriteFile(s) => format!("{} was modified since it was loaded.\nsaving will discard those changes.", s),
+            Self::Command(s) => format!(">> {}", &s),
+            Self::Search(s, _) => format!("find >> {}", &s),
+            Self::RecoverSwap(s) => format!("found unsaved changes to {}\nfrom a session that didn't exit cleanly.", s),
+            Self::FileChanged(s) => format!("{} was changed by another program,\nbut you have unsaved changes.", s),
+            Self::Info(_, s) => truncate_lines(s),
+            Self::QuitUnsaved(1) => "1 file has unsaved changes.\nquit anyway?".to_string(),
+            Self::QuitUnsaved(n) => format!("{} files have unsaved changes.\nquit anyway?", n),
+            Self::SudoSave(s) => format!("you aren't allowed to write {}.\nsave it with `{}`?", s, crate::config::CONFIG.sudo_command),
         }
     }
 }
 
+/// The first lines of `text` that fit in a popup, with `...` if there are more
+fn truncate_lines(text: &str) -> String {
+    let mut lines: Vec<&str> = text.lines().take(MAX_LINES).collect();
+    if text.lines().count() > MAX_LINES {
+        lines.push("...");
+    }
+    lines.join("\n")
+}
+
 pub struct Popup {
     kind: PopupKind,
     pub buttons: Vec<PopupButton>,
     pub button_idx: usize,
+    /// Lines of the content scrolled past, for content taller than the terminal. Kept in range when drawn.
+    pub scroll: usize,
 }
 
 impl Popup {
     pub fn from_kind(kind: PopupKind) -> Self {
         let buttons = kind.get_buttons();
         Self {
-            kind: kind,
-            buttons: buttons,
+            kind,
+            buttons,
             button_idx: 0,
+            scroll: 0,
         }
     }
 
@@ -91,36 +206,146 @@ impl Popup {
         self.kind.content()
     }
 
+    /// Asking whether to quit with unsaved changes, pressing ctrl + q again quits
+    pub fn is_quit_prompt(&self) -> bool {
+        matches!(self.kind, PopupKind::QuitUnsaved(_))
+    }
+
+    /// Closes the popup on success, or turns it into an error popup
+    fn close_or_error(&mut self, result: Result<(), std::io::Error>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                *self = Popup::from_kind(PopupKind::IOError(err.to_string()));
+                false
+            },
+        }
+    }
+
     fn handle_enter(&mut self, editor: &mut crate::editor::Editor) -> bool {
         match &self.kind {
-            PopupKind::Help | PopupKind::Dialogue(_) | PopupKind::IOError(_) => return true, //Only has an Ok button, and needs no logic. Just close it
+            PopupKind::Help | PopupKind::Dialogue(_) | PopupKind::IOError(_) | PopupKind::Info(..) => true, //Only has an Ok button, and needs no logic. Just close it
             PopupKind::SaveFile(path) => {
                 match self.buttons[self.button_idx] {
                     PopupButton::Ok => {
-                        if let Err(err) = editor.save_file_to_path(path.to_string()) {
-                            *self = Popup::from_kind(PopupKind::IOError(err.to_string()));
+                        if editor.path() == Some(path) && editor.changed_on_disk() {
+                            *self = Popup::from_kind(PopupKind::OverwriteFile(path.to_string()));
                             return false;
                         }
-                        return true;
+                        let result = editor.save_file_to_path(path.to_string());
+                        self.close_or_error(result)
                     },
-                    _ => return true,
-                };
+                    _ => true,
+                }
+            },
+            PopupKind::OverwriteFile(path) => {
+                match self.buttons[self.button_idx] {
+                    PopupButton::Overwrite => {
+                        let path = path.to_string();
+                        match editor.save_file_to_path(path.clone()) {
+                            Ok(()) => true,
+                            Err(err) => {
+                                *self = Popup::from_kind(PopupKind::save_error(err, Some(&path)));
+                                false
+                            },
+                        }
+                    },
+                    _ => true,
+                }
+            },
+            PopupKind::SudoSave(_) => {
+                //The main loop does the saving, it has to hand over the terminal in case of a password prompt
+                if let PopupButton::Sudo = self.buttons[self.button_idx] {
+                    editor.request_sudo_save();
+                }
+                true
             },
             PopupKind::LoadFile(path) => {
                 match self.buttons[self.button_idx] {
                     PopupButton::Ok => {
-                        if let Err(err) = editor.load_file_from_path(path.to_string()) {
+                        let path = path.to_string();
+                        let open_count = editor.open_files.len();
+                        if let Err(err) = editor.load_file_from_path(path.clone()) {
                             *self = Popup::from_kind(PopupKind::IOError(err.to_string()));
                             return false;
                         }
-                        return true;
+                        //Only freshly opened files can have a swap file from another session
+                        if editor.open_files.len() > open_count && editor.files_with_newer_swap().contains(&path) {
+                            *self = Popup::from_kind(PopupKind::RecoverSwap(path));
+                            return false;
+                        }
+                        true
+                    },
+                    _ => true,
+                }
+            },
+            PopupKind::FileChanged(path) => {
+                let path = path.to_string();
+                let result = match self.buttons[self.button_idx] {
+                    PopupButton::Reload => editor.reload_file(&path),
+                    PopupButton::KeepMine => editor.keep_buffer(&path),
+                    //The question comes back once the diff is closed, so the user can still decide
+                    _ => editor.show_disk_diff(&path, Some(PopupKind::FileChanged(path.clone()))),
+                };
+                self.close_or_error(result)
+            },
+            PopupKind::QuitUnsaved(count) => {
+                match self.buttons[self.button_idx] {
+                    PopupButton::Quit => {
+                        editor.request_quit();
+                        true
+                    },
+                    PopupButton::Diff => {
+                        let result = editor.show_unsaved_changes(Some(PopupKind::QuitUnsaved(*count)));
+                        self.close_or_error(result)
+                    },
+                    _ => true,
+                }
+            },
+            PopupKind::Command(input) => {
+                match self.buttons[self.button_idx] {
+                    PopupButton::Ok => {
+                        let input = input.to_string();
+                        let result = crate::command::run(&input, editor);
+                        self.close_or_error(result)
+                    },
+                    _ => true,
+                }
+            },
+            PopupKind::Search(needle, _) => {
+                match self.buttons[self.button_idx] {
+                    PopupButton::Ok => {
+                        let needle = needle.to_string();
+                        let result = editor.select_all_matches(&needle);
+                        self.close_or_error(result)
                     },
-                    _ => return true,
+                    _ => true,
+                }
+            },
+            PopupKind::RecoverSwap(path) => {
+                let result = match self.buttons[self.button_idx] {
+                    PopupButton::Recover => editor.recover_swap(path),
+                    _ => editor.discard_swap(path),
                 };
+                self.close_or_error(result)
             },
-            _ => {},
         }
-        false
+    }
+
+    /// Activates button `idx`, returns true if the popup should be closed
+    pub fn click_button(&mut self, idx: usize, editor: &mut crate::editor::Editor) -> bool {
+        if idx >= self.buttons.len() {
+            return false;
+        }
+        self.button_idx = idx;
+        self.handle_enter(editor)
+    }
+
+    /// Types pasted text into popups with an input, line breaks aren't part of any input
+    pub fn paste(&mut self, text: &str) {
+        if let PopupKind::SaveFile(input) | PopupKind::LoadFile(input) | PopupKind::Command(input) | PopupKind::Search(input, _) = &mut self.kind {
+            input.extend(text.chars().filter(|c| !c.is_control()));
+        }
     }
 
     pub fn handle_key(&mut self, key: KeyEvent, editor: &mut crate::editor::Editor) -> bool {
@@ -130,9 +355,29 @@ impl Popup {
             KeyCode::Enter => {
                 return self.handle_enter(editor);
             },
+            KeyCode::Up | KeyCode::Down => {
+                if let PopupKind::Search(needle, idx) = &mut self.kind {
+                    //Up goes back in the history, down forward again and past the newest entry to an empty input
+                    let history = &editor.search_history;
+                    let next = match (key.code, *idx) {
+                        (KeyCode::Up, None) => history.len().checked_sub(1),
+                        (KeyCode::Up, Some(i)) => Some(i.saturating_sub(1)),
+                        (_, Some(i)) if i + 1 < history.len() => Some(i + 1),
+                        _ => None,
+                    };
+                    *needle = next.map(|i| history[i].clone()).unwrap_or_default();
+                    *idx = next;
+                } else if key.code == KeyCode::Up {
+                    self.scroll = self.scroll.saturating_sub(1);
+                } else {
+                    self.scroll += 1;
+                }
+            },
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(PAGE_LINES),
+            KeyCode::PageDown => self.scroll += PAGE_LINES,
             KeyCode::Char(c) => {
                 match &mut self.kind {
-                    PopupKind::SaveFile(path) | PopupKind::LoadFile(path) => {
+                    PopupKind::SaveFile(path) | PopupKind::LoadFile(path) | PopupKind::Command(path) | PopupKind::Search(path, _) => {
                         path.push(c);
                     },
                     _ => {},
@@ -140,9 +385,8 @@ impl Popup {
             },
             KeyCode::Backspace => {
                 match &mut self.kind {
-                    PopupKind::SaveFile(path) | PopupKind::LoadFile(path) => {
-                        let new_path: String = path[..].graphemes(true).take(path.len()-1).collect();
-                        *path = new_path;
+                    PopupKind::SaveFile(path) | PopupKind::LoadFile(path) | PopupKind::Command(path) | PopupKind::Search(path, _) => {
+                        path.pop();
                     },
                     _ => {},
                 }
@@ -0,0 +1,131 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::editor::{Editor, File};
+
+/// An open file as remembered by a session
+pub struct SessionFile {
+    pub path: String,
+    pub cursor: (u16, u16),
+    pub scroll: (u16, u16),
+}
+
+/// The files that were open and which one was active, so they can be reopened later.
+/// Stored as plain text, one `key value` entry per line.
+pub struct Session {
+    pub files: Vec<SessionFile>,
+    pub cur_file_idx: usize,
+    /// Search history, the most recent last
+    pub searches: Vec<String>,
+}
+
+impl Session {
+    /// Captures the open files of the editor. Buffers that were never saved have no path, so they are left out.
+    pub fn from_editor(editor: &Editor) -> Self {
+        let mut files = Vec::new();
+        let mut cur_file_idx = 0;
+        for (i, file) in editor.open_files.iter().enumerate() {
+            if let Some(path) = file.path() {
+                if i == editor.cur_file_idx {
+                    cur_file_idx = files.len();
+                }
+                files.push(SessionFile {
+                    path: std::fs::canonicalize(path).map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|_| path.to_string()),
+                    cursor: file.cursor(),
+                    scroll: file.scroll(),
+                });
+            }
+        }
+        Self { files, cur_file_idx, searches: editor.search_history.clone() }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let content = std::fs::read_to_string(path)?;
+        let mut session = Self { files: Vec::new(), cur_file_idx: 0, searches: Vec::new() };
+        for line in content.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "current" => session.cur_file_idx = value.parse().unwrap_or(0),
+                "search" => session.searches.push(value.to_string()),
+                "file" => {
+                    //file <cursor x> <cursor y> <scroll x> <scroll y> <path>, the path goes last as it may contain spaces
+                    let mut parts = value.splitn(5, ' ');
+                    let mut num = || parts.next().and_then(|n| n.parse::<u16>().ok()).unwrap_or(0);
+                    let cursor = (num(), num());
+                    let scroll = (num(), num());
+                    if let Some(path) = parts.next() {
+                        session.files.push(SessionFile { path: path.to_string(), cursor, scroll });
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(session)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut content = format!("current {}\n", self.cur_file_idx);
+        for file in &self.files {
+            content.push_str(&format!("file {} {} {} {} {}\n", file.cursor.0, file.cursor.1, file.scroll.0, file.scroll.1, file.path));
+        }
+        for search in &self.searches {
+            content.push_str(&format!("search {}\n", search));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        crate::fileio::write_atomic(path, content.as_bytes(), false)
+    }
+
+    /// Opens the files of this session in a new editor, skipping files that no longer exist
+    pub fn open(&self) -> Result<Editor, io::Error> {
+        let cwd = std::env::current_dir()?;
+        let mut files = Vec::new();
+        let mut cur_file_idx = 0;
+        for (i, entry) in self.files.iter().enumerate() {
+            //Files below the working directory are shown relative to it, like when they're passed on the command line
+            let path = Path::new(&entry.path).strip_prefix(&cwd).map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|_| entry.path.clone());
+            if let Ok(mut file) = File::from_path(&path) {
+                file.set_cursor(entry.cursor);
+                file.set_scroll(entry.scroll);
+                if i <= self.cur_file_idx {
+                    cur_file_idx = files.len();
+                }
+                files.push(file);
+            }
+        }
+        let mut editor = Editor::from_files(files);
+        editor.cur_file_idx = cur_file_idx.min(editor.open_files.len() - 1);
+        editor.search_history = self.searches.clone();
+        editor.update_styled_text();
+        Ok(editor)
+    }
+}
+
+fn sessions_dir() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(dir.join("rocket").join("sessions"))
+}
+
+/// The session that is restored automatically when rocket is started in the current directory
+pub fn cwd_session_path() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let hash = stable_hash(cwd.to_string_lossy().as_bytes());
+    Some(sessions_dir()?.join(format!("cwd-{:016x}.session", hash)))
+}
+
+/// FNV-1a, which unlike `DefaultHasher` stays the same across Rust releases, so sessions are still found after an update
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// A session saved by name from the command runner
+pub fn named_session_path(name: &str) -> Result<PathBuf, io::Error> {
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Session names may only contain letters, digits, '-' and '_'!"));
+    }
+    sessions_dir().map(|dir| dir.join(format!("{}.session", name))).ok_or_else(|| io::Error::other("No home directory!"))
+}
@@ -0,0 +1,44 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Runs `command`, feeding it `input` if given, and returns what it printed.
+/// `name` is what to call the program in the error when it fails.
+pub fn pipe(mut command: Command, name: &str, input: Option<&str>) -> io::Result<String> {
+    let mut child = command
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| io::Error::new(err.kind(), format!("Couldn't run `{}`: {}", name, err)))?;
+
+    //Written from another thread, a program may start printing before it read everything
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            let input = input.to_string();
+            Some(std::thread::spawn(move || stdin.write_all(input.as_bytes())))
+        },
+        _ => None,
+    };
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("no output");
+        return Err(io::Error::other(format!("`{}` failed: {}", name, message.trim())));
+    }
+    String::from_utf8(output.stdout).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Runs the command line `command` with `sh`, in the working directory, piping `input` through it if given
+pub fn run(command: &str, input: Option<&str>) -> io::Result<String> {
+    let command = command.trim();
+    if command.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Empty command!"));
+    }
+    let mut sh = Command::new("sh");
+    sh.arg("-c").arg(command);
+    pipe(sh, command, input)
+}
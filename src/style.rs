@@ -1,7 +1,11 @@
 use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
 
 use tui::text::{Span, Spans};
-use tui::style::{Style, Color};
+use tui::style::{Style, Color, Modifier};
+
+use crate::lsp::Severity;
+use crate::git::LineChange;
 
 lazy_static! {
     static ref PALETTE_HEADER_SELECTED: Color = Color::Rgb(42, 126, 105);
@@ -16,6 +20,154 @@ lazy_static! {
     static ref PALETTE_BUTTON: Color = Color::Rgb(0, 71, 71);
     static ref PALETTE_BUTTON_SELECT: Color = Color::Rgb(251, 203, 179);
     static ref PALETTE_BUTTON_SELECT_TEXT: Color = Color::Rgb(32, 64, 56);
+    static ref PALETTE_SELECTION: Color = Color::Rgb(0, 71, 71);
+    static ref PALETTE_COMMENT: Color = Color::Rgb(120, 160, 150);
+    static ref PALETTE_STRING: Color = Color::Rgb(190, 220, 160);
+    static ref PALETTE_BRACKET_MATCH: Color = Color::Rgb(0, 110, 110);
+    static ref PALETTE_ERROR: Color = Color::Rgb(255, 120, 110);
+    static ref PALETTE_WARNING: Color = Color::Rgb(240, 200, 100);
+}
+
+/// What a character of source code is part of
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Lexeme {
+    Code,
+    Comment,
+    String,
+}
+
+/// Where the highlighter is at some point in a file, the state at the start of each line is carried over from the previous one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LexState {
+    Code,
+    /// Only seen inside a line, a line comment ends with the line
+    LineComment,
+    BlockComment,
+    /// Inside a string started by the given quote
+    String(char),
+}
+
+/// Comment and string syntax of a language
+struct Syntax {
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// Strings may continue on the next line
+    multiline_strings: bool,
+}
+
+fn syntax(extension: &str) -> Syntax {
+    match extension {
+        "rs" => Syntax { line_comment: Some("//"), block_comment: Some(("/*", "*/")), quotes: &['"', '\''], multiline_strings: true },
+        "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "java" | "kt" | "go" | "swift" | "scala" | "js" | "jsx" | "ts" | "tsx" => {
+            Syntax { line_comment: Some("//"), block_comment: Some(("/*", "*/")), quotes: &['"', '\'', '`'], multiline_strings: false }
+        },
+        "py" | "sh" | "bash" | "zsh" | "rb" | "pl" | "toml" | "yaml" | "yml" | "conf" => {
+            Syntax { line_comment: Some("#"), block_comment: None, quotes: &['"', '\''], multiline_strings: false }
+        },
+        "lua" | "sql" | "hs" => Syntax { line_comment: Some("--"), block_comment: None, quotes: &['"', '\''], multiline_strings: false },
+        "css" => Syntax { line_comment: None, block_comment: Some(("/*", "*/")), quotes: &['"', '\''], multiline_strings: false },
+        //Quotes are everywhere in prose, so strings aren't highlighted
+        "html" | "htm" | "xml" | "svg" | "md" => Syntax { line_comment: None, block_comment: Some(("<!--", "-->")), quotes: &[], multiline_strings: false },
+        _ => Syntax { line_comment: None, block_comment: None, quotes: &[], multiline_strings: false },
+    }
+}
+
+/// Tokens for commenting out a line as `(start, end)`, `end` only being set for block comments.
+/// Line comments are preferred, languages without them fall back to block comments.
+pub fn comment_tokens(extension: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let syntax = syntax(extension);
+    syntax.line_comment.map(|start| (start, None))
+        .or(syntax.block_comment.map(|(start, end)| (start, Some(end))))
+}
+
+/// In Rust `'` also starts lifetimes, it only opens a char literal like `'a'` or `'\n'`
+fn is_char_literal(graphemes: &[&str], i: usize) -> bool {
+    match graphemes.get(i + 1) {
+        Some(&"\\") => true,
+        Some(_) => graphemes.get(i + 2) == Some(&"'"),
+        None => false,
+    }
+}
+
+/// Splits a line into code, comments and strings, one lexeme per grapheme.
+/// `state` is the state at the start of the line, the state at its end is returned along with the lexemes.
+pub fn lex_line(line: &str, extension: &str, state: LexState) -> (Vec<Lexeme>, LexState) {
+    let (lexemes, state) = lex(line, extension, state);
+    let end_state = match state {
+        LexState::LineComment => LexState::Code,
+        LexState::String(_) if !syntax(extension).multiline_strings => LexState::Code,
+        state => state,
+    };
+    (lexemes, end_state)
+}
+
+/// State at grapheme `x` of a line, used to tell whether the cursor is inside a string or comment
+pub fn lex_state_at(line: &str, extension: &str, state: LexState, x: usize) -> LexState {
+    let prefix: String = line.graphemes(true).take(x).collect();
+    lex(&prefix, extension, state).1
+}
+
+/// Like `lex_line`, but the returned state is where the lexer stopped, before the line break ends comments and strings
+fn lex(line: &str, extension: &str, state: LexState) -> (Vec<Lexeme>, LexState) {
+    let syntax = syntax(extension);
+    let graphemes: Vec<(usize, &str)> = line.grapheme_indices(true).collect();
+    let strs: Vec<&str> = graphemes.iter().map(|&(_, g)| g).collect();
+    let starts_with = |i: usize, pat: &str| line[graphemes[i].0..].starts_with(pat);
+
+    let mut lexemes = Vec::with_capacity(graphemes.len());
+    let mut state = if state == LexState::LineComment { LexState::Code } else { state };
+    let mut i = 0;
+    while i < graphemes.len() {
+        let g = strs[i];
+        match state {
+            LexState::Code => {
+                if syntax.line_comment.is_some_and(|start| starts_with(i, start)) {
+                    state = LexState::LineComment;
+                    continue;
+                }
+                if let Some((start, _)) = syntax.block_comment.filter(|&(start, _)| starts_with(i, start)) {
+                    lexemes.extend(std::iter::repeat_n(Lexeme::Comment, start.len()));
+                    i += start.len();
+                    state = LexState::BlockComment;
+                    continue;
+                }
+                let quote = g.chars().next().filter(|c| syntax.quotes.contains(c));
+                match quote {
+                    Some('\'') if extension == "rs" && !is_char_literal(&strs, i) => lexemes.push(Lexeme::Code),
+                    Some(quote) => {
+                        lexemes.push(Lexeme::String);
+                        state = LexState::String(quote);
+                    },
+                    None => lexemes.push(Lexeme::Code),
+                }
+            },
+            LexState::LineComment => lexemes.push(Lexeme::Comment),
+            LexState::BlockComment => {
+                if let Some((_, end)) = syntax.block_comment.filter(|&(_, end)| starts_with(i, end)) {
+                    lexemes.extend(std::iter::repeat_n(Lexeme::Comment, end.len()));
+                    i += end.len();
+                    state = LexState::Code;
+                    continue;
+                }
+                lexemes.push(Lexeme::Comment);
+            },
+            LexState::String(quote) => {
+                lexemes.push(Lexeme::String);
+                if g == "\\" && i + 1 < graphemes.len() {
+                    lexemes.push(Lexeme::String);
+                    i += 1;
+                } else if g.starts_with(quote) {
+                    state = LexState::Code;
+                }
+            },
+        }
+        i += 1;
+    }
+    //Delimiters are ASCII, but a multi-byte grapheme right after one could push us past the end
+    lexemes.truncate(graphemes.len());
+
+    (lexemes, state)
 }
 
 fn no_style() -> Style {
@@ -39,14 +191,156 @@ fn get_token_style(token: &str, extension: &str) -> Style {
     }
 }
 
-pub fn style_line(line: String, extension: &str) -> Spans<'static> {
+/// Styles a line starting in lexer state `state`, returning the styled line and the state the next line starts in
+pub fn style_line(line: String, extension: &str, state: LexState) -> (Spans<'static>, LexState) {
+    let (lexemes, state) = lex_line(&line, extension, state);
     let mut spans = Vec::new();
     for token in line.split(" ") {
         let style = get_token_style(token, extension);
         spans.push(Span::styled(token.to_string(), style));
         spans.push(Span::from(" "));
     }
-    Spans::from(spans)
+    let mut spans = Spans::from(spans);
+
+    //Comments and strings are colored as a whole, whatever tokens they contain
+    let mut start = 0;
+    while start < lexemes.len() {
+        let kind = lexemes[start];
+        let end = start + lexemes[start..].iter().take_while(|&&l| l == kind).count();
+        let color = match kind {
+            Lexeme::Comment => Some(*PALETTE_COMMENT),
+            Lexeme::String => Some(*PALETTE_STRING),
+            Lexeme::Code => None,
+        };
+        if let Some(color) = color {
+            patch_style(&mut spans, start..end, Style::default().fg(color));
+        }
+        start = end;
+    }
+    (spans, state)
+}
+
+/// Patches `patch` onto the graphemes in `range` of a styled line, splitting spans where needed.
+/// The range may extend past the end of the line, the missing cells are added as spaces.
+pub fn patch_style(line: &mut Spans<'static>, range: std::ops::Range<usize>, patch: Style) {
+    if range.start >= range.end {
+        return;
+    }
+    let mut spans = Vec::with_capacity(line.0.len() + 2);
+    let mut pos = 0;
+    for span in line.0.drain(..) {
+        let len = span.content.graphemes(true).count();
+        let (start, end) = (range.start.clamp(pos, pos + len) - pos, range.end.clamp(pos, pos + len) - pos);
+        if start == end {
+            spans.push(span);
+        } else {
+            let graphemes: Vec<&str> = span.content.graphemes(true).collect();
+            let part = |a: usize, b: usize| graphemes[a..b].concat();
+            if start > 0 { spans.push(Span::styled(part(0, start), span.style)); }
+            spans.push(Span::styled(part(start, end), span.style.patch(patch)));
+            if end < len { spans.push(Span::styled(part(end, len), span.style)); }
+        }
+        pos += len;
+    }
+    if range.end > pos {
+        spans.push(Span::styled(" ".repeat(range.end - range.start.max(pos)), patch));
+    }
+    line.0 = spans;
+}
+
+pub fn selection_style() -> Style {
+    Style::default().bg(*PALETTE_SELECTION)
+}
+
+pub fn bracket_match_style() -> Style {
+    Style::default().bg(*PALETTE_BRACKET_MATCH).add_modifier(Modifier::BOLD)
+}
+
+/// Character in the gutter for a line that differs from git's HEAD
+pub fn git_marker(change: LineChange) -> char {
+    match change {
+        LineChange::Added => '+',
+        LineChange::Modified => '*',
+        LineChange::Deleted => '-',
+    }
+}
+
+pub fn git_marker_style(change: LineChange) -> Style {
+    let color = match change {
+        LineChange::Added => *PALETTE_STRING,
+        LineChange::Modified => *PALETTE_WARNING,
+        LineChange::Deleted => *PALETTE_ERROR,
+    };
+    Style::default().fg(color).add_modifier(Modifier::BOLD)
+}
+
+/// Lines only in the old version of a diff
+pub fn diff_removed_style() -> Style {
+    Style::default().fg(*PALETTE_ERROR)
+}
+
+/// Lines only in the new version of a diff
+pub fn diff_added_style() -> Style {
+    Style::default().fg(*PALETTE_STRING)
+}
+
+/// The part of a changed line that actually changed, on top of its removed or added style
+pub fn diff_emphasis_style(base: Style) -> Style {
+    base.bg(*PALETTE_SELECTION).add_modifier(Modifier::BOLD)
+}
+
+/// Hunk headers and the separator between the sides of a diff
+pub fn diff_hunk_style() -> Style {
+    Style::default().fg(*PALETTE_LINE_NUM)
+}
+
+pub fn diff_number_style() -> Style {
+    Style::default().fg(*PALETTE_LINE_NUM)
+}
+
+/// A byte in the hex view: changed bytes stand out, zeros and other unprintable bytes are dimmed
+pub fn hex_byte_style(byte: u8, modified: bool) -> Style {
+    if modified {
+        Style::default().fg(*PALETTE_WARNING).add_modifier(Modifier::BOLD)
+    } else if byte == 0 {
+        Style::default().fg(*PALETTE_LINE_NUM)
+    } else if !byte.is_ascii_graphic() && byte != b' ' {
+        Style::default().fg(*PALETTE_COMMENT)
+    } else {
+        Style::default()
+    }
+}
+
+/// Blame shown after the cursor line, out of the way of the text
+pub fn blame_style() -> Style {
+    Style::default().fg(*PALETTE_COMMENT).add_modifier(Modifier::ITALIC)
+}
+
+/// The cursors besides the main one, which the terminal can't draw itself
+pub fn extra_cursor_style() -> Style {
+    Style::default().add_modifier(Modifier::REVERSED)
+}
+
+fn severity_color(severity: Severity) -> Option<Color> {
+    match severity {
+        Severity::Error => Some(*PALETTE_ERROR),
+        Severity::Warning => Some(*PALETTE_WARNING),
+        Severity::Info => None,
+    }
+}
+
+/// Text a language server reported a problem with
+pub fn diagnostic_style(severity: Severity) -> Style {
+    let style = Style::default().add_modifier(Modifier::UNDERLINED);
+    match severity_color(severity) {
+        Some(color) => style.fg(color),
+        None => style,
+    }
+}
+
+/// Line number of a line with a problem
+pub fn diagnostic_gutter_style(severity: Severity) -> Style {
+    Style::default().fg(severity_color(severity).unwrap_or(*PALETTE_LINE_NUM)).add_modifier(Modifier::BOLD)
 }
 
 pub fn style_background() -> Style {
@@ -93,6 +387,20 @@ pub fn popup_style(header: bool) -> Style {
     }
 }
 
+/// An entry of the completion dropdown, the selected one looking like a selected button
+pub fn completion_style(selected: bool) -> Style {
+    if selected {
+        Style::default().bg(*PALETTE_BUTTON_SELECT).fg(*PALETTE_BUTTON_SELECT_TEXT)
+    } else {
+        Style::default().bg(*PALETTE_POPUP_HEADER)
+    }
+}
+
+/// Kind icon and detail text next to a completion, dimmer than its label
+pub fn completion_detail_style(selected: bool) -> Style {
+    completion_style(selected).add_modifier(Modifier::ITALIC)
+}
+
 pub fn button_style(selected: bool) -> Style {
     if selected {
         Style::default().bg(*PALETTE_BUTTON_SELECT).fg(*PALETTE_BUTTON_SELECT_TEXT)
@@ -0,0 +1,221 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans, Text};
+
+/// Lines kept after they scroll off the top, for scrolling back with shift + page up
+const SCROLLBACK: usize = 1000;
+/// The pane never gets smaller than this many rows, its header included
+const MIN_HEIGHT: u16 = 6;
+
+fn pty_size(rows: u16, cols: u16) -> PtySize {
+    PtySize { rows: rows.max(1), cols: cols.max(1), pixel_width: 0, pixel_height: 0 }
+}
+
+/// Rows the pane takes out of `height` rows of text area, header included.
+/// 0 when there isn't room for the header and a row of the shell, the pane is hidden then.
+pub fn pane_height(height: u16) -> u16 {
+    let rows = (height / 3).max(MIN_HEIGHT).min(height / 2);
+    if rows < 2 { 0 } else { rows }
+}
+
+/// The user's shell running in a pseudo terminal, its output kept on a virtual screen to draw in a pane
+pub struct TerminalPane {
+    parser: Arc<Mutex<vt100::Parser>>,
+    writer: Box<dyn Write + Send>,
+    master: Box<dyn MasterPty + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    size: (u16, u16),
+}
+
+impl TerminalPane {
+    /// Starts the user's shell in the working directory, on a screen `rows` by `cols` big
+    pub fn new(rows: u16, cols: u16) -> Result<Self, io::Error> {
+        let pair = native_pty_system().openpty(pty_size(rows, cols)).map_err(io::Error::other)?;
+        let mut command = CommandBuilder::new_default_prog();
+        command.cwd(std::env::current_dir()?);
+        command.env("TERM", "xterm-256color");
+        let child = pair.slave.spawn_command(command).map_err(io::Error::other)?;
+        //Our end of the slave has to go, otherwise reading never sees the shell exit
+        drop(pair.slave);
+
+        let mut reader = pair.master.try_clone_reader().map_err(io::Error::other)?;
+        let writer = pair.master.take_writer().map_err(io::Error::other)?;
+        let parser = Arc::new(Mutex::new(vt100::Parser::new(rows.max(1), cols.max(1), SCROLLBACK)));
+
+        //The shell's output is fed to the screen as it comes, the next draw shows it
+        let screen = Arc::clone(&parser);
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                screen.lock().expect("Failed to get lock on terminal screen!").process(&buf[..n]);
+            }
+        });
+
+        Ok(Self { parser, writer, master: pair.master, child, size: (rows, cols) })
+    }
+
+    /// Returns false once the shell has exited
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        if self.size == (rows, cols) {
+            return;
+        }
+        self.size = (rows, cols);
+        let _ = self.master.resize(pty_size(rows, cols));
+        self.parser.lock().expect("Failed to get lock on terminal screen!").screen_mut().set_size(rows.max(1), cols.max(1));
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        //Typing into a shell that is going away isn't worth reporting, the pane closes right after
+        let _ = self.writer.write_all(bytes).and_then(|_| self.writer.flush());
+    }
+
+    /// Moves the view `lines` into the scrollback, negative going back towards the live screen
+    pub fn scroll_by(&mut self, lines: isize) {
+        let mut parser = self.parser.lock().expect("Failed to get lock on terminal screen!");
+        let scrollback = (parser.screen().scrollback() as isize + lines).max(0) as usize;
+        parser.screen_mut().set_scrollback(scrollback);
+    }
+
+    /// Sends a key to the shell the way a terminal would encode it
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        let page = self.size.0 as isize;
+        match key.code {
+            KeyCode::PageUp if key.modifiers.contains(KeyModifiers::SHIFT) => return self.scroll_by(page),
+            KeyCode::PageDown if key.modifiers.contains(KeyModifiers::SHIFT) => return self.scroll_by(-page),
+            _ => {},
+        }
+        //Typing goes back to the live screen
+        self.parser.lock().expect("Failed to get lock on terminal screen!").screen_mut().set_scrollback(0);
+
+        let application_cursor = self.parser.lock().expect("Failed to get lock on terminal screen!").screen().application_cursor();
+        let arrow = |c: char| if application_cursor { format!("\x1bO{}", c) } else { format!("\x1b[{}", c) };
+        let mut bytes: Vec<u8> = match key.code {
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c {
+                'a'..='z' => vec![c as u8 - b'a' + 1],
+                'A'..='Z' => vec![c as u8 - b'A' + 1],
+                ' ' | '@' | '2' => vec![0],
+                '[' | '3' => vec![0x1b],
+                '\\' | '4' => vec![0x1c],
+                ']' | '5' => vec![0x1d],
+                '^' | '6' => vec![0x1e],
+                '_' | '/' | '7' => vec![0x1f],
+                _ => c.to_string().into_bytes(),
+            },
+            KeyCode::Char(c) => c.to_string().into_bytes(),
+            KeyCode::Enter => vec![b'\r'],
+            KeyCode::Backspace => vec![0x7f],
+            KeyCode::Tab => vec![b'\t'],
+            KeyCode::BackTab => b"\x1b[Z".to_vec(),
+            KeyCode::Esc => vec![0x1b],
+            KeyCode::Up => arrow('A').into_bytes(),
+            KeyCode::Down => arrow('B').into_bytes(),
+            KeyCode::Right => arrow('C').into_bytes(),
+            KeyCode::Left => arrow('D').into_bytes(),
+            KeyCode::Home => arrow('H').into_bytes(),
+            KeyCode::End => arrow('F').into_bytes(),
+            KeyCode::Insert => b"\x1b[2~".to_vec(),
+            KeyCode::Delete => b"\x1b[3~".to_vec(),
+            KeyCode::PageUp => b"\x1b[5~".to_vec(),
+            KeyCode::PageDown => b"\x1b[6~".to_vec(),
+            KeyCode::F(n @ 1..=4) => format!("\x1bO{}", (b'P' + n - 1) as char).into_bytes(),
+            KeyCode::F(n @ 5..=12) => {
+                let code = [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5];
+                format!("\x1b[{}~", code).into_bytes()
+            },
+            _ => return,
+        };
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            bytes.insert(0, 0x1b);
+        }
+        self.write(&bytes);
+    }
+
+    /// Sends pasted text, marked as a paste if the program running in the shell asked for that
+    pub fn paste(&mut self, text: &str) {
+        let bracketed = self.parser.lock().expect("Failed to get lock on terminal screen!").screen().bracketed_paste();
+        let text = text.replace("\r\n", "\r").replace('\n', "\r");
+        if bracketed {
+            self.write(format!("\x1b[200~{}\x1b[201~", text).as_bytes());
+        } else {
+            self.write(text.as_bytes());
+        }
+    }
+
+    /// The screen as styled lines, and where its cursor is unless it is hidden or scrolled away
+    pub fn render(&self) -> (Text<'static>, Option<(u16, u16)>) {
+        let parser = self.parser.lock().expect("Failed to get lock on terminal screen!");
+        let screen = parser.screen();
+        let (rows, cols) = screen.size();
+        let mut lines = Vec::new();
+        for row in 0..rows {
+            let mut spans = Vec::new();
+            let mut run = String::new();
+            let mut run_style = Style::default();
+            for col in 0..cols {
+                let Some(cell) = screen.cell(row, col) else { continue };
+                if cell.is_wide_continuation() {
+                    continue;
+                }
+                let style = cell_style(cell);
+                if style != run_style && !run.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut run), run_style));
+                }
+                run_style = style;
+                run.push_str(if cell.has_contents() { cell.contents() } else { " " });
+            }
+            if !run.is_empty() {
+                spans.push(Span::styled(run, run_style));
+            }
+            lines.push(Spans::from(spans));
+        }
+        let cursor = if screen.hide_cursor() || screen.scrollback() > 0 { None } else { Some(screen.cursor_position()) };
+        (Text::from(lines), cursor.map(|(row, col)| (col, row)))
+    }
+}
+
+impl Drop for TerminalPane {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn color(color: vt100::Color) -> Option<Color> {
+    match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(i) => Some(Color::Indexed(i)),
+        vt100::Color::Rgb(r, g, b) => Some(Color::Rgb(r, g, b)),
+    }
+}
+
+fn cell_style(cell: &vt100::Cell) -> Style {
+    let mut style = Style::default();
+    if let Some(fg) = color(cell.fgcolor()) {
+        style = style.fg(fg);
+    }
+    if let Some(bg) = color(cell.bgcolor()) {
+        style = style.bg(bg);
+    }
+    for (set, modifier) in [
+        (cell.bold(), Modifier::BOLD),
+        (cell.dim(), Modifier::DIM),
+        (cell.italic(), Modifier::ITALIC),
+        (cell.underline(), Modifier::UNDERLINED),
+        (cell.inverse(), Modifier::REVERSED),
+    ] {
+        if set {
+            style = style.add_modifier(modifier);
+        }
+    }
+    style
+}
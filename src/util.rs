@@ -1,33 +1,90 @@
-use tui::layout::{Layout, Direction, Constraint, Rect};
-
-pub fn centered_rect_perc(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-    let popup_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(
-            [
-                Constraint::Percentage((100 - percent_y) / 2),
-                Constraint::Percentage(percent_y),
-                Constraint::Percentage((100 - percent_y) / 2),
-            ]
-            .as_ref(),
-        )
-        .split(r);
-
-    Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(
-            [
-                Constraint::Percentage((100 - percent_x) / 2),
-                Constraint::Percentage(percent_x),
-                Constraint::Percentage((100 - percent_x) / 2),
-            ]
-            .as_ref(),
-        )
-        .split(popup_layout[1])[1]
+use tui::layout::Rect;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Columns a tab takes on screen, tabs are drawn as spaces
+pub const TAB_WIDTH: usize = 4;
+
+fn grapheme_width(grapheme: &str) -> usize {
+    if grapheme == "\t" { TAB_WIDTH } else { 1 }
+}
+
+/// Screen column of grapheme `x` of `line`, which differ once the line has tabs
+pub fn display_column(line: &str, x: usize) -> usize {
+    line.graphemes(true).take(x).map(grapheme_width).sum()
+}
+
+/// Grapheme of `line` drawn at screen `column`, the inverse of `display_column`.
+/// Columns inside a tab give the tab, columns past the end give the end of the line.
+pub fn grapheme_at_column(line: &str, column: usize) -> usize {
+    let mut end = 0;
+    for (x, grapheme) in line.graphemes(true).enumerate() {
+        end += grapheme_width(grapheme);
+        if end > column {
+            return x;
+        }
+    }
+    line.graphemes(true).count()
+}
+
+/// A `width` by `height` rect centered in `r`, shrunk to fit if `r` is smaller
+pub fn centered_rect_set(width: u16, height: u16, r: Rect) -> Rect {
+    let width = width.min(r.width);
+    let height = height.min(r.height);
+    Rect::new(r.x + (r.width - width) / 2, r.y + (r.height - height) / 2, width, height)
+}
+
+/// Splits `r` into `count` equally wide rects with `spacing` columns between them.
+/// The margin on both sides shrinks first when there isn't enough room.
+pub fn button_rects(count: usize, margin: u16, spacing: u16, r: Rect) -> Vec<Rect> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let count = count as u16;
+    let gaps = spacing * (count - 1);
+    let margin = margin.min(r.width.saturating_sub(gaps + count) / 2);
+    let inner = r.width.saturating_sub(2 * margin + gaps);
+    let width = inner / count;
+    (0..count).map(|i| {
+        let x = (r.x + margin + i * (width + spacing)).min(r.x + r.width);
+        Rect::new(x, r.y, width.min(r.x + r.width - x), r.height)
+    }).collect()
+}
+
+/// Width of the `<` and `>` markers shown when there are more tabs than fit
+pub const TAB_MARKER_WIDTH: u16 = 2;
+
+/// Range of tabs to draw so that the tab at `active` is visible in `width` columns.
+/// `widths` holds the width of each tab including its separator. Room is left for the
+/// overflow markers on the sides where tabs are cut off.
+pub fn visible_tabs(widths: &[u16], active: usize, width: u16) -> std::ops::Range<usize> {
+    if widths.iter().sum::<u16>() <= width {
+        return 0..widths.len();
+    }
+    let fits = |first: usize, last: usize| {
+        let markers = if first > 0 { TAB_MARKER_WIDTH } else { 0 } + if last < widths.len() { TAB_MARKER_WIDTH } else { 0 };
+        widths[first..last].iter().sum::<u16>() + markers <= width
+    };
+    let (mut first, mut last) = (active, active + 1);
+    //Grow to both sides in turn, preferring the tabs after the active one
+    loop {
+        let mut grew = false;
+        if last < widths.len() && fits(first, last + 1) {
+            last += 1;
+            grew = true;
+        }
+        if first > 0 && fits(first - 1, last) {
+            first -= 1;
+            grew = true;
+        }
+        if !grew {
+            return first..last;
+        }
+    }
 }
 
-pub fn centered_rect_set(x: u16, y: u16, r: Rect) -> Rect {
-    let percent_x = ((x as f32) / (r.width as f32) * 100f32) as u16;
-    let percent_y = ((y as f32) / (r.height as f32) * 100f32) as u16;
-    centered_rect_perc(percent_x, percent_y, r)
+/// `path` relative to the working directory if it is below it, the way paths passed on the command line look
+pub fn relative_to_cwd(path: &str) -> String {
+    std::env::current_dir().ok()
+        .and_then(|cwd| std::path::Path::new(path).strip_prefix(cwd).ok().map(|p| p.to_string_lossy().to_string()))
+        .unwrap_or_else(|| path.to_string())
 }
@@ -0,0 +1,123 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What a character is as far as word movement is concerned
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Class {
+    Whitespace,
+    /// Letters, digits and `_`, the things identifiers are made of
+    Word,
+    /// Everything else, like operators and brackets
+    Punctuation,
+}
+
+pub fn char_class(c: char) -> Class {
+    if c.is_whitespace() {
+        Class::Whitespace
+    } else if c.is_alphanumeric() || c == '_' {
+        Class::Word
+    } else {
+        Class::Punctuation
+    }
+}
+
+fn grapheme_class(g: &str) -> Class {
+    g.chars().next().map(char_class).unwrap_or(Class::Whitespace)
+}
+
+/// Splits a line into words, runs of punctuation and runs of whitespace, as grapheme ranges (start, end, class).
+/// Words follow Unicode word segmentation, so scripts without spaces still get sensible words,
+/// but unlike plain segmentation `foo.bar` is three pieces rather than one.
+pub fn segments(line: &str) -> Vec<(usize, usize, Class)> {
+    let mut segments: Vec<(usize, usize, Class)> = Vec::new();
+    let mut pos = 0;
+    for word in line.split_word_bounds() {
+        let mut word_start = true;
+        for g in word.graphemes(true) {
+            let class = grapheme_class(g);
+            match segments.last_mut() {
+                //Words only continue inside their Unicode word, punctuation and whitespace runs merge
+                Some(last) if last.2 == class && (class != Class::Word || !word_start) => last.1 = pos + 1,
+                _ => segments.push((pos, pos + 1, class)),
+            }
+            word_start = false;
+            pos += 1;
+        }
+    }
+    segments
+}
+
+/// Where ctrl + right ends up from grapheme `x`: the end of the next word or punctuation run
+pub fn next_boundary(line: &str, x: usize) -> usize {
+    segments(line).into_iter()
+        .find(|&(_, end, class)| end > x && class != Class::Whitespace)
+        .map(|(_, end, _)| end)
+        .unwrap_or_else(|| line.graphemes(true).count())
+}
+
+/// Where ctrl + left ends up from grapheme `x`: the start of the previous word or punctuation run
+pub fn prev_boundary(line: &str, x: usize) -> usize {
+    segments(line).into_iter()
+        .rev()
+        .find(|&(start, _, class)| start < x && class != Class::Whitespace)
+        .map(|(start, _, _)| start)
+        .unwrap_or(0)
+}
+
+/// The segment containing grapheme `x`, used to select a word on double click
+pub fn segment_at(line: &str, x: usize) -> Option<(usize, usize)> {
+    let segments = segments(line);
+    let last = segments.last().copied();
+    segments.into_iter()
+        .find(|&(start, end, _)| x >= start && x < end)
+        .or(last)
+        .map(|(start, end, _)| (start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Class::*;
+
+    #[test]
+    fn punctuation_splits_words() {
+        assert_eq!(segments("foo.bar  baz"), vec![(0, 3, Word), (3, 4, Punctuation), (4, 7, Word), (7, 9, Whitespace), (9, 12, Word)]);
+        //Punctuation runs stay together, identifiers include `_` and digits
+        assert_eq!(segments("a_1::b"), vec![(0, 3, Word), (3, 5, Punctuation), (5, 6, Word)]);
+        assert!(segments("").is_empty());
+    }
+
+    #[test]
+    fn unicode_words() {
+        //Graphemes are counted, a combining accent belongs to its letter
+        assert_eq!(segments("cafe\u{301} über"), vec![(0, 4, Word), (4, 5, Whitespace), (5, 9, Word)]);
+        //Han characters are a word each, katakana runs are one word
+        assert_eq!(segments("テキスト日本"), vec![(0, 4, Word), (4, 5, Word), (5, 6, Word)]);
+    }
+
+    #[test]
+    fn boundaries() {
+        let line = "let x = foo(bar);";
+        assert_eq!(next_boundary(line, 0), 3);
+        assert_eq!(next_boundary(line, 3), 5);
+        assert_eq!(next_boundary(line, 8), 11);
+        assert_eq!(next_boundary(line, 11), 12);
+        assert_eq!(next_boundary(line, 15), 17);
+        assert_eq!(next_boundary(line, 17), 17);
+        assert_eq!(prev_boundary(line, 17), 15);
+        assert_eq!(prev_boundary(line, 11), 8);
+        assert_eq!(prev_boundary(line, 4), 0);
+        assert_eq!(prev_boundary(line, 0), 0);
+        //Trailing whitespace is skipped to the end of the line
+        assert_eq!(next_boundary("ab  ", 2), 4);
+        assert_eq!(next_boundary("  ", 0), 2);
+    }
+
+    #[test]
+    fn double_click_segments() {
+        assert_eq!(segment_at("foo.bar", 1), Some((0, 3)));
+        assert_eq!(segment_at("foo.bar", 3), Some((3, 4)));
+        //Past the end gives the last segment
+        assert_eq!(segment_at("foo bar", 20), Some((4, 7)));
+        assert_eq!(segment_at("", 0), None);
+    }
+}